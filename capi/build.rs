@@ -0,0 +1,15 @@
+// write include/kd3005p.h from the extern "C" functions in src/lib.rs
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("cbindgen.toml is missing");
+    match cbindgen::generate_with_config(&crate_dir, config) {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{}/include/kd3005p.h", crate_dir));
+        }
+        // a broken header must not stop the build of the library itself
+        Err(e) => println!("cargo:warning=Could not generate kd3005p.h: {}", e),
+    }
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
@@ -0,0 +1,34 @@
+/* gcc example.c -Iinclude -L../target/release -lkd3005p_c -o example */
+#include <stdio.h>
+
+#include "kd3005p.h"
+
+int main(int argc, char **argv) {
+    const char *port = argc > 1 ? argv[1] : "SIMULATED";
+    Kd3005pSupply *supply = kd3005p_open(port);
+    if (supply == NULL) {
+        printf("Open failed: %s\n", kd3005p_last_error());
+        return 1;
+    }
+
+    kd3005p_set_voltage(supply, 1, 5.0);
+    kd3005p_set_current(supply, 1, 0.2);
+    kd3005p_set_output(supply, 1);
+
+    double voltage, current;
+    if (kd3005p_measure(supply, 1, &voltage, &current) == KD3005P_OK) {
+        printf("Measured %.2f V, %.3f A\n", voltage, current);
+    }
+    Kd3005pStatus status;
+    if (kd3005p_get_status(supply, &status) == KD3005P_OK) {
+        printf("Output %s, %s\n", status.output_on ? "ON" : "OFF",
+               status.constant_voltage_ch1 ? "CV" : "CC");
+    }
+    if (kd3005p_set_voltage(supply, 1, 99.0) != KD3005P_OK) {
+        printf("Refused: %s\n", kd3005p_last_error());
+    }
+
+    kd3005p_set_output(supply, 0);
+    kd3005p_close(supply);
+    return 0;
+}
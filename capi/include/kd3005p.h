@@ -0,0 +1,174 @@
+#ifndef KD3005P_H
+#define KD3005P_H
+
+/* Generated by cbindgen from capi/src/lib.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Version of this API, only changes when a function changes.
+ */
+#define KD3005P_API_VERSION 1
+
+/**
+ * The call worked.
+ */
+#define KD3005P_OK 0
+
+/**
+ * The supply or the driver refused, see kd3005p_last_error().
+ */
+#define KD3005P_ERROR -1
+
+/**
+ * A NULL pointer or an invalid string was given.
+ */
+#define KD3005P_INVALID_ARGUMENT -2
+
+/**
+ * A connection to one supply, from kd3005p_open(), freed with kd3005p_close().
+ */
+typedef struct Kd3005pSupply Kd3005pSupply;
+
+/**
+ * The decoded STATUS? byte, the flags are 0 or 1.
+ */
+typedef struct Kd3005pStatus {
+  /**
+   * The raw status byte.
+   */
+  uint8_t byte;
+  uint8_t output_on;
+  /**
+   * 1 in constant voltage mode, 0 in constant current mode.
+   */
+  uint8_t constant_voltage_ch1;
+  uint8_t constant_voltage_ch2;
+  /**
+   * 0 independent, 1 series, 2 parallel, 255 unknown.
+   */
+  uint8_t tracking;
+  uint8_t beep;
+  uint8_t locked;
+} Kd3005pStatus;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Version of the API the library was built with, compare with KD3005P_API_VERSION.
+ */
+uint32_t kd3005p_api_version(void);
+
+/**
+ * Open the supply on `port` (e.g. "/dev/ttyACM0", "COM3" or "SIMULATED") with
+ * 9600 baud 8N1. Returns NULL on failure.
+ *
+ * # Safety
+ * `port` must be a NUL terminated string.
+ */
+struct Kd3005pSupply *kd3005p_open(const char *port);
+
+/**
+ * Like kd3005p_open(), with another baud rate and read timeout; 0 keeps the default.
+ *
+ * # Safety
+ * `port` must be a NUL terminated string.
+ */
+struct Kd3005pSupply *kd3005p_open_with_config(const char *port,
+                                               uint32_t baud_rate,
+                                               uint32_t timeout_ms);
+
+/**
+ * Free the connection, NULL is ignored. The output is left as it is.
+ *
+ * # Safety
+ * `supply` must come from kd3005p_open() and not be used afterwards.
+ */
+void kd3005p_close(struct Kd3005pSupply *supply);
+
+/**
+ * Set the voltage of `channel` (1 or 2), checked against the limits of the model.
+ *
+ * # Safety
+ * `supply` must come from kd3005p_open().
+ */
+int32_t kd3005p_set_voltage(struct Kd3005pSupply *supply, uint8_t channel, double voltage);
+
+/**
+ * Set the current limit of `channel`, checked against the limits of the model.
+ *
+ * # Safety
+ * `supply` must come from kd3005p_open().
+ */
+int32_t kd3005p_set_current(struct Kd3005pSupply *supply, uint8_t channel, double current);
+
+/**
+ * Read the voltage setting of `channel`.
+ *
+ * # Safety
+ * `supply` must come from kd3005p_open(), `voltage` must point to a double.
+ */
+int32_t kd3005p_get_voltage(struct Kd3005pSupply *supply, uint8_t channel, double *voltage);
+
+/**
+ * Read the current limit of `channel`.
+ *
+ * # Safety
+ * `supply` must come from kd3005p_open(), `current` must point to a double.
+ */
+int32_t kd3005p_get_current(struct Kd3005pSupply *supply, uint8_t channel, double *current);
+
+/**
+ * Measure the actual output voltage and current of `channel`.
+ *
+ * # Safety
+ * `supply` must come from kd3005p_open(), `voltage` and `current` must point to doubles.
+ */
+int32_t kd3005p_measure(struct Kd3005pSupply *supply,
+                        uint8_t channel,
+                        double *voltage,
+                        double *current);
+
+/**
+ * Switch the output on (any value but 0) or off (0).
+ *
+ * # Safety
+ * `supply` must come from kd3005p_open().
+ */
+int32_t kd3005p_set_output(struct Kd3005pSupply *supply, int32_t on);
+
+/**
+ * Read and decode the status byte.
+ *
+ * # Safety
+ * `supply` must come from kd3005p_open(), `status` must point to a Kd3005pStatus.
+ */
+int32_t kd3005p_get_status(struct Kd3005pSupply *supply, struct Kd3005pStatus *status);
+
+/**
+ * The error of the last call on this thread, "" if it worked. The string stays
+ * valid until the next call on the same thread.
+ */
+const char *kd3005p_last_error(void);
+
+/**
+ * Copy the last error into `buffer` of `size` bytes (cut off if needed, always NUL
+ * terminated), for callers like LabVIEW that prefer their own buffer. Returns the
+ * full length of the message.
+ *
+ * # Safety
+ * `buffer` must point to `size` writable bytes.
+ */
+int32_t kd3005p_last_error_copy(char *buffer, size_t size);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* KD3005P_H */
@@ -0,0 +1,311 @@
+//! C API of the KD3005P driver. Every call returns KD3005P_OK or an error code,
+//! kd3005p_last_error() tells what went wrong on the calling thread.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use kd3005p_rs::driver::Supply;
+use kd3005p_rs::port_config::PortConfig;
+use kd3005p_rs::status::Tracking;
+
+/// Version of this API, only changes when a function changes.
+pub const KD3005P_API_VERSION: u32 = 1;
+
+/// The call worked.
+pub const KD3005P_OK: i32 = 0;
+/// The supply or the driver refused, see kd3005p_last_error().
+pub const KD3005P_ERROR: i32 = -1;
+/// A NULL pointer or an invalid string was given.
+pub const KD3005P_INVALID_ARGUMENT: i32 = -2;
+
+/// A connection to one supply, from kd3005p_open(), freed with kd3005p_close().
+pub struct Kd3005pSupply {
+    supply: Supply,
+}
+
+/// The decoded STATUS? byte, the flags are 0 or 1.
+#[repr(C)]
+pub struct Kd3005pStatus {
+    /// The raw status byte.
+    pub byte: u8,
+    pub output_on: u8,
+    /// 1 in constant voltage mode, 0 in constant current mode.
+    pub constant_voltage_ch1: u8,
+    pub constant_voltage_ch2: u8,
+    /// 0 independent, 1 series, 2 parallel, 255 unknown.
+    pub tracking: u8,
+    pub beep: u8,
+    pub locked: u8,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.trim().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+// run a driver call on the supply, no panic may cross into the C caller
+unsafe fn with_supply<F>(supply: *mut Kd3005pSupply, call: F) -> i32
+where
+    F: FnOnce(&mut Supply) -> Result<(), String>,
+{
+    let supply = match supply.as_mut() {
+        Some(supply) => supply,
+        None => {
+            set_last_error("The supply is NULL");
+            return KD3005P_INVALID_ARGUMENT;
+        }
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| call(&mut supply.supply))) {
+        Ok(Ok(())) => {
+            set_last_error("");
+            KD3005P_OK
+        }
+        Ok(Err(e)) => {
+            set_last_error(&e);
+            KD3005P_ERROR
+        }
+        Err(_) => {
+            set_last_error("Internal error in the driver");
+            KD3005P_ERROR
+        }
+    }
+}
+
+unsafe fn read_port(port: *const c_char) -> Option<String> {
+    if port.is_null() {
+        return None;
+    }
+    CStr::from_ptr(port).to_str().ok().map(str::to_string)
+}
+
+/// Version of the API the library was built with, compare with KD3005P_API_VERSION.
+#[no_mangle]
+pub extern "C" fn kd3005p_api_version() -> u32 {
+    KD3005P_API_VERSION
+}
+
+/// Open the supply on `port` (e.g. "/dev/ttyACM0", "COM3" or "SIMULATED") with
+/// 9600 baud 8N1. Returns NULL on failure.
+///
+/// # Safety
+/// `port` must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_open(port: *const c_char) -> *mut Kd3005pSupply {
+    kd3005p_open_with_config(port, 0, 0)
+}
+
+/// Like kd3005p_open(), with another baud rate and read timeout; 0 keeps the default.
+///
+/// # Safety
+/// `port` must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_open_with_config(
+    port: *const c_char,
+    baud_rate: u32,
+    timeout_ms: u32,
+) -> *mut Kd3005pSupply {
+    let port = match read_port(port) {
+        Some(port) => port,
+        None => {
+            set_last_error("The port must be a UTF-8 string");
+            return ptr::null_mut();
+        }
+    };
+    let mut port_config = PortConfig::default();
+    if baud_rate != 0 {
+        port_config.baud_rate = baud_rate;
+    }
+    if timeout_ms != 0 {
+        port_config.timeout_ms = timeout_ms as u64;
+    }
+    match panic::catch_unwind(|| Supply::open(&port, &port_config)) {
+        Ok(Ok(supply)) => {
+            set_last_error("");
+            Box::into_raw(Box::new(Kd3005pSupply { supply }))
+        }
+        Ok(Err(e)) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+        Err(_) => {
+            set_last_error("Internal error in the driver");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Free the connection, NULL is ignored. The output is left as it is.
+///
+/// # Safety
+/// `supply` must come from kd3005p_open() and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_close(supply: *mut Kd3005pSupply) {
+    if !supply.is_null() {
+        drop(Box::from_raw(supply));
+    }
+}
+
+/// Set the voltage of `channel` (1 or 2), checked against the limits of the model.
+///
+/// # Safety
+/// `supply` must come from kd3005p_open().
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_set_voltage(
+    supply: *mut Kd3005pSupply,
+    channel: u8,
+    voltage: f64,
+) -> i32 {
+    with_supply(supply, |supply| supply.set_voltage(channel, voltage))
+}
+
+/// Set the current limit of `channel`, checked against the limits of the model.
+///
+/// # Safety
+/// `supply` must come from kd3005p_open().
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_set_current(
+    supply: *mut Kd3005pSupply,
+    channel: u8,
+    current: f64,
+) -> i32 {
+    with_supply(supply, |supply| supply.set_amperage(channel, current))
+}
+
+/// Read the voltage setting of `channel`.
+///
+/// # Safety
+/// `supply` must come from kd3005p_open(), `voltage` must point to a double.
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_get_voltage(
+    supply: *mut Kd3005pSupply,
+    channel: u8,
+    voltage: *mut f64,
+) -> i32 {
+    if voltage.is_null() {
+        set_last_error("The voltage pointer is NULL");
+        return KD3005P_INVALID_ARGUMENT;
+    }
+    with_supply(supply, |supply| {
+        *voltage = supply.voltage_setting(channel)?;
+        Ok(())
+    })
+}
+
+/// Read the current limit of `channel`.
+///
+/// # Safety
+/// `supply` must come from kd3005p_open(), `current` must point to a double.
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_get_current(
+    supply: *mut Kd3005pSupply,
+    channel: u8,
+    current: *mut f64,
+) -> i32 {
+    if current.is_null() {
+        set_last_error("The current pointer is NULL");
+        return KD3005P_INVALID_ARGUMENT;
+    }
+    with_supply(supply, |supply| {
+        *current = supply.amperage_setting(channel)?;
+        Ok(())
+    })
+}
+
+/// Measure the actual output voltage and current of `channel`.
+///
+/// # Safety
+/// `supply` must come from kd3005p_open(), `voltage` and `current` must point to doubles.
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_measure(
+    supply: *mut Kd3005pSupply,
+    channel: u8,
+    voltage: *mut f64,
+    current: *mut f64,
+) -> i32 {
+    if voltage.is_null() || current.is_null() {
+        set_last_error("The voltage or current pointer is NULL");
+        return KD3005P_INVALID_ARGUMENT;
+    }
+    with_supply(supply, |supply| {
+        let measurement = supply.measure(channel)?;
+        *voltage = measurement.voltage;
+        *current = measurement.amperage;
+        Ok(())
+    })
+}
+
+/// Switch the output on (any value but 0) or off (0).
+///
+/// # Safety
+/// `supply` must come from kd3005p_open().
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_set_output(supply: *mut Kd3005pSupply, on: i32) -> i32 {
+    with_supply(supply, |supply| supply.set_output(on != 0))
+}
+
+/// Read and decode the status byte.
+///
+/// # Safety
+/// `supply` must come from kd3005p_open(), `status` must point to a Kd3005pStatus.
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_get_status(
+    supply: *mut Kd3005pSupply,
+    status: *mut Kd3005pStatus,
+) -> i32 {
+    if status.is_null() {
+        set_last_error("The status pointer is NULL");
+        return KD3005P_INVALID_ARGUMENT;
+    }
+    with_supply(supply, |supply| {
+        let decoded = supply.status()?;
+        *status = Kd3005pStatus {
+            byte: decoded.byte,
+            output_on: decoded.output_on() as u8,
+            constant_voltage_ch1: decoded.constant_voltage(1) as u8,
+            constant_voltage_ch2: decoded.constant_voltage(2) as u8,
+            tracking: match decoded.tracking() {
+                Tracking::Independent => 0,
+                Tracking::Series => 1,
+                Tracking::Parallel => 2,
+                Tracking::Unknown => 255,
+            },
+            beep: decoded.beep() as u8,
+            locked: decoded.locked() as u8,
+        };
+        Ok(())
+    })
+}
+
+/// The error of the last call on this thread, "" if it worked. The string stays
+/// valid until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn kd3005p_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+}
+
+/// Copy the last error into `buffer` of `size` bytes (cut off if needed, always NUL
+/// terminated), for callers like LabVIEW that prefer their own buffer. Returns the
+/// full length of the message.
+///
+/// # Safety
+/// `buffer` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn kd3005p_last_error_copy(buffer: *mut c_char, size: usize) -> i32 {
+    LAST_ERROR.with(|last_error| {
+        let message = last_error.borrow();
+        let bytes = message.as_bytes();
+        if !buffer.is_null() && size > 0 {
+            let length = bytes.len().min(size - 1);
+            ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, buffer, length);
+            *buffer.add(length) = 0;
+        }
+        bytes.len() as i32
+    })
+}
@@ -0,0 +1,298 @@
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use kd3005p_rs::driver;
+use kd3005p_rs::port_config::PortConfig;
+use kd3005p_rs::sequence::{self, Rail};
+use kd3005p_rs::status;
+
+// everything the supply or the driver refuses ends up as this exception
+create_exception!(kd3005p, SupplyError, PyException);
+
+fn supply_error(e: String) -> PyErr {
+    SupplyError::new_err(e.trim().to_string())
+}
+
+// {"baud_rate": 9600, "timeout_ms": 200} on top of the defaults, same keys as the config file
+fn read_port_config(settings: Option<&Bound<'_, PyDict>>) -> PyResult<PortConfig> {
+    let mut port_config = PortConfig::default();
+    if let Some(settings) = settings {
+        for (key, value) in settings.iter() {
+            let key: String = key.extract()?;
+            let value = value.str()?.to_string();
+            port_config.set(&key, &value).map_err(supply_error)?;
+        }
+    }
+    Ok(port_config)
+}
+
+// one reading of the actual output
+#[pyclass(frozen, module = "kd3005p")]
+#[derive(Clone)]
+struct Measurement {
+    #[pyo3(get)]
+    voltage: f64,
+    #[pyo3(get)]
+    amperage: f64,
+    #[pyo3(get)]
+    power: f64,
+}
+
+#[pymethods]
+impl Measurement {
+    fn __repr__(&self) -> String {
+        format!(
+            "Measurement(voltage={:.2}, amperage={:.3}, power={:.3})",
+            self.voltage, self.amperage, self.power
+        )
+    }
+}
+
+impl From<driver::Measurement> for Measurement {
+    fn from(measurement: driver::Measurement) -> Self {
+        Measurement {
+            voltage: measurement.voltage,
+            amperage: measurement.amperage,
+            power: measurement.power,
+        }
+    }
+}
+
+// the decoded STATUS? byte
+#[pyclass(frozen, module = "kd3005p")]
+#[derive(Clone)]
+struct Status {
+    status: status::Status,
+}
+
+#[pymethods]
+impl Status {
+    #[getter]
+    fn byte(&self) -> u8 {
+        self.status.byte
+    }
+
+    #[getter]
+    fn output_on(&self) -> bool {
+        self.status.output_on()
+    }
+
+    #[getter]
+    fn beep(&self) -> bool {
+        self.status.beep()
+    }
+
+    #[getter]
+    fn locked(&self) -> bool {
+        self.status.locked()
+    }
+
+    // "independent", "series", "parallel" or "unknown"
+    #[getter]
+    fn tracking(&self) -> String {
+        format!("{:?}", self.status.tracking()).to_lowercase()
+    }
+
+    #[pyo3(signature = (channel=1))]
+    fn constant_voltage(&self, channel: u8) -> bool {
+        self.status.constant_voltage(channel)
+    }
+
+    // "CV" or "CC"
+    #[pyo3(signature = (channel=1))]
+    fn mode(&self, channel: u8) -> &'static str {
+        if self.status.constant_voltage(channel) {
+            "CV"
+        } else {
+            "CC"
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Status(byte=0x{:02x}, output_on={}, mode={})",
+            self.status.byte,
+            if self.status.output_on() {
+                "True"
+            } else {
+                "False"
+            },
+            self.mode(1)
+        )
+    }
+}
+
+// the connection to one supply, the same driver as the SCPI and web servers use
+#[pyclass(module = "kd3005p")]
+struct Supply {
+    supply: driver::Supply,
+}
+
+#[pymethods]
+impl Supply {
+    // opening asks for the ID, so the limits of the model are known
+    #[new]
+    #[pyo3(signature = (port, port_config=None))]
+    fn new(py: Python<'_>, port: &str, port_config: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let port_config = read_port_config(port_config)?;
+        let supply = py
+            .allow_threads(|| driver::Supply::open(port, &port_config))
+            .map_err(supply_error)?;
+        Ok(Supply { supply })
+    }
+
+    #[getter]
+    fn port(&self) -> &str {
+        &self.supply.current_port
+    }
+
+    #[getter]
+    fn model(&self) -> &'static str {
+        self.supply.model.name
+    }
+
+    #[getter]
+    fn channels(&self) -> u8 {
+        self.supply.model.channels
+    }
+
+    #[getter]
+    fn max_voltage(&self) -> f64 {
+        self.supply.model.max_voltage
+    }
+
+    #[getter]
+    fn max_amperage(&self) -> f64 {
+        self.supply.model.max_amperage
+    }
+
+    fn id(&self, py: Python<'_>) -> PyResult<String> {
+        py.allow_threads(|| self.supply.id()).map_err(supply_error)
+    }
+
+    fn status(&self, py: Python<'_>) -> PyResult<Status> {
+        py.allow_threads(|| self.supply.status())
+            .map(|status| Status { status })
+            .map_err(supply_error)
+    }
+
+    #[pyo3(signature = (channel=1))]
+    fn voltage_setting(&self, py: Python<'_>, channel: u8) -> PyResult<f64> {
+        py.allow_threads(|| self.supply.voltage_setting(channel))
+            .map_err(supply_error)
+    }
+
+    #[pyo3(signature = (channel=1))]
+    fn amperage_setting(&self, py: Python<'_>, channel: u8) -> PyResult<f64> {
+        py.allow_threads(|| self.supply.amperage_setting(channel))
+            .map_err(supply_error)
+    }
+
+    #[pyo3(signature = (voltage, channel=1))]
+    fn set_voltage(&self, py: Python<'_>, voltage: f64, channel: u8) -> PyResult<()> {
+        py.allow_threads(|| self.supply.set_voltage(channel, voltage))
+            .map_err(supply_error)
+    }
+
+    #[pyo3(signature = (amperage, channel=1))]
+    fn set_amperage(&self, py: Python<'_>, amperage: f64, channel: u8) -> PyResult<()> {
+        py.allow_threads(|| self.supply.set_amperage(channel, amperage))
+            .map_err(supply_error)
+    }
+
+    #[pyo3(signature = (channel=1))]
+    fn measure(&self, py: Python<'_>, channel: u8) -> PyResult<Measurement> {
+        py.allow_threads(|| self.supply.measure(channel))
+            .map(Measurement::from)
+            .map_err(supply_error)
+    }
+
+    fn set_output(&mut self, py: Python<'_>, on: bool) -> PyResult<()> {
+        let supply = &mut self.supply;
+        py.allow_threads(|| supply.set_output(on))
+            .map_err(supply_error)
+    }
+
+    fn set_ovp(&mut self, py: Python<'_>, on: bool) -> PyResult<()> {
+        let supply = &mut self.supply;
+        py.allow_threads(|| supply.set_ovp(on))
+            .map_err(supply_error)
+    }
+
+    fn set_ocp(&mut self, py: Python<'_>, on: bool) -> PyResult<()> {
+        let supply = &mut self.supply;
+        py.allow_threads(|| supply.set_ocp(on))
+            .map_err(supply_error)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Supply(port={:?}, model={:?})",
+            self.supply.current_port, self.supply.model.name
+        )
+    }
+}
+
+// power sequencing over several supplies, the rails are given by label and port
+#[pyclass(module = "kd3005p")]
+struct Sequencer {
+    rails: Vec<Rail>,
+    port_config: PortConfig,
+}
+
+#[pymethods]
+impl Sequencer {
+    #[new]
+    #[pyo3(signature = (rails, port_config=None))]
+    fn new(rails: &Bound<'_, PyDict>, port_config: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut sequencer_rails = Vec::new();
+        for (label, port) in rails.iter() {
+            let label: String = label.extract()?;
+            let port: String = port.extract()?;
+            sequencer_rails.push(Rail::new(&label, &port));
+        }
+        Ok(Sequencer {
+            rails: sequencer_rails,
+            port_config: read_port_config(port_config)?,
+        })
+    }
+
+    // switch the rails on in order, returns the log; on a failure all rails are switched off again
+    fn power_up(&self, py: Python<'_>, sequence: &str) -> PyResult<String> {
+        self.run(py, sequence, true)
+    }
+
+    // switch the rails off in reverse order, returns the log
+    fn power_down(&self, py: Python<'_>, sequence: &str) -> PyResult<String> {
+        self.run(py, sequence, false)
+    }
+}
+
+impl Sequencer {
+    fn run(&self, py: Python<'_>, sequence: &str, up: bool) -> PyResult<String> {
+        let steps = sequence::parse_sequence(sequence).map_err(supply_error)?;
+        py.allow_threads(|| {
+            let mut my_output = String::new();
+            let result = if up {
+                sequence::power_up(&steps, &self.rails, &self.port_config, &mut my_output)
+            } else {
+                sequence::power_down(&steps, &self.rails, &self.port_config, &mut my_output)
+            };
+            result.map(|_| my_output)
+        })
+        .map_err(supply_error)
+    }
+}
+
+#[pymodule]
+fn kd3005p(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("SupplyError", m.py().get_type::<SupplyError>())?;
+    m.add("SIMULATED", kd3005p_rs::simulator::SIMULATED_PORT)?;
+    m.add_class::<Supply>()?;
+    m.add_class::<Status>()?;
+    m.add_class::<Measurement>()?;
+    m.add_class::<Sequencer>()?;
+    Ok(())
+}
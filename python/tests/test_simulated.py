@@ -0,0 +1,86 @@
+# run with: maturin develop && pytest
+# everything runs against the simulated KD3005P, which drives a 10 Ohm load
+import pytest
+
+import kd3005p
+
+
+@pytest.fixture
+def supply():
+    supply = kd3005p.Supply(kd3005p.SIMULATED)
+    supply.set_output(False)
+    yield supply
+    supply.set_output(False)
+
+
+def test_open_identifies_the_model(supply):
+    assert supply.model == "KD3005P"
+    assert supply.channels == 1
+    assert supply.max_voltage == 30.0
+    assert "KD3005P" in supply.id()
+
+
+def test_settings_read_back(supply):
+    supply.set_voltage(5.0)
+    supply.set_amperage(0.25)
+    assert supply.voltage_setting() == pytest.approx(5.0)
+    assert supply.amperage_setting() == pytest.approx(0.25)
+
+
+def test_measure_in_constant_voltage(supply):
+    supply.set_voltage(5.0)
+    supply.set_amperage(1.0)
+    supply.set_output(True)
+    measurement = supply.measure()
+    assert measurement.voltage == pytest.approx(5.0)
+    assert measurement.amperage == pytest.approx(0.5)
+    assert measurement.power == pytest.approx(2.5)
+    status = supply.status()
+    assert status.output_on
+    assert status.mode() == "CV"
+
+
+def test_measure_in_constant_current(supply):
+    supply.set_voltage(5.0)
+    supply.set_amperage(0.2)
+    supply.set_output(True)
+    assert supply.measure().voltage == pytest.approx(2.0)
+    assert supply.status().mode() == "CC"
+
+
+def test_output_off_gives_nothing(supply):
+    supply.set_voltage(5.0)
+    assert not supply.status().output_on
+    assert supply.measure().voltage == 0.0
+
+
+def test_limits_of_the_model_are_checked(supply):
+    supply.set_voltage(5.0)
+    with pytest.raises(kd3005p.SupplyError, match="out of range"):
+        supply.set_voltage(31.0)
+    with pytest.raises(kd3005p.SupplyError, match="no channel 2"):
+        supply.measure(2)
+    assert supply.voltage_setting() == pytest.approx(5.0)
+
+
+def test_port_config_is_validated():
+    with pytest.raises(kd3005p.SupplyError):
+        kd3005p.Supply(kd3005p.SIMULATED, port_config={"baud_rate": 12})
+    supply = kd3005p.Supply(kd3005p.SIMULATED, port_config={"timeout_ms": 200})
+    assert supply.model == "KD3005P"
+
+
+def test_sequencer_switches_the_rails(supply):
+    sequencer = kd3005p.Sequencer({"Rail 5V": kd3005p.SIMULATED})
+    log = sequencer.power_up("Rail 5V on, wait 10 ms")
+    assert "Rail 5V: output ON" in log
+    assert supply.status().output_on
+    sequencer.power_down("Rail 5V on, wait 10 ms")
+    assert not supply.status().output_on
+
+
+def test_sequencer_finds_typos_before_switching(supply):
+    sequencer = kd3005p.Sequencer({"Rail 5V": kd3005p.SIMULATED})
+    with pytest.raises(kd3005p.SupplyError, match="Rail 3V3"):
+        sequencer.power_up("Rail 5V on, Rail 3V3 on")
+    assert not supply.status().output_on
@@ -0,0 +1,328 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::timeout;
+use tokio_serial::SerialPortBuilderExt;
+use tracing::{debug, error, warn};
+
+use crate::capture::{capturing, record, CapturingPort, Direction, ReplayPort, REPLAY_PREFIX};
+use crate::driver::Measurement;
+use crate::limits::SafetyLimits;
+use crate::model::*;
+use crate::port_config::PortConfig;
+use crate::protocol::*;
+use crate::serial::*;
+use crate::simulator::{is_simulated, SimulatedPort};
+use crate::status::Status;
+
+// anything commands can be sent through without blocking: a tokio-serial port, the simulated
+// supply or a replay
+pub trait AsyncTransport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + ?Sized> AsyncTransport for T {}
+
+// the simulated supply and a replay answer at once, so their blocking calls never wait
+struct ReadyPort<T>(T);
+
+impl<T: io::Read + Unpin> AsyncRead for ReadyPort<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let length = io::Read::read(&mut self.get_mut().0, buf.initialize_unfilled())?;
+        buf.advance(length);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: io::Write + Unpin> AsyncWrite for ReadyPort<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Write::write(&mut self.get_mut().0, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(io::Write::flush(&mut self.get_mut().0))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+// the capture records the async traffic like the blocking one
+impl<T: AsyncRead + Unpin> AsyncRead for CapturingPort<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            record(&this.port, Direction::Rx, &buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for CapturingPort<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(length)) = result {
+            record(&this.port, Direction::Tx, &buf[..length]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// like open_port() in serial.rs, with the same choice of transport, but one that can be awaited
+fn open_port_async(
+    current_port: &str,
+    port_config: &PortConfig,
+) -> Result<Box<dyn AsyncTransport>, String> {
+    let port = open_transport_async(current_port, port_config).map_err(|e| {
+        warn!(port = current_port, "{}", e); // log the error, the caller shows it
+        e
+    })?;
+    if capturing() {
+        // everything sent and received goes into the capture file too
+        return Ok(Box::new(CapturingPort::new(port, current_port)));
+    }
+    Ok(port)
+}
+
+fn open_transport_async(
+    current_port: &str,
+    port_config: &PortConfig,
+) -> Result<Box<dyn AsyncTransport>, String> {
+    debug!(target: TRAFFIC_TARGET, port = current_port, "Trying to open port");
+    if is_simulated(current_port) {
+        // no hardware needed for this one
+        return Ok(Box::new(ReadyPort(SimulatedPort::open(current_port)?)));
+    }
+    if let Some(path) = current_port.strip_prefix(REPLAY_PREFIX) {
+        // a recorded session instead of the supply
+        return Ok(Box::new(ReadyPort(ReplayPort::open(path)?)));
+    }
+    if cfg!(target_os = "linux") && !std::path::Path::new(current_port).exists() {
+        SERIAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+        return Err("Port path does not exist".to_string());
+    }
+    port_config.validate()?;
+    let opened_port = serialport::new(current_port, port_config.baud_rate)
+        .data_bits(port_config.data_bits)
+        .parity(port_config.parity)
+        .stop_bits(port_config.stop_bits)
+        .flow_control(port_config.flow_control)
+        .timeout(port_config.timeout())
+        .open_native_async()
+        .map_err(|e| {
+            SERIAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+            format!("Error: Failed to open port, error code was: {}", e)
+        })?;
+    debug!(target: TRAFFIC_TARGET, port = current_port, "Port opened");
+    Ok(Box::new(opened_port))
+}
+
+// send a command and collect the answer until the supply is quiet for the port timeout
+async fn transmit_async(
+    port: &mut Box<dyn AsyncTransport>,
+    port_config: &PortConfig,
+    command: &str,
+) -> Vec<u8> {
+    let started = Instant::now(); // for the latency
+    if let Err(e) = port.write_all(command.as_bytes()).await {
+        SERIAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+        error!(command, error = %e, "Write failed");
+        return Vec::new();
+    }
+
+    let mut serial_buf: Vec<u8> = vec![0; 32];
+    let mut result_vec: Vec<u8> = Vec::new();
+    loop {
+        match timeout(port_config.timeout(), port.read(serial_buf.as_mut_slice())).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(length)) => result_vec.extend_from_slice(&serial_buf[..length]),
+            Ok(Err(e)) => {
+                SERIAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+                error!(command, error = %e, "Read failed");
+                break;
+            }
+            Err(_) => break, // the end of every answer
+        }
+    }
+    if result_vec.is_empty() && is_query(command) {
+        SERIAL_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+        warn!(command, "No answer to the query");
+    }
+    debug!(
+        target: TRAFFIC_TARGET,
+        command,
+        tx = ?command.as_bytes(),
+        rx = ?result_vec,
+        answer = %String::from_utf8_lossy(&result_vec),
+        latency_ms = started.elapsed().as_millis() as u64,
+    );
+    result_vec
+}
+
+// like send_command() in serial.rs: a query without answer is tried again
+pub async fn send_command_async(
+    current_port: &str,
+    port_config: &PortConfig,
+    command: &str,
+) -> Result<Vec<u8>, String> {
+    let mut attempt = 0;
+    loop {
+        let mut port = open_port_async(current_port, port_config)?;
+        let answer = transmit_async(&mut port, port_config, command).await;
+        if answer.is_empty() && is_query(command) && attempt < QUERY_RETRIES {
+            attempt += 1;
+            SERIAL_RETRIES.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                port = current_port,
+                command, attempt, "No answer, trying again"
+            );
+            continue;
+        }
+        return Ok(answer);
+    }
+}
+
+// the async twin of driver::Supply, same methods and the same checks
+pub struct AsyncSupply {
+    pub current_port: String,
+    pub port_config: PortConfig,
+    pub model: &'static Model,
+    // what was last switched through this driver, None until then
+    pub output_wanted: Option<bool>,
+    pub ovp_on: Option<bool>,
+    pub ocp_on: Option<bool>,
+    // soft limits of the user, checked before a setting is sent
+    pub limits: SafetyLimits,
+}
+
+impl AsyncSupply {
+    // ask the supply who it is, so the right limits are used
+    pub async fn open(current_port: &str, port_config: &PortConfig) -> Result<AsyncSupply, String> {
+        let mut supply = AsyncSupply {
+            current_port: current_port.to_string(),
+            port_config: port_config.clone(),
+            model: &MODELS[0],
+            output_wanted: None,
+            ovp_on: None,
+            ocp_on: None,
+            limits: SafetyLimits::default(),
+        };
+        let id_answer = supply.id().await?;
+        supply.model = identify(&id_answer).unwrap_or(&MODELS[0]);
+        Ok(supply)
+    }
+
+    async fn send(&self, command: &str) -> Result<Vec<u8>, String> {
+        send_command_async(&self.current_port, &self.port_config, command).await
+    }
+
+    pub async fn id(&self) -> Result<String, String> {
+        parse_text(&self.send(ID_COMMAND).await?, ID_COMMAND)
+    }
+
+    pub async fn status(&self) -> Result<Status, String> {
+        parse_status(&self.send(STATUS_COMMAND).await?)
+    }
+
+    pub async fn voltage_setting(&self, channel: u8) -> Result<f64, String> {
+        let command = voltage_setting_command(self.model, channel)?;
+        parse_number(&self.send(&command).await?, &command)
+    }
+
+    pub async fn amperage_setting(&self, channel: u8) -> Result<f64, String> {
+        let command = amperage_setting_command(self.model, channel)?;
+        parse_number(&self.send(&command).await?, &command)
+    }
+
+    pub async fn set_voltage(&self, channel: u8, voltage: f64) -> Result<(), String> {
+        self.limits.check_voltage(voltage)?;
+        self.send(&set_voltage_command(self.model, channel, voltage)?)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_amperage(&self, channel: u8, amperage: f64) -> Result<(), String> {
+        self.limits.check_amperage(amperage)?;
+        self.send(&set_amperage_command(self.model, channel, amperage)?)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn measure(&self, channel: u8) -> Result<Measurement, String> {
+        let (voltage_command, amperage_command) = measure_commands(self.model, channel)?;
+        let voltage = parse_number(&self.send(&voltage_command).await?, &voltage_command)?;
+        let amperage = parse_number(&self.send(&amperage_command).await?, &amperage_command)?;
+        Ok(Measurement::new(voltage, amperage))
+    }
+
+    // the output only goes on when the settings of every channel are inside the soft limits
+    pub async fn set_output(&mut self, turn_on: bool) -> Result<(), String> {
+        if turn_on {
+            self.check_settings().await?;
+        }
+        self.send(output_command(turn_on)).await?;
+        self.output_wanted = Some(turn_on);
+        Ok(())
+    }
+
+    pub async fn set_ovp(&mut self, turn_on: bool) -> Result<(), String> {
+        self.send(ovp_command(self.model, turn_on)?).await?;
+        self.ovp_on = Some(turn_on);
+        Ok(())
+    }
+
+    pub async fn set_ocp(&mut self, turn_on: bool) -> Result<(), String> {
+        self.send(ocp_command(self.model, turn_on)?).await?;
+        self.ocp_on = Some(turn_on);
+        Ok(())
+    }
+
+    // the status byte has no protection bit, but an output that went off by itself has tripped
+    pub fn protection_tripped(&self, status: &Status) -> bool {
+        self.output_wanted == Some(true) && !status.output_on()
+    }
+
+    // the same as Supply::check_settings()
+    pub async fn check_settings(&self) -> Result<(), String> {
+        if self.limits.max_voltage <= 0.0 && self.limits.max_amperage <= 0.0 {
+            return Ok(());
+        }
+        for channel in 1..=self.model.channels {
+            self.limits
+                .check_voltage(self.voltage_setting(channel).await?)?;
+            self.limits
+                .check_amperage(self.amperage_setting(channel).await?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// a port name like "replay:session.cap" plays a recorded session back instead of a real port,
+// "replay:session.cap#/dev/ttyACM1" plays only what went over that port
+pub const REPLAY_PREFIX: &str = "replay:";
+pub const REPLAY_PORT_SEPARATOR: char = '#';
+
+// the file all traffic is written to, None when nothing is recorded
+struct Capture {
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Tx,
+    Rx,
+}
+
+// one line of a capture file
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureRecord {
+    pub seconds: f64, // since the capture was started
+    pub port: String,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+fn lock_capture() -> std::sync::MutexGuard<'static, Option<Capture>> {
+    CAPTURE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// record everything that goes over any port into the file, until stop_capture()
+pub fn start_capture(path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Cannot create {}: {}", path, e))?;
+    let mut file = BufWriter::new(file);
+    let epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs_f64())
+        .unwrap_or(0.0);
+    writeln!(
+        file,
+        "# kd3005p-rs capture, started {:.3} s after 1970-01-01 UTC\n# seconds\tport\tTX/RX\thex bytes\ttext",
+        epoch
+    )
+    .map_err(|e| format!("Cannot write {}: {}", path, e))?;
+    *lock_capture() = Some(Capture {
+        file,
+        started: Instant::now(),
+    });
+    Ok(())
+}
+
+pub fn stop_capture() {
+    if let Some(mut capture) = lock_capture().take() {
+        let _ = capture.file.flush();
+    }
+}
+
+pub fn capturing() -> bool {
+    lock_capture().is_some()
+}
+
+// add one line to the capture file, if there is one
+pub(crate) fn record(port: &str, direction: Direction, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    if let Some(capture) = lock_capture().as_mut() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let line = format!(
+            "{:.6}\t{}\t{}\t{}\t{:?}\n",
+            capture.started.elapsed().as_secs_f64(),
+            port,
+            match direction {
+                Direction::Tx => "TX",
+                Direction::Rx => "RX",
+            },
+            hex.join(" "),
+            String::from_utf8_lossy(bytes)
+        );
+        // written at once, so nothing is lost when the program is killed
+        let _ = capture
+            .file
+            .write_all(line.as_bytes())
+            .and_then(|_| capture.file.flush());
+    }
+}
+
+// read a capture file back, the text column is only for people and is not read
+pub fn read_capture(path: &str) -> Result<Vec<CaptureRecord>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let mut records = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let invalid = || format!("{} line {}: invalid record '{}'", path, number + 1, line);
+        if fields.len() < 4 {
+            return Err(invalid());
+        }
+        let direction = match fields[2] {
+            "TX" => Direction::Tx,
+            "RX" => Direction::Rx,
+            _ => return Err(invalid()),
+        };
+        let bytes = fields[3]
+            .split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        records.push(CaptureRecord {
+            seconds: fields[0].parse().map_err(|_| invalid())?,
+            port: fields[1].to_string(),
+            direction,
+            bytes,
+        });
+    }
+    Ok(records)
+}
+
+// a real port (or the simulated one) with everything that goes through it recorded
+pub struct CapturingPort<T> {
+    pub(crate) inner: T,
+    pub(crate) port: String,
+}
+
+impl<T> CapturingPort<T> {
+    pub fn new(inner: T, port: &str) -> Self {
+        CapturingPort {
+            inner,
+            port: port.to_string(),
+        }
+    }
+}
+
+impl<T: Write> Write for CapturingPort<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.inner.write(buf)?;
+        record(&self.port, Direction::Tx, &buf[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Read> Read for CapturingPort<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        record(&self.port, Direction::Rx, &buf[..length]);
+        Ok(length)
+    }
+}
+
+// the recorded commands with their answers, in the order they were sent
+struct ReplaySession {
+    exchanges: VecDeque<(Vec<u8>, Vec<u8>)>,
+    all: Vec<(Vec<u8>, Vec<u8>)>, // to start again
+}
+
+// like the simulated supply, a session is kept between the port openings
+static REPLAYS: Mutex<Option<HashMap<String, Arc<Mutex<ReplaySession>>>>> = Mutex::new(None);
+
+// the session of "file" or "file#port", without a port it is the first port of the file
+fn replay_session(replay: &str) -> Result<Arc<Mutex<ReplaySession>>, String> {
+    let mut replays = REPLAYS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let replays = replays.get_or_insert_with(HashMap::new);
+    if let Some(session) = replays.get(replay) {
+        return Ok(session.clone());
+    }
+    let (path, port) = match replay.split_once(REPLAY_PORT_SEPARATOR) {
+        Some((path, port)) => (path, Some(port)),
+        None => (replay, None),
+    };
+    let records = read_capture(path)?;
+    let port = match port.or_else(|| records.first().map(|record| record.port.as_str())) {
+        Some(port) => port.to_string(),
+        None => String::new(), // an empty file, nothing to replay
+    };
+    if !records.is_empty() && !records.iter().any(|record| record.port == port) {
+        return Err(format!("{} has nothing recorded for port {}", path, port));
+    }
+    // every TX with all RX up to the next TX of the same port is one exchange
+    let mut exchanges: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    for record in records.into_iter().filter(|record| record.port == port) {
+        match (record.direction, exchanges.last_mut()) {
+            (Direction::Tx, _) => exchanges.push((record.bytes, Vec::new())),
+            (Direction::Rx, Some((_, answer))) => answer.extend_from_slice(&record.bytes),
+            (Direction::Rx, None) => {} // an answer without command, e.g. from a reset
+        }
+    }
+    let session = Arc::new(Mutex::new(ReplaySession {
+        exchanges: exchanges.clone().into(),
+        all: exchanges,
+    }));
+    replays.insert(replay.to_string(), session.clone());
+    Ok(session)
+}
+
+fn lock_session(session: &Arc<Mutex<ReplaySession>>) -> std::sync::MutexGuard<'_, ReplaySession> {
+    session
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// start the replay of a file (or "file#port") from its beginning again
+pub fn rewind_replay(replay: &str) -> Result<(), String> {
+    let session = replay_session(replay)?;
+    let mut session = lock_session(&session);
+    session.exchanges = session.all.clone().into();
+    Ok(())
+}
+
+// how many recorded commands were not sent yet, 0 when the driver did all of them
+pub fn replay_remaining(replay: &str) -> Result<usize, String> {
+    let session = replay_session(replay)?;
+    let remaining = lock_session(&session).exchanges.len();
+    Ok(remaining)
+}
+
+// a port that answers with a recorded session; every command must be the one that was
+// recorded next, otherwise the write fails
+pub struct ReplayPort {
+    session: Arc<Mutex<ReplaySession>>,
+    answer: Vec<u8>,
+}
+
+impl ReplayPort {
+    pub fn open(replay: &str) -> Result<Self, String> {
+        Ok(ReplayPort {
+            session: replay_session(replay)?,
+            answer: Vec::new(),
+        })
+    }
+}
+
+impl Write for ReplayPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = lock_session(&self.session);
+        match session.exchanges.front() {
+            Some((command, _)) if command.as_slice() == buf => {
+                let (_, answer) = session.exchanges.pop_front().unwrap_or_default();
+                self.answer = answer;
+                Ok(buf.len())
+            }
+            Some((command, _)) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "replay expected {:?}, got {:?}",
+                    String::from_utf8_lossy(command),
+                    String::from_utf8_lossy(buf)
+                ),
+            )),
+            None => Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "replay is at its end, got {:?}",
+                    String::from_utf8_lossy(buf)
+                ),
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for ReplayPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // hand out the recorded answer, then nothing like a port that timed out
+        let length = buf.len().min(self.answer.len());
+        buf[..length].copy_from_slice(&self.answer[..length]);
+        self.answer.drain(..length);
+        Ok(length)
+    }
+}
@@ -0,0 +1,363 @@
+use std::time::Instant;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+use crate::driver::{Measurement, Supply};
+use crate::energy::EnergyCounter;
+use crate::model::Model;
+use crate::port_config::PortConfig;
+use crate::sequence::*;
+
+// the current must stay below the termination current for this many readings in a row
+const TERMINATION_READINGS: u32 = 3;
+
+// the battery types with their usual values per cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chemistry {
+    LiIon,
+    LiFePo4,
+    NiMh,
+    LeadAcid,
+}
+
+pub const CHEMISTRIES: [Chemistry; 4] = [
+    Chemistry::LiIon,
+    Chemistry::LiFePo4,
+    Chemistry::NiMh,
+    Chemistry::LeadAcid,
+];
+
+impl Chemistry {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Chemistry::LiIon => "Li-ion",
+            Chemistry::LiFePo4 => "LiFePO4",
+            Chemistry::NiMh => "NiMH",
+            Chemistry::LeadAcid => "Lead-acid",
+        }
+    }
+
+    // the name is matched without case, so "li-ion" works too
+    pub fn from_name(name: &str) -> Result<Chemistry, String> {
+        CHEMISTRIES
+            .iter()
+            .find(|chemistry| chemistry.name().eq_ignore_ascii_case(name.trim()))
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "Unknown battery type '{}', expected Li-ion, LiFePO4, NiMH or Lead-acid",
+                    name.trim()
+                )
+            })
+    }
+
+    // the CV voltage of one cell
+    pub fn cell_voltage(&self) -> f64 {
+        match self {
+            Chemistry::LiIon => 4.2,
+            Chemistry::LiFePo4 => 3.6,
+            Chemistry::NiMh => 1.5,
+            Chemistry::LeadAcid => 2.4,
+        }
+    }
+
+    // the charge ends when the current in CV drops to this part of the charge current
+    pub fn termination_ratio(&self) -> f64 {
+        match self {
+            Chemistry::LiIon => 0.1,
+            Chemistry::LiFePo4 => 0.05,
+            Chemistry::NiMh => 0.1,
+            Chemistry::LeadAcid => 0.05,
+        }
+    }
+}
+
+// everything a charge needs, all values for the whole pack
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChargeProfile {
+    pub chemistry: Chemistry,
+    pub charge_current: f64,      // A in the CC phase
+    pub float_voltage: f64,       // V in the CV phase
+    pub termination_current: f64, // A, the charge is done below this in CV
+    pub max_minutes: f64,         // the charge is stopped after this time in any case
+    pub max_milliamp_hours: f64,  // and after this charge, 0 means no limit
+}
+
+impl ChargeProfile {
+    // the usual values for a pack of `cells` cells in series
+    pub fn new(chemistry: Chemistry, cells: u32, charge_current: f64) -> Self {
+        ChargeProfile {
+            chemistry,
+            charge_current,
+            float_voltage: chemistry.cell_voltage() * cells as f64,
+            termination_current: charge_current * chemistry.termination_ratio(),
+            max_minutes: 240.0,
+            max_milliamp_hours: 0.0,
+        }
+    }
+
+    // check the values against each other and against what the supply can do
+    pub fn validate(&self, model: &Model) -> Result<(), String> {
+        model.check_voltage(self.float_voltage)?;
+        model.check_amperage(self.charge_current)?;
+        if self.float_voltage <= 0.0 || self.charge_current <= 0.0 {
+            return Err("The float voltage and the charge current must be above 0".to_string());
+        }
+        if self.termination_current < 0.0 || self.termination_current >= self.charge_current {
+            return Err(
+                "The termination current must be between 0 and the charge current".to_string(),
+            );
+        }
+        if self.max_minutes <= 0.0 {
+            return Err("The maximum time must be above 0 minutes".to_string());
+        }
+        if self.max_milliamp_hours < 0.0 {
+            return Err("The maximum charge must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+// where the charge is: first constant current, then constant voltage, then done with the reason
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChargePhase {
+    ConstantCurrent,
+    ConstantVoltage,
+    Done(String),
+}
+
+// a running charge on one channel, stepped by the monitoring once per reading
+#[derive(Clone, Debug, PartialEq)]
+pub struct Charger {
+    pub label: String,
+    pub current_port: String,
+    pub model: &'static Model,
+    pub channel: u8,
+    pub profile: ChargeProfile,
+    pub phase: ChargePhase,
+    pub energy: EnergyCounter,
+    pub last_measurement: Option<Measurement>,
+    started: Instant,
+    low_current_readings: u32,
+}
+
+// a running charge changes with every reading, so the GUI can compare it directly
+#[cfg(feature = "gui")]
+impl Data for Charger {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl Charger {
+    // set the CV voltage and the CC current through the driver, so the soft limits of the supply
+    // are checked, then switch on through the sequencer, which checks the status byte
+    pub fn start(
+        supply: &mut Supply,
+        label: &str,
+        channel: u8,
+        profile: ChargeProfile,
+        my_output: &mut String,
+    ) -> Result<Charger, String> {
+        // OUT1 switches all channels together, the other channel would get its old setting
+        if supply.model.channels > 1 {
+            return Err(format!(
+                "The {} switches all {} outputs together, charging needs a supply with one output",
+                supply.model.name, supply.model.channels
+            ));
+        }
+        profile.validate(supply.model)?;
+        // both before anything is sent
+        supply.limits.check_voltage(profile.float_voltage)?;
+        supply.limits.check_amperage(profile.charge_current)?;
+        supply.set_voltage(channel, profile.float_voltage)?;
+        supply.set_amperage(channel, profile.charge_current)?;
+        let rail = Rail {
+            limits: supply.limits.clone(),
+            ..Rail::new(label, &supply.current_port)
+        };
+        let steps = vec![SequenceStep::Rail(rail.label.clone())];
+        power_up(
+            &steps,
+            std::slice::from_ref(&rail),
+            &supply.port_config,
+            my_output,
+        )?;
+        my_output.push_str(&format!(
+            "{}: charging {} with {:.3} A up to {:.2} V \n",
+            rail.label,
+            profile.chemistry.name(),
+            profile.charge_current,
+            profile.float_voltage
+        ));
+        Ok(Charger {
+            label: rail.label.clone(),
+            current_port: rail.current_port.clone(),
+            model: supply.model,
+            channel,
+            profile,
+            phase: ChargePhase::ConstantCurrent,
+            energy: EnergyCounter::new(),
+            last_measurement: None,
+            started: Instant::now(),
+            low_current_readings: 0,
+        })
+    }
+
+    pub fn running(&self) -> bool {
+        !matches!(self.phase, ChargePhase::Done(_))
+    }
+
+    // read the status and the output once, follow the phases and stop when the charge is done
+    pub fn step(&mut self, port_config: &PortConfig, my_output: &mut String) {
+        if !self.running() {
+            return;
+        }
+        let supply = Supply::with_model(&self.current_port, port_config, self.model);
+        let status = match supply.status() {
+            Ok(status) => status,
+            Err(_) => {
+                self.stop(port_config, "no status from the supply", my_output);
+                return;
+            }
+        };
+        if !status.output_on() {
+            // switched off at the panel, by the protection or the battery is gone
+            self.phase = ChargePhase::Done("the output went off".to_string());
+            my_output.push_str(&format!("{}: the output went off! \n", self.label));
+            return;
+        }
+
+        let measurement = match supply.measure(self.channel) {
+            Ok(measurement) => measurement,
+            Err(_) => {
+                self.stop(port_config, "no reading from the supply", my_output);
+                return;
+            }
+        };
+        self.energy.add(measurement, Instant::now());
+        self.last_measurement = Some(measurement);
+
+        // the supply leaves CC by itself when the battery reaches the float voltage
+        if self.phase == ChargePhase::ConstantCurrent && status.constant_voltage(self.channel) {
+            self.phase = ChargePhase::ConstantVoltage;
+            my_output.push_str(&format!(
+                "{}: CC -> CV at {:.2} V after {} \n",
+                self.label,
+                measurement.voltage,
+                self.energy.describe()
+            ));
+        }
+        if self.phase == ChargePhase::ConstantVoltage
+            && measurement.amperage <= self.profile.termination_current
+        {
+            self.low_current_readings += 1;
+        } else {
+            self.low_current_readings = 0;
+        }
+
+        let minutes = self.started.elapsed().as_secs_f64() / 60.0;
+        if self.low_current_readings >= TERMINATION_READINGS {
+            let reason = format!("full, the current dropped to {:.3} A", measurement.amperage);
+            self.stop(port_config, &reason, my_output);
+        } else if minutes >= self.profile.max_minutes {
+            let reason = format!(
+                "the maximum time of {} min is over",
+                self.profile.max_minutes
+            );
+            self.stop(port_config, &reason, my_output);
+        } else if self.profile.max_milliamp_hours > 0.0
+            && self.energy.milliamp_hours >= self.profile.max_milliamp_hours
+        {
+            let reason = format!(
+                "the maximum charge of {} mAh is reached",
+                self.profile.max_milliamp_hours
+            );
+            self.stop(port_config, &reason, my_output);
+        }
+    }
+
+    // switch off through the sequencer and remember why
+    pub fn stop(&mut self, port_config: &PortConfig, reason: &str, my_output: &mut String) {
+        let steps = vec![SequenceStep::Rail(self.label.clone())];
+        let rail = Rail::new(&self.label, &self.current_port);
+        if let Err(e) = power_down(&steps, &[rail], port_config, my_output) {
+            my_output.push_str(&format!("{}! \n", e));
+        }
+        my_output.push_str(&format!(
+            "{}: charge stopped, {}: {} \n",
+            self.label,
+            reason,
+            self.energy.describe()
+        ));
+        self.phase = ChargePhase::Done(reason.to_string());
+    }
+
+    // e.g. "Li-ion CV: 4.20 V 0.350 A, 0.4521 Wh / 110.20 mAh in 00:15:10"
+    pub fn describe(&self) -> String {
+        let phase = match &self.phase {
+            ChargePhase::ConstantCurrent => "CC".to_string(),
+            ChargePhase::ConstantVoltage => "CV".to_string(),
+            ChargePhase::Done(reason) => format!("done ({})", reason),
+        };
+        let reading = match &self.last_measurement {
+            Some(measurement) => {
+                format!("{:.2} V {:.3} A", measurement.voltage, measurement.amperage)
+            }
+            None => "-- V -- A".to_string(),
+        };
+        format!(
+            "{} {}: {}, {}",
+            self.profile.chemistry.name(),
+            phase,
+            reading,
+            self.energy.describe()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(current_port: &str) -> Supply {
+        Supply::open(current_port, &PortConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn a_dual_supply_is_refused() {
+        let mut supply = open("SIMULATED:KA3305P charger");
+        let profile = ChargeProfile::new(Chemistry::LiIon, 1, 0.5);
+        let mut my_output = String::new();
+        assert!(Charger::start(&mut supply, "dual", 1, profile, &mut my_output).is_err());
+        assert!(!supply.status().unwrap().output_on());
+    }
+
+    #[test]
+    fn the_soft_limits_are_checked_before_anything_is_sent() {
+        let mut supply = open("SIMULATED:KD3005P charger limits");
+        supply.limits.max_amperage = 0.2;
+        let profile = ChargeProfile::new(Chemistry::LiIon, 2, 0.5);
+        let mut my_output = String::new();
+        assert!(Charger::start(&mut supply, "pack", 1, profile, &mut my_output).is_err());
+        assert_eq!(supply.voltage_setting(1).unwrap(), 12.0); // untouched
+        assert!(!supply.status().unwrap().output_on());
+    }
+
+    #[test]
+    fn a_charge_starts_in_cc() {
+        let mut supply = open("SIMULATED:KD3005P charger start");
+        let profile = ChargeProfile::new(Chemistry::LiIon, 1, 0.3);
+        let mut my_output = String::new();
+        let mut charger = Charger::start(&mut supply, "cell", 1, profile, &mut my_output).unwrap();
+        assert_eq!(supply.voltage_setting(1).unwrap(), 4.2);
+        assert!(supply.status().unwrap().output_on());
+        // 4.2 V into 10 Ohm wants 0.42 A, so the 0.3 A limit holds it in CC
+        charger.step(&PortConfig::default(), &mut my_output);
+        assert_eq!(charger.phase, ChargePhase::ConstantCurrent);
+        assert_eq!(charger.last_measurement.unwrap().amperage, 0.3);
+        charger.stop(&PortConfig::default(), "test", &mut my_output);
+        assert!(!supply.status().unwrap().output_on());
+    }
+}
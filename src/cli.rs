@@ -0,0 +1,314 @@
+use crate::limits::*;
+use crate::port_config::*;
+use crate::settings::*;
+
+pub const DEFAULT_MQTT_TOPIC: &str = "kd3005p";
+pub const DEFAULT_MQTT_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_SAMPLES: usize = 10;
+pub const DEFAULT_MEASURE_INTERVAL_MS: u64 = 200;
+
+pub const USAGE: &str = "Usage: kd3005p-rs [OPTIONS]
+       kd3005p-rs measure --port <PORT> [--samples <N>] [--channel <CH>] [--interval <MS>]
+       kd3005p-rs memories --port <PORT> [--store <SLOT>=<PROFILE>]...
+
+Commands:
+    measure                  read the output N times and print min/max/mean/std/rms, needs --port
+    memories                 print the settings of M1-M5 and put the supply back, needs --port
+
+Options:
+    --port <PORT>            serial port of the supply, SIMULATED for the simulated KD3005P
+                             (SIMULATED:<MODEL> for another model, e.g. SIMULATED:KA3305P),
+                             replay:<FILE>[#<PORT>] to play back a capture file, only the
+                             traffic of PORT or of the first port in it (default the last port)
+    --config <FILE>          read and save the settings in FILE, the serial port settings too
+                             (default kd3005p-rs/settings.conf in the config directory)
+    --capture <FILE>         record everything sent to and received from the supplies in FILE
+    --scpi <ADDRESS>         run without GUI as SCPI server on ADDRESS (e.g. 0.0.0.0:5025), needs --port
+    --http <ADDRESS>         run without GUI as web server on ADDRESS (e.g. 127.0.0.1:8080), needs --port
+    --mqtt <BROKER>          run without GUI as MQTT bridge to BROKER (e.g. localhost:1883), needs --port
+    --mqtt-topic <PREFIX>    topic prefix for MQTT (default kd3005p)
+    --mqtt-interval <MS>     MQTT publish interval in milliseconds (default 1000)
+    --samples <N>            readings for measure (default 10)
+    --channel <CH>           channel for measure (default 1)
+    --interval <MS>          time between the readings of measure (default 200)
+    --store <SLOT>=<PROFILE> store a profile of the settings file in memory SLOT (1-5) first,
+                             e.g. --store \"1=ESP32 board\"
+    --baud-rate <BAUD>       baud rate of the port (default 9600)
+    --data-bits <BITS>       5, 6, 7 or 8 (default 8)
+    --parity <PARITY>        none, odd or even (default none)
+    --stop-bits <BITS>       1 or 2 (default 1)
+    --flow-control <FLOW>    none, software or hardware (default none)
+    --timeout <MS>           read timeout in milliseconds (default 100)
+    --max-voltage <V>        soft limit for the voltage setting (default none)
+    --max-current <A>        soft limit for the current setting (default none)
+    --max-power <W>          switch the output off above this measured power (default none)
+    --max-on-time <S>        switch the output off after this many seconds (default none)
+    --watchdog <S>           switch the output off when the GUI or the client is silent this long
+    -v, -vv                  log the serial traffic (-v) or everything (-vv)
+    -q, --quiet              log only warnings and errors
+    -h, --help               print this help
+";
+
+pub struct CliOptions {
+    pub port: Option<String>,
+    pub capture_file: Option<String>,
+    pub scpi_address: Option<String>,
+    pub http_address: Option<String>,
+    pub mqtt_broker: Option<String>,
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))] // only read with the mqtt feature
+    pub mqtt_topic: String,
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    pub mqtt_interval_ms: u64,
+    pub measure_samples: Option<usize>, // Some with the measure command
+    pub measure_channel: u8,
+    pub measure_interval_ms: u64,
+    pub memories: bool,                        // the memories command
+    pub memory_assignments: Vec<(u8, String)>, // slot and profile name of every --store
+    pub port_config: PortConfig,
+    pub limits: SafetyLimits,
+    pub settings: Settings, // from the settings file, without the flags
+    pub settings_path: Option<String>, // None when there is no config directory
+    pub settings_warning: Option<String>, // why the settings file could not be used
+    pub verbosity: i8,      // 0 is info, below is quieter, above is more
+    pub show_help: bool,
+}
+
+// turn the command line arguments (without the program name) into options
+pub fn parse_args(args: &[String]) -> Result<CliOptions, String> {
+    let mut settings_file: Option<String> = None;
+    let mut port_settings: Vec<(&str, String)> = Vec::new(); // single settings, applied after the file
+    let mut limit_settings: Vec<(&str, String)> = Vec::new();
+    let mut show_help = false;
+    let mut verbosity: i8 = 0;
+    let mut port: Option<String> = None;
+    let mut capture_file: Option<String> = None;
+    let mut scpi_address: Option<String> = None;
+    let mut http_address: Option<String> = None;
+    let mut mqtt_broker: Option<String> = None;
+    let mut mqtt_topic = DEFAULT_MQTT_TOPIC.to_string();
+    let mut mqtt_interval_ms = DEFAULT_MQTT_INTERVAL_MS;
+    let mut measure = false;
+    let mut samples = DEFAULT_SAMPLES;
+    let mut measure_channel = 1;
+    let mut measure_interval_ms = DEFAULT_MEASURE_INTERVAL_MS;
+    let mut memories = false;
+    let mut memory_assignments: Vec<(u8, String)> = Vec::new();
+
+    // the command comes first, the options after it
+    let args = match args.first().map(String::as_str) {
+        Some("measure") => {
+            measure = true;
+            &args[1..]
+        }
+        Some("memories") => {
+            memories = true;
+            &args[1..]
+        }
+        _ => args,
+    };
+
+    let mut arg_iter = args.iter();
+    while let Some(arg) = arg_iter.next() {
+        let limit_key = match arg.as_str() {
+            "--max-voltage" => Some(MAX_VOLTAGE_KEY),
+            "--max-current" => Some(MAX_AMPERAGE_KEY),
+            "--max-power" => Some(MAX_POWER_KEY),
+            "--max-on-time" => Some(MAX_ON_TIME_KEY),
+            "--watchdog" => Some(WATCHDOG_KEY),
+            _ => None,
+        };
+        if let Some(key) = limit_key {
+            match arg_iter.next() {
+                Some(value) => limit_settings.push((key, value.to_string())),
+                None => return Err(format!("Missing value for '{}'\n\n{}", arg, USAGE)),
+            }
+            continue;
+        }
+        let setting_key = match arg.as_str() {
+            "-h" | "--help" => {
+                show_help = true;
+                continue;
+            }
+            "-v" | "--verbose" => {
+                verbosity += 1;
+                continue;
+            }
+            "-vv" => {
+                verbosity += 2;
+                continue;
+            }
+            "-q" | "--quiet" => {
+                verbosity -= 1;
+                continue;
+            }
+            "--config" | "--port" | "--capture" | "--scpi" | "--http" | "--mqtt"
+            | "--mqtt-topic" | "--mqtt-interval" | "--samples" | "--channel" | "--interval"
+            | "--store" => None,
+            "--baud-rate" => Some(BAUD_RATE_KEY),
+            "--data-bits" => Some(DATA_BITS_KEY),
+            "--parity" => Some(PARITY_KEY),
+            "--stop-bits" => Some(STOP_BITS_KEY),
+            "--flow-control" => Some(FLOW_CONTROL_KEY),
+            "--timeout" => Some(TIMEOUT_KEY),
+            _ => return Err(format!("Unknown argument '{}'\n\n{}", arg, USAGE)),
+        };
+        let value = match arg_iter.next() {
+            Some(value) => value.to_string(),
+            None => return Err(format!("Missing value for '{}'\n\n{}", arg, USAGE)),
+        };
+        match (setting_key, arg.as_str()) {
+            (Some(key), _) => port_settings.push((key, value)),
+            (None, "--port") => port = Some(value),
+            (None, "--capture") => capture_file = Some(value),
+            (None, "--scpi") => scpi_address = Some(value),
+            (None, "--http") => http_address = Some(value),
+            (None, "--mqtt") => mqtt_broker = Some(value),
+            (None, "--mqtt-topic") => mqtt_topic = value,
+            (None, "--mqtt-interval") => {
+                mqtt_interval_ms = match value.parse::<u64>() {
+                    Ok(interval) if interval >= 100 => interval,
+                    _ => {
+                        return Err(format!(
+                            "--mqtt-interval needs at least 100 ms, not '{}'",
+                            value
+                        ))
+                    }
+                }
+            }
+            (None, "--samples") => {
+                samples = match value.parse::<usize>() {
+                    Ok(number) if number >= 1 => number,
+                    _ => return Err(format!("--samples needs a number above 0, not '{}'", value)),
+                }
+            }
+            (None, "--channel") => {
+                measure_channel = value
+                    .parse::<u8>()
+                    .map_err(|_| format!("Invalid channel '{}'", value))?
+            }
+            (None, "--interval") => {
+                measure_interval_ms = value
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid interval '{}'", value))?
+            }
+            (None, "--store") => {
+                let assignment = value
+                    .split_once('=')
+                    .and_then(|(slot, name)| Some((slot.trim().parse::<u8>().ok()?, name)));
+                match assignment {
+                    Some((slot, name)) if !name.trim().is_empty() => {
+                        memory_assignments.push((slot, name.trim().to_string()))
+                    }
+                    _ => {
+                        return Err(format!(
+                            "--store needs <SLOT>=<PROFILE>, e.g. 1=ESP32, not '{}'",
+                            value
+                        ))
+                    }
+                }
+            }
+            (None, _) => settings_file = Some(value), // --config
+        }
+    }
+
+    // the settings file is read after all flags, so a broken file does not stop -h; it is
+    // only a warning, the program starts with the defaults and does not save over the file
+    let mut settings_path = settings_file
+        .or_else(|| default_settings_path().map(|path| path.to_string_lossy().to_string()));
+    let mut settings_warning = None;
+    let settings = match &settings_path {
+        Some(path) => match Settings::load(path) {
+            Ok(settings) => settings,
+            Err(e) => {
+                settings_warning = Some(format!(
+                    "{}, the defaults are used and the settings are not saved",
+                    e.trim()
+                ));
+                settings_path = None;
+                Settings::default()
+            }
+        },
+        None => Settings::default(),
+    };
+    // the file gives the base, the single flags override it
+    let mut port_config = settings.port_config.clone();
+    for (key, value) in port_settings {
+        port_config.set(key, &value)?;
+    }
+    let mut limits = settings.limits.clone();
+    for (key, value) in limit_settings {
+        limits.set(key, &value)?;
+    }
+
+    if port.is_none() {
+        port = settings.find_port();
+    }
+
+    if scpi_address.is_some() && port.is_none() {
+        return Err(format!(
+            "--scpi needs the --port of the supply\n\n{}",
+            USAGE
+        ));
+    }
+    if http_address.is_some() && port.is_none() {
+        return Err(format!(
+            "--http needs the --port of the supply\n\n{}",
+            USAGE
+        ));
+    }
+    if mqtt_broker.is_some() && port.is_none() {
+        return Err(format!(
+            "--mqtt needs the --port of the supply\n\n{}",
+            USAGE
+        ));
+    }
+    if measure && port.is_none() {
+        return Err(format!(
+            "measure needs the --port of the supply\n\n{}",
+            USAGE
+        ));
+    }
+    if memories && port.is_none() {
+        return Err(format!(
+            "memories needs the --port of the supply\n\n{}",
+            USAGE
+        ));
+    }
+    if !memory_assignments.is_empty() && !memories {
+        return Err(format!("--store works with memories only\n\n{}", USAGE));
+    }
+    let server_modes = [&scpi_address, &http_address, &mqtt_broker]
+        .iter()
+        .filter(|mode| mode.is_some())
+        .count()
+        + measure as usize
+        + memories as usize;
+    if server_modes > 1 {
+        return Err(format!(
+            "Use only one of measure, memories, --scpi, --http and --mqtt\n\n{}",
+            USAGE
+        ));
+    }
+
+    Ok(CliOptions {
+        port,
+        capture_file,
+        scpi_address,
+        http_address,
+        mqtt_broker,
+        mqtt_topic,
+        mqtt_interval_ms,
+        measure_samples: if measure { Some(samples) } else { None },
+        measure_channel,
+        measure_interval_ms,
+        memories,
+        memory_assignments,
+        port_config,
+        limits,
+        settings,
+        settings_path,
+        settings_warning,
+        verbosity,
+        show_help,
+    })
+}
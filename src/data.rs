@@ -1,13 +1,509 @@
 use druid::{Data, Lens};
-use serialport::SerialPortInfo;
+use std::sync::Arc;
+
+use crate::charger::*;
+use crate::energy::EnergyCounter;
+use crate::limits::*;
+use crate::model::*;
+use crate::port_config::*;
+use crate::profiles::{Profile, NOTES_KEY};
+use crate::rules::RuleSet;
+use crate::settings::{Settings, AMPERAGE_KEY, VOLTAGE_KEY};
+use crate::stats::MeasurementStatistics;
+use crate::sweep::*;
+
+// what the port field shows before a port is chosen
+pub const NO_PORT: &str = "None selected";
 
 #[derive(Clone, Data, Lens)]
 pub struct TheAppState {
+    pub instruments: Arc<Vec<Instrument>>,
+    pub next_instrument_id: u32,
+    pub output_info: String,
+    pub sequence_text: String,
+    pub sequence_running: bool, // one power sequence at a time
+    pub port_config: PortConfig,
+    pub port_config_form: PortConfigForm,
+    pub limits: SafetyLimits, // from the command line, new supplies start with them
+    pub settings: Settings,   // from the settings file, saved again when the program ends
+    pub settings_path: Option<String>,
+    pub sweep_form: SweepForm,
+    pub console_form: ConsoleForm,
+    pub profile_form: ProfileForm,
+    pub log_entries: Arc<Vec<LogEntry>>, // the newest lines of the log, see logging.rs
+    pub log_filter: LogFilter,
+    pub log_path: String, // where "Save log" writes to
+    pub log_message: String,
+    #[data(ignore)]
+    pub watchdog: Option<Watchdog>, // fed by the sweep too, it runs beside the GUI
+}
+
+// everything that belongs to one connected power supply
+#[derive(Clone, Data, Lens)]
+pub struct Instrument {
+    pub id: u32,
+    pub label: String,
+    pub model: String,
     pub current_port: String,
-    pub port_open: bool,
+    pub channels: Arc<Vec<Channel>>,
+    pub live: bool,
+    pub output_info: String,
+    pub limits: SafetyLimits, // soft limits of this supply
+    pub limits_form: LimitsForm,
+    pub limits_applied: bool, // changed in the GUI, saved with the settings
+    pub guard: LimitGuard,
+    pub rules_text: String, // one alarm rule per line, see README
+    pub rules: RuleSet,     // the applied rules, checked while "Watch rules" is on
+    pub watch_rules: bool,
+    pub default_voltage: f64, // setpoints of new channels, from the settings
+    pub default_amperage: f64,
+}
+
+// settings and readouts of one output channel
+#[derive(Clone, Data, Lens)]
+pub struct Channel {
+    pub number: u8,
     pub current_voltage: String,
     pub current_amperage: String,
-    pub output_info: String,
-    #[data(ignore)]
-    pub the_ports: Vec<SerialPortInfo>,
+    pub measured_voltage: String,
+    pub measured_amperage: String,
+    pub energy: EnergyCounter, // Wh and mAh of the live readings
+    pub statistics: MeasurementStatistics, // min/max/mean of the live readings
+    pub statistics_window: String,
+    pub charge_form: ChargeForm,
+    pub charger: Option<Charger>, // the running or last battery charge
+}
+
+// the data each instrument panel works on: the shared port settings and the instrument itself
+pub type InstrumentData = (PortConfig, Instrument);
+
+// the data each channel column works on: its instrument and the channel itself
+pub type ChannelData = (InstrumentData, Channel);
+
+impl Channel {
+    pub fn new(number: u8, voltage: f64, amperage: f64) -> Self {
+        Channel {
+            number,
+            current_voltage: format!("{:.2}", voltage),
+            current_amperage: format!("{:.3}", amperage),
+            measured_voltage: "--".to_string(),
+            measured_amperage: "--".to_string(),
+            energy: EnergyCounter::new(),
+            statistics: MeasurementStatistics::new(0),
+            statistics_window: "0".to_string(),
+            charge_form: ChargeForm::from_profile(&ChargeProfile::new(Chemistry::LiIon, 1, 0.5)),
+            charger: None,
+        }
+    }
+}
+
+impl Channel {
+    pub fn charging(&self) -> bool {
+        self.charger.as_ref().is_some_and(Charger::running)
+    }
+}
+
+impl Instrument {
+    pub fn new(id: u32, label: &str, limits: &SafetyLimits, settings: &Settings) -> Self {
+        Instrument {
+            id,
+            label: label.to_string(),
+            model: KD3005P.name.to_string(), // until the supply tells us what it is
+            current_port: NO_PORT.to_string(),
+            channels: Arc::new(vec![Channel::new(1, settings.voltage, settings.amperage)]),
+            live: false,
+            output_info: String::new(),
+            limits: limits.clone(),
+            limits_form: LimitsForm::from_limits(limits),
+            limits_applied: false,
+            guard: LimitGuard::new(),
+            rules_text: String::new(),
+            rules: RuleSet::default(),
+            watch_rules: false,
+            default_voltage: settings.voltage,
+            default_amperage: settings.amperage,
+        }
+    }
+}
+
+impl Instrument {
+    // false until a port is picked, nothing is sent to the supply then
+    pub fn has_port(&self) -> bool {
+        self.current_port != NO_PORT && !self.current_port.is_empty()
+    }
+
+    pub fn model_info(&self) -> &'static Model {
+        find_model(&self.model)
+    }
+
+    // take over a detected model, with one channel column per output
+    pub fn set_model(&mut self, model: &Model) {
+        self.model = model.name.to_string();
+        let channels = Arc::make_mut(&mut self.channels);
+        channels.truncate(model.channels as usize);
+        while channels.len() < model.channels as usize {
+            channels.push(Channel::new(
+                channels.len() as u8 + 1,
+                self.default_voltage,
+                self.default_amperage,
+            ));
+        }
+    }
+}
+
+impl TheAppState {
+    // add a new supply with a default label
+    pub fn add_instrument(&mut self) {
+        self.next_instrument_id += 1;
+        let label = format!("Supply {}", self.next_instrument_id);
+        Arc::make_mut(&mut self.instruments).push(Instrument::new(
+            self.next_instrument_id,
+            &label,
+            &self.limits,
+            &self.settings,
+        ));
+    }
+
+    pub fn remove_instrument(&mut self, id: u32) {
+        Arc::make_mut(&mut self.instruments).retain(|instrument| instrument.id != id);
+    }
+
+    // keep the port and the setpoints of the first supply for the next start, and the limits
+    pub fn save_settings(&mut self) -> Result<(), String> {
+        let path = match &self.settings_path {
+            Some(path) => path.clone(),
+            None => {
+                return Err(
+                    "The settings are not saved, there is no config directory or the file could not be read"
+                        .to_string(),
+                )
+            }
+        };
+        if let Some(my_instrument) = self.instruments.first() {
+            if my_instrument.has_port() {
+                self.settings.remember_port(&my_instrument.current_port);
+            }
+            if let Some(my_channel) = my_instrument.channels.first() {
+                if let (Ok(voltage), Ok(amperage)) = (
+                    my_channel.current_voltage.trim().parse::<f64>(),
+                    my_channel.current_amperage.trim().parse::<f64>(),
+                ) {
+                    self.settings.voltage = voltage;
+                    self.settings.amperage = amperage;
+                }
+            }
+        }
+        // the soft limits of the first supply that got new ones in the GUI, the watchdog is
+        // set for the whole program and stays as it is
+        if let Some(my_instrument) = self.instruments.iter().find(|i| i.limits_applied) {
+            let watchdog_seconds = self.settings.limits.watchdog_seconds;
+            self.settings.limits = my_instrument.limits.clone();
+            self.settings.limits.watchdog_seconds = watchdog_seconds;
+        }
+        self.settings.save(&path)
+    }
+}
+
+// the text fields of the "advanced" port settings dialog
+#[derive(Clone, Data, Lens)]
+pub struct PortConfigForm {
+    pub baud_rate: String,
+    pub data_bits: String,
+    pub parity: String,
+    pub stop_bits: String,
+    pub flow_control: String,
+    pub timeout_ms: String,
+    pub message: String,
+}
+
+impl PortConfigForm {
+    pub fn from_config(port_config: &PortConfig) -> Self {
+        PortConfigForm {
+            baud_rate: port_config.get(BAUD_RATE_KEY),
+            data_bits: port_config.get(DATA_BITS_KEY),
+            parity: port_config.get(PARITY_KEY),
+            stop_bits: port_config.get(STOP_BITS_KEY),
+            flow_control: port_config.get(FLOW_CONTROL_KEY),
+            timeout_ms: port_config.get(TIMEOUT_KEY),
+            message: String::new(),
+        }
+    }
+
+    // check all fields, only a completely valid form gives a new config
+    pub fn to_config(&self) -> Result<PortConfig, String> {
+        let mut port_config = PortConfig::default();
+        port_config.set(BAUD_RATE_KEY, &self.baud_rate)?;
+        port_config.set(DATA_BITS_KEY, &self.data_bits)?;
+        port_config.set(PARITY_KEY, &self.parity)?;
+        port_config.set(STOP_BITS_KEY, &self.stop_bits)?;
+        port_config.set(FLOW_CONTROL_KEY, &self.flow_control)?;
+        port_config.set(TIMEOUT_KEY, &self.timeout_ms)?;
+        Ok(port_config)
+    }
+}
+
+// the text fields of the soft limits of a supply, the watchdog is set for the whole program
+#[derive(Clone, Data, Lens)]
+pub struct LimitsForm {
+    pub max_voltage: String,
+    pub max_amperage: String,
+    pub max_power: String,
+    pub max_on_time: String,
+}
+
+impl LimitsForm {
+    pub fn from_limits(limits: &SafetyLimits) -> Self {
+        LimitsForm {
+            max_voltage: limits.get(MAX_VOLTAGE_KEY),
+            max_amperage: limits.get(MAX_AMPERAGE_KEY),
+            max_power: limits.get(MAX_POWER_KEY),
+            max_on_time: limits.get(MAX_ON_TIME_KEY),
+        }
+    }
+
+    // check all fields, only a completely valid form gives new limits
+    pub fn to_limits(&self, limits: &SafetyLimits) -> Result<SafetyLimits, String> {
+        let mut new_limits = limits.clone();
+        new_limits.set(MAX_VOLTAGE_KEY, &self.max_voltage)?;
+        new_limits.set(MAX_AMPERAGE_KEY, &self.max_amperage)?;
+        new_limits.set(MAX_POWER_KEY, &self.max_power)?;
+        new_limits.set(MAX_ON_TIME_KEY, &self.max_on_time)?;
+        Ok(new_limits)
+    }
+}
+
+// the text fields of the battery charge box of a channel
+#[derive(Clone, Data, Lens)]
+pub struct ChargeForm {
+    pub chemistry: String,
+    pub charge_current: String,
+    pub float_voltage: String,
+    pub termination_current: String,
+    pub max_minutes: String,
+    pub max_milliamp_hours: String,
+}
+
+impl ChargeForm {
+    pub fn from_profile(profile: &ChargeProfile) -> Self {
+        ChargeForm {
+            chemistry: profile.chemistry.name().to_string(),
+            charge_current: format!("{:.3}", profile.charge_current),
+            float_voltage: format!("{:.2}", profile.float_voltage),
+            termination_current: format!("{:.3}", profile.termination_current),
+            max_minutes: format!("{}", profile.max_minutes),
+            max_milliamp_hours: format!("{}", profile.max_milliamp_hours),
+        }
+    }
+
+    // every field must be a number, the values are checked by ChargeProfile::validate()
+    pub fn to_profile(&self) -> Result<ChargeProfile, String> {
+        Ok(ChargeProfile {
+            chemistry: Chemistry::from_name(&self.chemistry)?,
+            charge_current: read_number(&self.charge_current, "charge current")?,
+            float_voltage: read_number(&self.float_voltage, "float voltage")?,
+            termination_current: read_number(&self.termination_current, "termination current")?,
+            max_minutes: read_number(&self.max_minutes, "maximum time")?,
+            max_milliamp_hours: read_number(&self.max_milliamp_hours, "maximum charge")?,
+        })
+    }
+}
+
+fn read_number(value: &str, name: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid {} '{}', expected a number", name, value.trim()))
+}
+
+// the I-V sweep window, for the supply with the id
+#[derive(Clone, Data, Lens)]
+pub struct SweepForm {
+    pub instrument_id: u32,
+    pub label: String,
+    pub sweep_current: bool, // step the current limit instead of the voltage
+    pub channel: String,
+    pub start: String,
+    pub stop: String,
+    pub step: String,
+    pub fixed: String,
+    pub settle_ms: String,
+    pub max_power: String,
+    pub csv_path: String,
+    pub points: Arc<Vec<SweepPoint>>,
+    pub swept: Option<SweepSettings>, // how the points were measured, for the CSV
+    pub running: bool, // the sweep runs on its own thread, the supply is left alone then
+    pub message: String,
+}
+
+impl SweepForm {
+    pub fn new() -> Self {
+        SweepForm {
+            instrument_id: 0,
+            label: String::new(),
+            sweep_current: false,
+            channel: "1".to_string(),
+            start: "0.00".to_string(),
+            stop: "5.00".to_string(),
+            step: "0.10".to_string(),
+            fixed: "0.100".to_string(),
+            settle_ms: "200".to_string(),
+            max_power: "1.0".to_string(),
+            csv_path: "iv_curve.csv".to_string(),
+            points: Arc::new(Vec::new()),
+            swept: None,
+            running: false,
+            message: String::new(),
+        }
+    }
+
+    // every field must be a number, the values are checked by SweepSettings::validate()
+    pub fn to_settings(&self) -> Result<SweepSettings, String> {
+        Ok(SweepSettings {
+            mode: if self.sweep_current {
+                SweepMode::Current
+            } else {
+                SweepMode::Voltage
+            },
+            channel: self
+                .channel
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid channel '{}'", self.channel.trim()))?,
+            start: read_number(&self.start, "start")?,
+            stop: read_number(&self.stop, "stop")?,
+            step: read_number(&self.step, "step")?,
+            fixed: read_number(&self.fixed, "fixed value")?,
+            settle_ms: self
+                .settle_ms
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid settling time '{}'", self.settle_ms.trim()))?,
+            max_power: read_number(&self.max_power, "power limit")?,
+        })
+    }
+}
+
+// the raw command console of one supply
+#[derive(Clone, Data, Lens)]
+pub struct ConsoleForm {
+    pub instrument_id: u32,
+    pub label: String,
+    pub command: String,
+    pub history: Arc<Vec<String>>, // the sent commands, oldest first
+    pub history_index: usize,      // where "Previous"/"Next" are, history.len() is the empty line
+    pub log: String,               // what was sent and received
+}
+
+impl ConsoleForm {
+    pub fn new() -> Self {
+        ConsoleForm {
+            instrument_id: 0,
+            label: String::new(),
+            command: String::new(),
+            history: Arc::new(Vec::new()),
+            history_index: 0,
+            log: String::new(),
+        }
+    }
+
+    // remember a sent command, a repeated one only once
+    pub fn remember(&mut self, command: &str) {
+        let history = Arc::make_mut(&mut self.history);
+        if history.last().map(String::as_str) != Some(command) {
+            history.push(command.to_string());
+        }
+        self.history_index = history.len();
+    }
+
+    // step through the history, -1 is back, 1 is forward
+    pub fn browse(&mut self, step: i32) {
+        let index = (self.history_index as i32 + step).clamp(0, self.history.len() as i32) as usize;
+        self.history_index = index;
+        self.command = self.history.get(index).cloned().unwrap_or_default();
+    }
+}
+
+// which lines the log view shows
+#[derive(Clone, Copy, Data, Debug, PartialEq)]
+pub enum LogFilter {
+    All,     // with debug and the traffic
+    Info,    // info, warnings and errors
+    Errors,  // warnings and errors
+    Traffic, // only the commands and answers
+}
+
+// one line of the log view
+#[derive(Clone, Data, Lens)]
+pub struct LogEntry {
+    pub time: String,
+    pub level: String,
+    pub text: String,
+}
+
+// the fields of the profiles dialog, the profiles themselves are in the settings
+#[derive(Clone, Data, Lens)]
+pub struct ProfileForm {
+    pub instrument_id: u32, // the supply "Apply" sets
+    pub label: String,
+    pub channel: String,
+    pub slot: String,            // the memory M1-M5 "Store in M" writes to
+    pub names: Arc<Vec<String>>, // of all profiles, for the list
+    pub name: String,
+    pub voltage: String,
+    pub amperage: String,
+    pub limits: LimitsForm,
+    pub notes: String,
+    pub file_path: String, // for import and export
+    pub message: String,
+}
+
+impl ProfileForm {
+    pub fn new() -> Self {
+        ProfileForm {
+            instrument_id: 0,
+            label: String::new(),
+            channel: "1".to_string(),
+            slot: "1".to_string(),
+            names: Arc::new(Vec::new()),
+            name: String::new(),
+            voltage: String::new(),
+            amperage: String::new(),
+            limits: LimitsForm::from_limits(&SafetyLimits::default()),
+            notes: String::new(),
+            file_path: "profiles.conf".to_string(),
+            message: String::new(),
+        }
+    }
+
+    // fill the fields with a profile, to apply or change it
+    pub fn show(&mut self, profile: &Profile) {
+        self.name = profile.name.clone();
+        self.voltage = profile.voltage.to_string();
+        self.amperage = profile.amperage.to_string();
+        self.limits = LimitsForm::from_limits(&profile.limits);
+        self.notes = profile.notes.clone();
+    }
+
+    // check all fields, only a completely valid form gives a profile
+    pub fn to_profile(&self) -> Result<Profile, String> {
+        let mut profile = Profile::new(&self.name);
+        profile.set(VOLTAGE_KEY, &self.voltage)?;
+        profile.set(AMPERAGE_KEY, &self.amperage)?;
+        profile.limits = self.limits.to_limits(&SafetyLimits::default())?;
+        for line in self.notes.lines().filter(|line| !line.trim().is_empty()) {
+            profile.set(NOTES_KEY, line)?;
+        }
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn refresh_names(&mut self, settings: &Settings) {
+        self.names = Arc::new(
+            settings
+                .profiles
+                .iter()
+                .map(|profile| profile.name.clone())
+                .collect(),
+        );
+    }
 }
@@ -0,0 +1,260 @@
+use core::time::Duration;
+
+use serde::Serialize;
+
+use crate::limits::{LimitGuard, SafetyLimits};
+use crate::model::*;
+use crate::port_config::PortConfig;
+use crate::protocol::*;
+use crate::serial::*;
+use crate::stats::MeasurementStatistics;
+use crate::status::Status;
+
+// one reading of the actual output
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Measurement {
+    pub voltage: f64,
+    pub amperage: f64,
+    pub power: f64,
+}
+
+impl Measurement {
+    pub fn new(voltage: f64, amperage: f64) -> Self {
+        Measurement {
+            voltage,
+            amperage,
+            power: voltage * amperage,
+        }
+    }
+}
+
+// a supply on a port, with typed results instead of text output; used by the servers
+pub struct Supply {
+    pub current_port: String,
+    pub port_config: PortConfig,
+    pub model: &'static Model,
+    // what was last switched through this driver, None until then
+    pub output_wanted: Option<bool>,
+    pub ovp_on: Option<bool>,
+    pub ocp_on: Option<bool>,
+    // soft limits of the user, checked before a setting is sent
+    pub limits: SafetyLimits,
+}
+
+impl Supply {
+    // ask the supply who it is, so the right limits are used
+    pub fn open(current_port: &str, port_config: &PortConfig) -> Result<Supply, String> {
+        let mut supply = Supply::with_model(current_port, port_config, &MODELS[0]);
+        let id_answer = supply.id()?;
+        supply.model = identify(&id_answer).unwrap_or(&MODELS[0]);
+        Ok(supply)
+    }
+
+    // a supply whose model is already known, nothing is sent
+    pub fn with_model(
+        current_port: &str,
+        port_config: &PortConfig,
+        model: &'static Model,
+    ) -> Supply {
+        Supply {
+            current_port: current_port.to_string(),
+            port_config: port_config.clone(),
+            model,
+            output_wanted: None,
+            ovp_on: None,
+            ocp_on: None,
+            limits: SafetyLimits::default(),
+        }
+    }
+
+    // send a command and give the raw answer
+    fn send(&self, command: &str) -> Result<Vec<u8>, String> {
+        send_command(&self.current_port, &self.port_config, command)
+    }
+
+    pub fn id(&self) -> Result<String, String> {
+        parse_text(&self.send(ID_COMMAND)?, ID_COMMAND)
+    }
+
+    pub fn status(&self) -> Result<Status, String> {
+        parse_status(&self.send(STATUS_COMMAND)?)
+    }
+
+    pub fn voltage_setting(&self, channel: u8) -> Result<f64, String> {
+        let command = voltage_setting_command(self.model, channel)?;
+        parse_number(&self.send(&command)?, &command)
+    }
+
+    pub fn amperage_setting(&self, channel: u8) -> Result<f64, String> {
+        let command = amperage_setting_command(self.model, channel)?;
+        parse_number(&self.send(&command)?, &command)
+    }
+
+    pub fn set_voltage(&self, channel: u8, voltage: f64) -> Result<(), String> {
+        self.limits.check_voltage(voltage)?;
+        self.send(&set_voltage_command(self.model, channel, voltage)?)?;
+        Ok(())
+    }
+
+    pub fn set_amperage(&self, channel: u8, amperage: f64) -> Result<(), String> {
+        self.limits.check_amperage(amperage)?;
+        self.send(&set_amperage_command(self.model, channel, amperage)?)?;
+        Ok(())
+    }
+
+    // take the settings of a memory M1-M5
+    pub fn recall(&self, slot: u8) -> Result<(), String> {
+        self.send(&recall_command(slot)?)?;
+        Ok(())
+    }
+
+    // store the settings in a memory M1-M5
+    pub fn save(&self, slot: u8) -> Result<(), String> {
+        self.send(&save_command(slot)?)?;
+        Ok(())
+    }
+
+    pub fn measure(&self, channel: u8) -> Result<Measurement, String> {
+        let (voltage_command, amperage_command) = measure_commands(self.model, channel)?;
+        let voltage = parse_number(&self.send(&voltage_command)?, &voltage_command)?;
+        let amperage = parse_number(&self.send(&amperage_command)?, &amperage_command)?;
+        Ok(Measurement::new(voltage, amperage))
+    }
+
+    // read the output `samples` times with `interval` in between, e.g. for the idle current
+    pub fn measure_statistics(
+        &self,
+        channel: u8,
+        samples: usize,
+        interval: Duration,
+    ) -> Result<MeasurementStatistics, String> {
+        let mut statistics = MeasurementStatistics::new(0);
+        for sample in 0..samples {
+            if sample > 0 {
+                std::thread::sleep(interval);
+            }
+            statistics.add(self.measure(channel)?);
+        }
+        Ok(statistics)
+    }
+
+    // the output only goes on when the settings of every channel are inside the soft limits
+    pub fn set_output(&mut self, turn_on: bool) -> Result<(), String> {
+        if turn_on {
+            self.check_settings()?;
+        }
+        self.send(output_command(turn_on))?;
+        self.output_wanted = Some(turn_on);
+        Ok(())
+    }
+
+    // send OFF and read the status back, an error if the output is still on
+    pub fn switch_off(&mut self) -> Result<(), String> {
+        self.set_output(false)?;
+        if self.status()?.output_on() {
+            return Err("the output is still ON".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn set_ovp(&mut self, turn_on: bool) -> Result<(), String> {
+        self.send(ovp_command(self.model, turn_on)?)?;
+        self.ovp_on = Some(turn_on);
+        Ok(())
+    }
+
+    pub fn set_ocp(&mut self, turn_on: bool) -> Result<(), String> {
+        self.send(ocp_command(self.model, turn_on)?)?;
+        self.ocp_on = Some(turn_on);
+        Ok(())
+    }
+
+    // the status byte has no protection bit, but an output that went off by itself has tripped
+    pub fn protection_tripped(&self, status: &Status) -> bool {
+        self.output_wanted == Some(true) && !status.output_on()
+    }
+
+    // the settings of every channel must be inside the soft limits, e.g. before the output goes
+    // on after they were changed at the front panel
+    pub fn check_settings(&self) -> Result<(), String> {
+        if self.limits.max_voltage <= 0.0 && self.limits.max_amperage <= 0.0 {
+            return Ok(());
+        }
+        for channel in 1..=self.model.channels {
+            self.limits.check_voltage(self.voltage_setting(channel)?)?;
+            self.limits
+                .check_amperage(self.amperage_setting(channel)?)?;
+        }
+        Ok(())
+    }
+
+    // switch the output off if it runs above the power limit or longer than allowed,
+    // gives the reason if it did; an output that does not go off is an error, the next check
+    // tries again because the reason is still there
+    pub fn enforce_limits(&mut self, guard: &mut LimitGuard) -> Result<Option<String>, String> {
+        if !self.limits.watch_output() {
+            return Ok(None);
+        }
+        let status = self.status()?;
+        let mut measurements = Vec::new();
+        if status.output_on() && self.limits.max_power > 0.0 {
+            for channel in 1..=self.model.channels {
+                measurements.push(self.measure(channel)?);
+            }
+        }
+        let limits = self.limits.clone();
+        match guard.check(&limits, status.output_on(), &measurements) {
+            Some(reason) => match self.switch_off() {
+                Ok(()) => Ok(Some(reason)),
+                Err(e) => Err(format!("{}, but switching OFF failed: {}", reason, e)),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_above_the_limits_are_found() {
+        let mut supply =
+            Supply::open("SIMULATED:KA3305P check settings", &PortConfig::default()).unwrap();
+        assert_eq!(supply.model.name, "KA3305P");
+        supply.set_voltage(1, 3.3).unwrap();
+        supply.set_voltage(2, 3.3).unwrap();
+        supply.set_amperage(1, 0.1).unwrap();
+        supply.set_amperage(2, 0.1).unwrap();
+        supply.limits.max_voltage = 5.0;
+        assert!(supply.check_settings().is_ok());
+        supply.limits.max_voltage = 0.0;
+        supply.set_voltage(2, 12.0).unwrap();
+        supply.limits.max_voltage = 5.0;
+        assert!(supply.check_settings().is_err()); // channel 2
+                                                   // and the output stays off
+        assert!(supply.set_output(true).is_err());
+        assert!(!supply.status().unwrap().output_on());
+        assert!(supply.set_voltage(1, 6.0).is_err());
+        assert!(supply.set_voltage(3, 1.0).is_err()); // no channel 3
+        supply.limits.max_voltage = 12.0;
+        supply.set_output(true).unwrap();
+        assert!(supply.status().unwrap().output_on());
+    }
+
+    #[test]
+    fn the_power_limit_switches_off() {
+        let mut supply =
+            Supply::open("SIMULATED:KD3005P power limit", &PortConfig::default()).unwrap();
+        let mut guard = LimitGuard::new();
+        supply.set_voltage(1, 5.0).unwrap();
+        supply.set_amperage(1, 1.0).unwrap();
+        supply.set_output(true).unwrap(); // 2.5 W into the load
+        supply.limits.max_power = 3.0;
+        assert_eq!(supply.enforce_limits(&mut guard).unwrap(), None);
+        supply.limits.max_power = 2.0;
+        assert!(supply.enforce_limits(&mut guard).unwrap().is_some());
+        assert!(!supply.status().unwrap().output_on());
+        assert_eq!(supply.enforce_limits(&mut guard).unwrap(), None);
+    }
+}
@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+use crate::driver::Measurement;
+
+// a gap of more than 3 poll intervals between two readings is not counted, e.g. when the
+// live readout was off; a reading that is only a bit late is still counted
+const GAP_POLL_INTERVALS: f64 = 3.0;
+const DEFAULT_MAX_GAP_SECONDS: f64 = 10.0; // until the poll interval is known
+
+// running totals of energy (Wh) and charge (mAh) from the measured power and current
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnergyCounter {
+    pub watt_hours: f64,
+    pub milliamp_hours: f64,
+    pub seconds: f64, // the time that was counted
+    pub paused: bool,
+    max_gap_seconds: f64,
+    last_reading: Option<(Instant, Measurement)>,
+}
+
+impl Default for EnergyCounter {
+    fn default() -> Self {
+        EnergyCounter {
+            watt_hours: 0.0,
+            milliamp_hours: 0.0,
+            seconds: 0.0,
+            paused: false,
+            max_gap_seconds: DEFAULT_MAX_GAP_SECONDS,
+            last_reading: None,
+        }
+    }
+}
+
+// the totals change with every reading, so the GUI can compare them directly
+#[cfg(feature = "gui")]
+impl Data for EnergyCounter {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl EnergyCounter {
+    pub fn new() -> Self {
+        EnergyCounter::default()
+    }
+
+    // the readings come this often, a longer gap than a few of them is not counted
+    pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.max_gap_seconds = poll_interval.as_secs_f64() * GAP_POLL_INTERVALS;
+    }
+
+    // count the time since the last reading with the mean of both readings (trapezoid rule)
+    pub fn add(&mut self, measurement: Measurement, time: Instant) {
+        if let Some((last_time, last_measurement)) = self.last_reading {
+            let seconds = time.duration_since(last_time).as_secs_f64();
+            if !self.paused && seconds <= self.max_gap_seconds {
+                let power = (last_measurement.power + measurement.power) / 2.0;
+                let amperage = (last_measurement.amperage + measurement.amperage) / 2.0;
+                self.watt_hours += power * seconds / 3600.0;
+                self.milliamp_hours += amperage * seconds / 3.6; // A * s = 1000 mA * h / 3600
+                self.seconds += seconds;
+            }
+        }
+        self.last_reading = Some((time, measurement));
+    }
+
+    // forget the last reading, so the time until the next one is not counted
+    pub fn interrupt(&mut self) {
+        self.last_reading = None;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.interrupt();
+    }
+
+    // start again from zero, paused or not stays as it is
+    pub fn reset(&mut self) {
+        self.watt_hours = 0.0;
+        self.milliamp_hours = 0.0;
+        self.seconds = 0.0;
+        self.interrupt();
+    }
+
+    // e.g. "1.2345 Wh / 246.80 mAh in 01:02:03"
+    pub fn describe(&self) -> String {
+        let seconds = self.seconds as u64;
+        format!(
+            "{:.4} Wh / {:.2} mAh in {:02}:{:02}:{:02}{}",
+            self.watt_hours,
+            self.milliamp_hours,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            if self.paused { " (paused)" } else { "" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn energy_is_the_mean_of_two_readings() {
+        let mut counter = EnergyCounter::new();
+        let start = Instant::now();
+        counter.add(Measurement::new(10.0, 1.0), start);
+        counter.add(Measurement::new(10.0, 3.0), start + Duration::from_secs(9));
+        // 20 W for 9 s and 2 A for 9 s
+        assert!((counter.watt_hours - 20.0 * 9.0 / 3600.0).abs() < 1e-9);
+        assert!((counter.milliamp_hours - 2000.0 * 9.0 / 3600.0).abs() < 1e-9);
+        assert_eq!(counter.seconds, 9.0);
+        assert_eq!(counter.describe(), "0.0500 Wh / 5.00 mAh in 00:00:09");
+    }
+
+    #[test]
+    fn a_long_gap_and_a_pause_are_not_counted() {
+        let mut counter = EnergyCounter::new();
+        let start = Instant::now();
+        counter.add(Measurement::new(10.0, 1.0), start);
+        counter.add(Measurement::new(10.0, 1.0), start + Duration::from_secs(60));
+        assert_eq!(counter.seconds, 0.0);
+        counter.set_paused(true);
+        counter.add(Measurement::new(10.0, 1.0), start + Duration::from_secs(61));
+        counter.add(Measurement::new(10.0, 1.0), start + Duration::from_secs(62));
+        assert_eq!(counter.watt_hours, 0.0);
+        assert!(counter.describe().ends_with("(paused)"));
+        // the first reading after the pause only starts the counting again
+        counter.set_paused(false);
+        counter.add(Measurement::new(10.0, 1.0), start + Duration::from_secs(70));
+        counter.add(Measurement::new(10.0, 1.0), start + Duration::from_secs(71));
+        assert_eq!(counter.seconds, 1.0);
+        counter.reset();
+        assert_eq!(counter, EnergyCounter::new());
+    }
+
+    #[test]
+    fn the_gap_follows_the_poll_interval() {
+        let mut counter = EnergyCounter::new();
+        counter.set_poll_interval(Duration::from_secs(60));
+        let start = Instant::now();
+        counter.add(Measurement::new(10.0, 1.0), start);
+        counter.add(Measurement::new(10.0, 1.0), start + Duration::from_secs(70));
+        assert_eq!(counter.seconds, 70.0);
+        counter.add(
+            Measurement::new(10.0, 1.0),
+            start + Duration::from_secs(300),
+        );
+        assert_eq!(counter.seconds, 70.0); // more than 3 intervals
+        counter.set_poll_interval(Duration::from_millis(100));
+        counter.add(
+            Measurement::new(10.0, 1.0),
+            start + Duration::from_secs(301),
+        );
+        assert_eq!(counter.seconds, 70.0);
+    }
+}
@@ -1,110 +1,481 @@
-use druid::widget::{Button, Container, Flex, Label, Scroll, TextBox};
-use druid::{Color, FontDescriptor, FontFamily, Widget, WidgetExt};
+use druid::widget::{
+    Button, Checkbox, Container, Controller, EnvScope, Flex, Label, List, Radio, Scroll, TextBox,
+};
+use druid::{
+    commands, lens, theme, AppDelegate, Application, Color, Data, DelegateCtx, Env, Event,
+    EventCtx, ExtEventSink, FontDescriptor, FontFamily, Lens, LensExt, Selector, Target, Widget,
+    WidgetExt, WindowDesc, WindowId,
+};
+use std::sync::Arc;
+use tracing::{info, warn};
 
+use crate::port_config::PortConfig;
 use crate::serial::*;
 
+use crate::charger::*;
 use crate::data::*;
+use crate::driver::Supply;
+use crate::limits::Watchdog;
+use crate::logging::{clear_log, log_text, LogController};
+use crate::memories::{read_memories, write_memories};
+use crate::model::identify;
+use crate::monitor::MonitorController;
+use crate::plot::CurvePlot;
+use crate::profiles::*;
+use crate::protocol::decode_answer;
+use crate::rules::RuleSet;
+use crate::sequence::*;
+use crate::simulator::SIMULATED_PORT;
+use crate::stats::MeasurementStatistics;
+use crate::sweep::*;
 
-pub fn ui_builder() -> impl Widget<TheAppState> {
+static ADVANCED_WIDTH: f64 = 320.0;
+static ADVANCED_HEIGHT: f64 = 330.0;
+static INFO_HEIGHT: f64 = 120.0;
+static LOG_HEIGHT: f64 = 150.0;
+static SWEEP_WIDTH: f64 = 520.0;
+static SWEEP_HEIGHT: f64 = 700.0;
+static CONSOLE_WIDTH: f64 = 560.0;
+static CONSOLE_HEIGHT: f64 = 420.0;
+static CONSOLE_LOG_HEIGHT: f64 = 260.0;
+static PROFILES_WIDTH: f64 = 560.0;
+static PROFILES_HEIGHT: f64 = 720.0;
+static PROFILES_LIST_HEIGHT: f64 = 140.0;
+
+// the console keeps only this many lines of its log
+const CONSOLE_LINES: usize = 500;
+
+// sent by an instrument panel to remove its instrument, carries the instrument id
+const REMOVE_INSTRUMENT: Selector<u32> = Selector::new("kd3005p.remove-instrument");
+
+// sent by an instrument panel to open the I-V sweep window for its instrument
+const OPEN_SWEEP: Selector<u32> = Selector::new("kd3005p.open-sweep");
+
+// sent by an instrument panel to open the raw command console for its instrument
+const OPEN_CONSOLE: Selector<u32> = Selector::new("kd3005p.open-console");
+
+// sent by an instrument panel to open the profiles dialog for its instrument
+const OPEN_PROFILES: Selector<u32> = Selector::new("kd3005p.open-profiles");
+
+// sent by the sweep thread for every measured point and once at the end
+const SWEEP_POINT: Selector<SweepPoint> = Selector::new("kd3005p.sweep-point");
+const SWEEP_DONE: Selector<SweepResult> = Selector::new("kd3005p.sweep-done");
+
+// sent by the sequence thread at the end, carries what happened
+const SEQUENCE_DONE: Selector<String> = Selector::new("kd3005p.sequence-done");
+
+// the points or the error of a sweep, and what happened on the way
+type SweepResult = (Result<Vec<SweepPoint>, String>, String);
+
+// sent by a profile in the list of the profiles dialog, carries the profile name
+const SELECT_PROFILE: Selector<String> = Selector::new("kd3005p.select-profile");
+
+fn instrument_panel() -> impl Widget<InstrumentData> {
     // define the port selection colum:
-    let current_port_text = TextBox::new().lens(TheAppState::current_port).padding(5.0); // text field that shows current port
+    let current_port_text = TextBox::new()
+        .lens(instrument_lens().then(Instrument::current_port))
+        .padding(5.0); // text field that shows current port
     let mut select_col = Flex::column() // column to hold all the fields
         .with_child(current_port_text); // add the text field
 
     let initial_serial_vector = list_serial_ports(); // get all serial ports
     for i in initial_serial_vector {
         // add a button for each found port
-        let button = Button::new(format!("{}", i.port_name))
-            .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-                my_app_state.current_port = i.port_name.to_string();
-                println!("{}", &my_app_state.current_port);
+        let button = Button::new(i.port_name.to_string())
+            .on_click(move |_ctx, (_, my_instrument): &mut InstrumentData, _env| {
+                my_instrument.current_port = i.port_name.to_string();
+                info!("Port {} selected", &my_instrument.current_port);
             })
             .padding(5.0);
         select_col.add_child(button);
     }
+    // and one for the simulated supply, to try things without hardware
+    let simulated_button = Button::new(SIMULATED_PORT.to_string())
+        .on_click(move |_ctx, (_, my_instrument): &mut InstrumentData, _env| {
+            my_instrument.current_port = SIMULATED_PORT.to_string();
+        })
+        .padding(5.0);
+    select_col.add_child(simulated_button);
 
     // define button to fetch ID information
-    let id_button = Button::new("KD3005P ID".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            get_id(&my_app_state.current_port, &mut my_output);
-            my_app_state.output_info.clear();
-            my_app_state.output_info.push_str(&my_output);
-        })
+    let id_button = Button::new("Supply ID".to_string())
+        .on_click(
+            move |_ctx, (port_config, my_instrument): &mut InstrumentData, _env| {
+                let mut my_output = String::new();
+                let answer_string = match get_id(&my_instrument.current_port, port_config) {
+                    Ok(answer_string) => answer_string,
+                    Err(e) => {
+                        show_result(my_instrument, Err(format!("{}! \n", e)));
+                        return;
+                    }
+                };
+                // pick the model from the answer, so the right limits are used
+                if !answer_string.is_empty() {
+                    my_output.push_str(&format!("{} \n", answer_string.trim()));
+                    match identify(&answer_string) {
+                        Some(model) => {
+                            my_instrument.set_model(model);
+                            my_output.push_str(&format!("Detected {}", model.describe()));
+                        }
+                        None => my_output.push_str(&format!(
+                            "Unknown model, using the {} limits! \n",
+                            my_instrument.model
+                        )),
+                    }
+                }
+                show_result(my_instrument, Ok(my_output));
+            },
+        )
         .padding(5.0); //button
 
     // define button to fetch status information
-    let status_button = Button::new("KD3005P status".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            get_status(&my_app_state.current_port, &mut my_output);
-            my_app_state.output_info.clear();
-            my_app_state.output_info.push_str(&my_output);
+    let status_button = Button::new("Supply status".to_string())
+        .on_click(
+            move |_ctx, (port_config, my_instrument): &mut InstrumentData, _env| {
+                let result = match get_status(&my_instrument.current_port, port_config) {
+                    Ok(status) => Ok(status.describe(my_instrument.model_info().channels)),
+                    Err(e) => Err(format!("{}! \n", e)),
+                };
+                show_result(my_instrument, result);
+            },
+        )
+        .padding(5.0); //button
+
+    let on_button = Button::new("Output ON".to_string())
+        .on_click(
+            move |_ctx, (port_config, my_instrument): &mut InstrumentData, _env| {
+                let result = switch_on(my_instrument, port_config);
+                show_result(my_instrument, result);
+            },
+        )
+        .padding(5.0); //button
+
+    let off_button = Button::new("Output OFF".to_string())
+        .on_click(
+            move |_ctx, (port_config, my_instrument): &mut InstrumentData, _env| {
+                let result = switch_output(&my_instrument.current_port, port_config, OFF_COMMAND);
+                show_result(my_instrument, result);
+            },
+        )
+        .padding(5.0); //button
+
+    // define button to open the I-V sweep window for this supply
+    let sweep_button = Button::new("I-V sweep...".to_string())
+        .on_click(move |ctx, (_, my_instrument): &mut InstrumentData, _env| {
+            // the sweep window works on the app state, so ask the main window to open it
+            ctx.submit_command(OPEN_SWEEP.with(my_instrument.id));
+        })
+        .padding(5.0); //button
+
+    // define button to open the raw command console for this supply
+    let console_button = Button::new("Console...".to_string())
+        .on_click(move |ctx, (_, my_instrument): &mut InstrumentData, _env| {
+            ctx.submit_command(OPEN_CONSOLE.with(my_instrument.id));
+        })
+        .padding(5.0); //button
+
+    // define button to open the setpoint profiles of the host for this supply
+    let profiles_button = Button::new("Profiles...".to_string())
+        .on_click(move |ctx, (_, my_instrument): &mut InstrumentData, _env| {
+            ctx.submit_command(OPEN_PROFILES.with(my_instrument.id));
+        })
+        .padding(5.0); //button
+
+    let status_row = Flex::row() // define a row for the status buttons
+        .with_child(id_button)
+        .with_child(status_button)
+        .with_child(sweep_button)
+        .with_child(console_button)
+        .with_child(profiles_button);
+
+    // one column per channel, as many as the model has
+    let channel_row = List::new(channel_column).horizontal().lens(lens::Map::new(
+        |my_data: &InstrumentData| (my_data.clone(), my_data.1.channels.clone()),
+        |my_data: &mut InstrumentData, channel_data: (InstrumentData, Arc<Vec<Channel>>)| {
+            *my_data = channel_data.0;
+            my_data.1.channels = channel_data.1;
+        },
+    ));
+
+    // define the buttons for the protection, only sent if the model has it
+    let mut protection_row = Flex::row();
+    for (button_text, protection_command) in [
+        ("OVP ON", OVP_ON_COMMAND),
+        ("OVP OFF", OVP_OFF_COMMAND),
+        ("OCP ON", OCP_ON_COMMAND),
+        ("OCP OFF", OCP_OFF_COMMAND),
+    ] {
+        let protection_button = Button::new(button_text.to_string())
+            .on_click(
+                move |_ctx, (port_config, my_instrument): &mut InstrumentData, _env| {
+                    let result = if my_instrument.model_info().ovp_ocp {
+                        switch_output(&my_instrument.current_port, port_config, protection_command)
+                    } else {
+                        Err(format!("The {} has no OVP/OCP! \n", my_instrument.model))
+                    };
+                    show_result(my_instrument, result);
+                },
+            )
+            .padding(2.0); //button
+        protection_row.add_child(protection_button);
+    }
+
+    // define the buttons for the tracking of dual output models
+    let mut tracking_row = Flex::row();
+    for (button_text, tracking_command) in [
+        ("Independent", TRACK_INDEPENDENT_COMMAND),
+        ("Series", TRACK_SERIES_COMMAND),
+        ("Parallel", TRACK_PARALLEL_COMMAND),
+    ] {
+        let tracking_button = Button::new(button_text.to_string())
+            .on_click(
+                move |_ctx, (port_config, my_instrument): &mut InstrumentData, _env| {
+                    let result = if my_instrument.model_info().tracking {
+                        switch_output(&my_instrument.current_port, port_config, tracking_command)
+                    } else {
+                        Err(format!("The {} has no tracking! \n", my_instrument.model))
+                    };
+                    show_result(my_instrument, result);
+                },
+            )
+            .padding(2.0); //button
+        tracking_row.add_child(tracking_button);
+    }
+
+    let on_off_row = Flex::row() // define a row for the status buttons
+        .with_child(on_button)
+        .with_child(off_button);
+
+    // the soft limits of this supply, 0 means no limit
+    let limits_col = Flex::column()
+        .with_child(setting_row(
+            "Max. voltage [V]",
+            TextBox::new().lens(LimitsForm::max_voltage),
+        ))
+        .with_child(setting_row(
+            "Max. current [A]",
+            TextBox::new().lens(LimitsForm::max_amperage),
+        ))
+        .with_child(setting_row(
+            "Max. power [W]",
+            TextBox::new().lens(LimitsForm::max_power),
+        ))
+        .with_child(setting_row(
+            "Max. on-time [s]",
+            TextBox::new().lens(LimitsForm::max_on_time),
+        ))
+        .lens(instrument_lens().then(Instrument::limits_form));
+
+    // take over the limits, but only if all of them are valid
+    let apply_limits_button = Button::new("Apply limits".to_string())
+        .on_click(move |_ctx, (_, my_instrument): &mut InstrumentData, _env| {
+            let result = my_instrument
+                .limits_form
+                .to_limits(&my_instrument.limits)
+                .map(|limits| {
+                    my_instrument.limits = limits;
+                    my_instrument.limits_applied = true;
+                    "New soft limits applied! \n".to_string()
+                });
+            show_result(my_instrument, result);
+        })
+        .padding(5.0); //button
+
+    let limits_box = Flex::column()
+        .with_child(Label::new("Soft limits (0 = none)".to_string()).padding(5.0))
+        .with_child(limits_col)
+        .with_child(apply_limits_button);
+
+    // the alarm rules of this supply, one per line, checked on fast readings
+    let rules_text = TextBox::multiline()
+        .with_placeholder("current > 1.2 for 200 ms: off, notify")
+        .lens(instrument_lens().then(Instrument::rules_text))
+        .expand_width()
+        .padding(5.0); // text field
+    let apply_rules_button = Button::new("Apply rules".to_string())
+        .on_click(move |_ctx, (_, my_instrument): &mut InstrumentData, _env| {
+            let result = RuleSet::parse(&my_instrument.rules_text).map(|rules| {
+                let my_output = format!("{} rules applied! \n", rules.rules.len());
+                my_instrument.rules = rules;
+                my_output
+            });
+            show_result(my_instrument, result);
+        })
+        .padding(5.0); //button
+    let watch_rules_checkbox = Checkbox::new("Watch rules")
+        .lens(instrument_lens().then(Instrument::watch_rules))
+        .padding(5.0);
+    let rules_box = Flex::column()
+        .with_child(Label::new("Alarm rules".to_string()).padding(5.0))
+        .with_child(rules_text)
+        .with_child(
+            Flex::row()
+                .with_child(apply_rules_button)
+                .with_child(watch_rules_checkbox),
+        );
+
+    // define the container column for ALL settings
+    let settings_col = Flex::column()
+        .with_child(status_row)
+        .with_child(channel_row)
+        .with_child(on_off_row)
+        .with_child(protection_row)
+        .with_child(tracking_row)
+        .with_child(
+            Container::new(limits_box)
+                .border(Color::grey8(0x55), 2.0)
+                .padding(2.0),
+        )
+        .with_child(
+            Container::new(rules_box)
+                .border(Color::grey8(0x55), 2.0)
+                .padding(2.0),
+        )
+        .padding(5.0);
+
+    let info_label = Label::raw() // label
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE)) // font for label
+        .lens(instrument_lens().then(Instrument::output_info))
+        .padding(5.0);
+
+    // the row containing all the serial stuff
+    let mut serial_row = Flex::row();
+    // add it all to the main container row, with borders and padding
+
+    serial_row.add_child(
+        Container::new(select_col)
+            .border(Color::grey8(0x55), 2.0)
+            .padding(5.0),
+    );
+    serial_row.add_child(
+        Container::new(settings_col)
+            .border(Color::grey8(0x55), 2.0)
+            .padding(5.0),
+    );
+
+    // the head line of the panel: user defined label, live readouts and remove button
+    let label_text = TextBox::new()
+        .with_placeholder("Label, e.g. Rail 5V")
+        .lens(instrument_lens().then(Instrument::label))
+        .padding(5.0); // text field
+    let live_checkbox = Checkbox::new("Live")
+        .lens(instrument_lens().then(Instrument::live))
+        .padding(5.0);
+    let model_label =
+        Label::dynamic(|(_, my_instrument): &InstrumentData, _env| my_instrument.model.to_string())
+            .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+            .padding(5.0);
+    let remove_button = Button::new("Remove".to_string())
+        .on_click(move |ctx, (_, my_instrument): &mut InstrumentData, _env| {
+            // the list is owned by the app state, so ask the main window to do it
+            ctx.submit_command(REMOVE_INSTRUMENT.with(my_instrument.id));
         })
         .padding(5.0); //button
 
+    let head_row = Flex::row()
+        .with_child(label_text)
+        .with_child(live_checkbox)
+        .with_child(model_label)
+        .with_child(remove_button);
+
+    let info_scroll = Scroll::new(Container::new(info_label))
+        .vertical()
+        .fix_height(INFO_HEIGHT);
+    Container::new(
+        Flex::column()
+            .with_child(head_row)
+            .with_child(serial_row)
+            .with_child(info_scroll),
+    )
+    .border(Color::grey8(0x88), 2.0)
+    .padding(5.0)
+}
+
+fn channel_column() -> impl Widget<ChannelData> {
     // define the box for voltage setting
     let voltage_label = Label::new("Voltage in [V]".to_string()).padding(5.0); // label
     let voltage_text = TextBox::new()
-        .lens(TheAppState::current_voltage)
+        .lens(channel_lens().then(Channel::current_voltage))
         .padding(5.0); // text field
 
     let set_voltage_button = Button::new("Set Voltage".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            let mut answer_string = String::new();
-            get_set_amperage_voltage(
-                &my_app_state.current_port,
-                VSET_COMMAND,
-                &mut answer_string,
-                &my_app_state.current_voltage,
-                &mut my_output,
-            );
-            my_app_state.output_info.clear();
-            my_app_state.output_info.push_str(&my_output);
-        })
+        .on_click(
+            move |_ctx, ((port_config, my_instrument), my_channel): &mut ChannelData, _env| {
+                // do not send anything the supply can not do
+                if let Err(e) = parse_form_number(&my_channel.current_voltage).and_then(|voltage| {
+                    my_instrument.model_info().check_voltage(voltage)?;
+                    my_instrument.limits.check_voltage(voltage)
+                }) {
+                    show_result(my_instrument, Err(e));
+                    return;
+                }
+                let result = match get_set_amperage_voltage(
+                    &my_instrument.current_port,
+                    port_config,
+                    my_channel.number,
+                    VSET_COMMAND,
+                    &my_channel.current_voltage,
+                ) {
+                    Ok(_) => Ok(format!(
+                        "Voltage of channel {} set to {} V! \n",
+                        my_channel.number, my_channel.current_voltage
+                    )),
+                    Err(e) => Err(format!("{}! \n", e)),
+                };
+                show_result(my_instrument, result);
+            },
+        )
         .padding(5.0); //button
 
     let get_voltage_button = Button::new("Get Voltage".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            let mut answer_string = String::new();
-            let dummy_value = "".to_string();
-            get_set_amperage_voltage(
-                &my_app_state.current_port,
-                VGET_COMMAND,
-                &mut answer_string,
-                &dummy_value,
-                &mut my_output,
-            );
-            if !answer_string.is_empty() {
-                my_app_state.current_voltage = answer_string;
-            }
-            my_app_state.output_info.clear();
-            my_app_state.output_info.push_str(&my_output);
-        })
+        .on_click(
+            move |_ctx, ((port_config, my_instrument), my_channel): &mut ChannelData, _env| {
+                let result = match get_set_amperage_voltage(
+                    &my_instrument.current_port,
+                    port_config,
+                    my_channel.number,
+                    VGET_COMMAND,
+                    "",
+                ) {
+                    Ok(answer_string) if !answer_string.is_empty() => {
+                        let my_output = format!(
+                            "The voltage setting of channel {} is {} V! \n",
+                            my_channel.number, answer_string
+                        );
+                        my_channel.current_voltage = answer_string;
+                        Ok(my_output)
+                    }
+                    Ok(_) => Err("No answer from the supply! \n".to_string()),
+                    Err(e) => Err(format!("{}! \n", e)),
+                };
+                show_result(my_instrument, result);
+            },
+        )
         .padding(5.0); //button
 
     let get_actual_voltage_button = Button::new("Actual Voltage".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            let mut answer_string = String::new();
-            let dummy_value = "".to_string();
-            get_set_amperage_voltage(
-                &my_app_state.current_port,
-                VOUT_COMMAND,
-                &mut answer_string,
-                &dummy_value,
-                &mut my_output,
-            );
-            my_app_state.output_info.clear();
-            if !answer_string.is_empty() {
-                my_app_state
-                    .output_info
-                    .push_str(&(format!("The actual output voltage is {} V!", answer_string)));
-            }
-            my_app_state.output_info.push_str(&my_output);
-        })
+        .on_click(
+            move |_ctx, ((port_config, my_instrument), my_channel): &mut ChannelData, _env| {
+                let answer = get_set_amperage_voltage(
+                    &my_instrument.current_port,
+                    port_config,
+                    my_channel.number,
+                    VOUT_COMMAND,
+                    "",
+                );
+                let result = match answer {
+                    Ok(answer_string) if !answer_string.is_empty() => {
+                        let my_output =
+                            format!("The actual output voltage is {} V! \n", answer_string);
+                        my_channel.measured_voltage = answer_string;
+                        Ok(my_output)
+                    }
+                    Ok(_) => Err("No answer from the supply! \n".to_string()),
+                    Err(e) => Err(format!("{}! \n", e)),
+                };
+                show_result(my_instrument, result);
+            },
+        )
         .padding(5.0); //button
 
     let voltage_col = Flex::column() //create a column for it and add it all
@@ -117,94 +488,88 @@ pub fn ui_builder() -> impl Widget<TheAppState> {
     // define the box for current setting
     let amperage_label = Label::new("Amperage in [A]".to_string()).padding(5.0); // label
     let amperage_text = TextBox::new()
-        .lens(TheAppState::current_amperage)
+        .lens(channel_lens().then(Channel::current_amperage))
         .padding(5.0); // text field
     let set_amperage_button = Button::new("Set Amperage".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            let mut answer_string = String::new();
-            get_set_amperage_voltage(
-                &my_app_state.current_port,
-                ISET_COMMAND,
-                &mut answer_string,
-                &my_app_state.current_amperage,
-                &mut my_output,
-            );
-            my_app_state.output_info.clear();
-            my_app_state.output_info.push_str(&my_output);
-        })
+        .on_click(
+            move |_ctx, ((port_config, my_instrument), my_channel): &mut ChannelData, _env| {
+                // do not send anything the supply can not do
+                if let Err(e) =
+                    parse_form_number(&my_channel.current_amperage).and_then(|amperage| {
+                        my_instrument.model_info().check_amperage(amperage)?;
+                        my_instrument.limits.check_amperage(amperage)
+                    })
+                {
+                    show_result(my_instrument, Err(e));
+                    return;
+                }
+                let result = match get_set_amperage_voltage(
+                    &my_instrument.current_port,
+                    port_config,
+                    my_channel.number,
+                    ISET_COMMAND,
+                    &my_channel.current_amperage,
+                ) {
+                    Ok(_) => Ok(format!(
+                        "Amperage of channel {} set to {} A! \n",
+                        my_channel.number, my_channel.current_amperage
+                    )),
+                    Err(e) => Err(format!("{}! \n", e)),
+                };
+                show_result(my_instrument, result);
+            },
+        )
         .padding(5.0); //button
 
     let get_amperage_button = Button::new("Get Amperage".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            let mut answer_string = String::new();
-            let dummy_value = "".to_string();
-            get_set_amperage_voltage(
-                &my_app_state.current_port,
-                IGET_COMMAND,
-                &mut answer_string,
-                &dummy_value,
-                &mut my_output,
-            );
-            if !answer_string.is_empty() {
-                my_app_state.current_amperage = answer_string;
-            }
-            my_app_state.output_info.clear();
-            my_app_state.output_info.push_str(&my_output);
-        })
+        .on_click(
+            move |_ctx, ((port_config, my_instrument), my_channel): &mut ChannelData, _env| {
+                let result = match get_set_amperage_voltage(
+                    &my_instrument.current_port,
+                    port_config,
+                    my_channel.number,
+                    IGET_COMMAND,
+                    "",
+                ) {
+                    Ok(answer_string) if !answer_string.is_empty() => {
+                        let my_output = format!(
+                            "The amperage setting of channel {} is {} A! \n",
+                            my_channel.number, answer_string
+                        );
+                        my_channel.current_amperage = answer_string;
+                        Ok(my_output)
+                    }
+                    Ok(_) => Err("No answer from the supply! \n".to_string()),
+                    Err(e) => Err(format!("{}! \n", e)),
+                };
+                show_result(my_instrument, result);
+            },
+        )
         .padding(5.0); //button
 
     let get_actual_amperage_button = Button::new("Actual Amperage".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            let mut answer_string = String::new();
-            let dummy_value = "".to_string();
-            get_set_amperage_voltage(
-                &my_app_state.current_port,
-                IOUT_COMMAND,
-                &mut answer_string,
-                &dummy_value,
-                &mut my_output,
-            );
-            my_app_state.output_info.clear();
-            if !answer_string.is_empty() {
-                my_app_state
-                    .output_info
-                    .push_str(&(format!("The actual output amperage is {} A!", answer_string)));
-            }
-            my_app_state.output_info.push_str(&my_output);
-        })
-        .padding(5.0); //button
-
-    let on_button = Button::new("Output ON".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            let mut answer_string = String::new();
-            turn_on_off(
-                &my_app_state.current_port,
-                ON_COMMAND,
-                &mut answer_string,
-                &mut my_output,
-            );
-            my_app_state.output_info.clear();
-            my_app_state.output_info.push_str(&my_output);
-        })
-        .padding(5.0); //button
-
-    let off_button = Button::new("Output OFF".to_string())
-        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
-            let mut my_output = String::new();
-            let mut answer_string = String::new();
-            turn_on_off(
-                &my_app_state.current_port,
-                OFF_COMMAND,
-                &mut answer_string,
-                &mut my_output,
-            );
-            my_app_state.output_info.clear();
-            my_app_state.output_info.push_str(&my_output);
-        })
+        .on_click(
+            move |_ctx, ((port_config, my_instrument), my_channel): &mut ChannelData, _env| {
+                let answer = get_set_amperage_voltage(
+                    &my_instrument.current_port,
+                    port_config,
+                    my_channel.number,
+                    IOUT_COMMAND,
+                    "",
+                );
+                let result = match answer {
+                    Ok(answer_string) if !answer_string.is_empty() => {
+                        let my_output =
+                            format!("The actual output amperage is {} A! \n", answer_string);
+                        my_channel.measured_amperage = answer_string;
+                        Ok(my_output)
+                    }
+                    Ok(_) => Err("No answer from the supply! \n".to_string()),
+                    Err(e) => Err(format!("{}! \n", e)),
+                };
+                show_result(my_instrument, result);
+            },
+        )
         .padding(5.0); //button
 
     let amperage_col = Flex::column() //create a column for it and add it alle
@@ -214,10 +579,6 @@ pub fn ui_builder() -> impl Widget<TheAppState> {
         .with_child(get_amperage_button)
         .with_child(get_actual_amperage_button);
 
-    let status_row = Flex::row() // define a row for the status buttons
-        .with_child(id_button)
-        .with_child(status_button);
-
     // define the container row to hold voltage and current setting
     let voltage_current_row = Flex::row()
         .with_child(
@@ -231,40 +592,1402 @@ pub fn ui_builder() -> impl Widget<TheAppState> {
                 .padding(2.0),
         );
 
-    let on_off_row = Flex::row() // define a row for the status buttons
-        .with_child(on_button)
-        .with_child(off_button);
+    let channel_label = Label::dynamic(|(_, my_channel): &ChannelData, _env| {
+        format!(
+            "CH{}: {} V / {} A",
+            my_channel.number, my_channel.measured_voltage, my_channel.measured_amperage
+        )
+    })
+    .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+    .padding(5.0);
 
-    // define the container column for ALL settings
-    let settings_col = Flex::column()
-        .with_child(status_row)
+    // energy and charge of the live readings, with pause and reset
+    let energy_label =
+        Label::dynamic(|(_, my_channel): &ChannelData, _env| my_channel.energy.describe())
+            .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+            .padding(5.0);
+
+    let pause_energy_button = Button::dynamic(|(_, my_channel): &ChannelData, _env| {
+        if my_channel.energy.paused {
+            "Resume".to_string()
+        } else {
+            "Pause".to_string()
+        }
+    })
+    .on_click(|_ctx, (_, my_channel): &mut ChannelData, _env| {
+        let paused = !my_channel.energy.paused;
+        my_channel.energy.set_paused(paused);
+    })
+    .padding(5.0); //button
+
+    let reset_energy_button = Button::new("Reset".to_string())
+        .on_click(|_ctx, (_, my_channel): &mut ChannelData, _env| {
+            my_channel.energy.reset();
+        })
+        .padding(5.0); //button
+
+    let energy_row = Flex::row()
+        .with_child(pause_energy_button)
+        .with_child(reset_energy_button);
+
+    // statistics of the live readings, since the reset or over the last N readings
+    let statistics_label =
+        Label::dynamic(|(_, my_channel): &ChannelData, _env| my_channel.statistics.describe())
+            .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+            .padding(5.0);
+
+    let reset_statistics_button = Button::new("Reset stats".to_string())
+        .on_click(|_ctx, (_, my_channel): &mut ChannelData, _env| {
+            my_channel.statistics.reset();
+        })
+        .padding(5.0); //button
+
+    let window_text = TextBox::new()
+        .lens(channel_lens().then(Channel::statistics_window))
+        .fix_width(60.0)
+        .padding(5.0); // text field
+
+    let window_button = Button::new("Set window".to_string())
+        .on_click(
+            |_ctx, ((_, my_instrument), my_channel): &mut ChannelData, _env| match my_channel
+                .statistics_window
+                .trim()
+                .parse::<usize>()
+            {
+                Ok(window) => my_channel.statistics = MeasurementStatistics::new(window),
+                Err(_) => {
+                    let e = format!(
+                        "Invalid window '{}', give the number of readings (0 = all)! \n",
+                        my_channel.statistics_window.trim()
+                    );
+                    show_result(my_instrument, Err(e));
+                }
+            },
+        )
+        .padding(5.0); //button
+
+    let statistics_row = Flex::row()
+        .with_child(reset_statistics_button)
+        .with_child(window_text)
+        .with_child(window_button);
+
+    Flex::column()
+        .with_child(channel_label)
         .with_child(voltage_current_row)
-        .with_child(on_off_row)
+        .with_child(energy_label)
+        .with_child(energy_row)
+        .with_child(statistics_label)
+        .with_child(statistics_row)
+        .with_child(
+            Container::new(charge_box())
+                .border(Color::grey8(0x55), 2.0)
+                .padding(2.0),
+        )
+}
+
+// the battery charge box of a channel: the profile, start / stop and the progress
+fn charge_box() -> impl Widget<ChannelData> {
+    // one button per battery type, fills in the usual values for one cell
+    let mut chemistry_row = Flex::row();
+    for chemistry in CHEMISTRIES.iter() {
+        let chemistry = *chemistry;
+        let chemistry_button = Button::new(chemistry.name().to_string())
+            .on_click(move |_ctx, (_, my_channel): &mut ChannelData, _env| {
+                let charge_current = my_channel
+                    .charge_form
+                    .charge_current
+                    .trim()
+                    .parse::<f64>()
+                    .unwrap_or(0.5);
+                my_channel.charge_form =
+                    ChargeForm::from_profile(&ChargeProfile::new(chemistry, 1, charge_current));
+            })
+            .padding(5.0); //button
+        chemistry_row.add_child(chemistry_button);
+    }
+
+    let settings_col = Flex::column()
+        .with_child(setting_row(
+            "Battery type",
+            TextBox::new().lens(ChargeForm::chemistry),
+        ))
+        .with_child(setting_row(
+            "Charge current [A]",
+            TextBox::new().lens(ChargeForm::charge_current),
+        ))
+        .with_child(setting_row(
+            "Float voltage [V]",
+            TextBox::new().lens(ChargeForm::float_voltage),
+        ))
+        .with_child(setting_row(
+            "End current [A]",
+            TextBox::new().lens(ChargeForm::termination_current),
+        ))
+        .with_child(setting_row(
+            "Max. time [min]",
+            TextBox::new().lens(ChargeForm::max_minutes),
+        ))
+        .with_child(setting_row(
+            "Max. charge [mAh]",
+            TextBox::new().lens(ChargeForm::max_milliamp_hours),
+        ))
+        .lens(channel_lens().then(Channel::charge_form));
+
+    let start_button = Button::new("Start charge".to_string())
+        .on_click(
+            move |_ctx, ((port_config, my_instrument), my_channel): &mut ChannelData, _env| {
+                if my_channel.charging() {
+                    show_result(
+                        my_instrument,
+                        Err("A charge is already running! \n".to_string()),
+                    );
+                    return;
+                }
+                let mut my_output = String::new();
+                let result = my_channel.charge_form.to_profile().and_then(|profile| {
+                    // the supply tells its model itself, a dual one is refused
+                    let mut supply = Supply::open(&my_instrument.current_port, port_config)?;
+                    supply.limits = my_instrument.limits.clone();
+                    Charger::start(
+                        &mut supply,
+                        &my_instrument.label,
+                        my_channel.number,
+                        profile,
+                        &mut my_output,
+                    )
+                });
+                let result = match result {
+                    Ok(charger) => {
+                        my_channel.charger = Some(charger);
+                        Ok(my_output)
+                    }
+                    Err(e) => Err(format!("{}Charge not started: {} \n", my_output, e)),
+                };
+                show_result(my_instrument, result);
+            },
+        )
+        .padding(5.0); //button
+
+    let stop_button = Button::new("Stop charge".to_string())
+        .on_click(
+            move |_ctx, ((port_config, my_instrument), my_channel): &mut ChannelData, _env| {
+                if let Some(charger) = my_channel
+                    .charger
+                    .as_mut()
+                    .filter(|charger| charger.running())
+                {
+                    let mut my_output = String::new();
+                    charger.stop(port_config, "stopped by hand", &mut my_output);
+                    show_result(my_instrument, Ok(my_output));
+                }
+            },
+        )
+        .padding(5.0); //button
+
+    let charge_label =
+        Label::dynamic(
+            |(_, my_channel): &ChannelData, _env| match &my_channel.charger {
+                Some(charger) => charger.describe(),
+                None => "No charge yet".to_string(),
+            },
+        )
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
         .padding(5.0);
 
-    let info_label = Label::raw() // label
-        .with_font(FontDescriptor::new(FontFamily::MONOSPACE)) // font for label
+    Flex::column()
+        .with_child(Label::new("Battery charge".to_string()).padding(5.0))
+        .with_child(chemistry_row)
+        .with_child(settings_col)
+        .with_child(Flex::row().with_child(start_button).with_child(stop_button))
+        .with_child(charge_label)
+}
+
+// read a number from a text field, before the model and the soft limits check it
+fn parse_form_number(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("'{}' is not a number! \n", value))
+}
+
+// lens from the channel column data to the channel
+fn channel_lens() -> impl Lens<ChannelData, Channel> {
+    lens!(ChannelData, 1)
+}
+
+// lens from the panel data to the instrument
+fn instrument_lens() -> impl Lens<InstrumentData, Instrument> {
+    lens!(InstrumentData, 1)
+}
+
+// send the OFF command to every supply, no matter what the single panels do
+fn turn_all_off(my_app_state: &mut TheAppState) {
+    my_app_state.output_info.clear();
+    let port_config = my_app_state.port_config.clone();
+    for my_instrument in Arc::make_mut(&mut my_app_state.instruments).iter_mut() {
+        let result = switch_output(&my_instrument.current_port, &port_config, OFF_COMMAND);
+        let my_output = match &result {
+            Ok(my_output) | Err(my_output) => my_output.clone(),
+        };
+        show_result(my_instrument, result);
+        my_app_state
+            .output_info
+            .push_str(&format!("{}: {}", my_instrument.label, my_output));
+    }
+}
+
+// switch the output on through the driver, which checks the settings against the soft limits
+// of the supply first, and tell what happened, for the output box
+fn switch_on(my_instrument: &Instrument, port_config: &PortConfig) -> Result<String, String> {
+    let mut supply = Supply::with_model(
+        &my_instrument.current_port,
+        port_config,
+        my_instrument.model_info(),
+    );
+    supply.limits = my_instrument.limits.clone();
+    match supply.set_output(true) {
+        Ok(()) => Ok(format!("{} sent! \n", ON_COMMAND)),
+        Err(e) => Err(format!("{} not sent: {}! \n", ON_COMMAND, e.trim())),
+    }
+}
+
+// send an off, protection or tracking command and tell what happened, for the output box
+fn switch_output(
+    current_port: &str,
+    port_config: &PortConfig,
+    command: &str,
+) -> Result<String, String> {
+    match turn_on_off(current_port, port_config, command) {
+        Ok(()) => Ok(format!("{} sent! \n", command)),
+        Err(e) => Err(format!("{} failed: {}! \n", command, e)),
+    }
+}
+
+// show what a button did in the output box of the supply, and put it into the log too: a
+// failure as a warning, everything else as info
+fn show_result(my_instrument: &mut Instrument, result: Result<String, String>) {
+    let my_output = match result {
+        Ok(my_output) => {
+            info!("{}: {}", my_instrument.label, my_output.trim());
+            my_output
+        }
+        Err(e) => {
+            warn!("{}: {}", my_instrument.label, e.trim());
+            e
+        }
+    };
+    my_instrument.output_info.clear();
+    my_instrument.output_info.push_str(&my_output);
+}
+
+// run the power sequence up (in order) or down (in reverse order), on its own thread so the
+// GUI keeps going during the waits; the end comes back as SEQUENCE_DONE
+fn run_sequence(my_app_state: &mut TheAppState, up: bool, sink: ExtEventSink) {
+    if my_app_state.sequence_running {
+        return; // one sequence at a time
+    }
+    let rails: Vec<Rail> = my_app_state
+        .instruments
+        .iter()
+        .map(|instrument| Rail {
+            limits: instrument.limits.clone(),
+            ..Rail::new(&instrument.label, &instrument.current_port)
+        })
+        .collect();
+    let steps = match parse_sequence(&my_app_state.sequence_text) {
+        Ok(steps) => steps,
+        Err(e) => {
+            warn!("{}", e.trim());
+            my_app_state.output_info = format!("Sequence failed: {} \n", e);
+            return;
+        }
+    };
+    let port_config = my_app_state.port_config.clone();
+    my_app_state.sequence_running = true;
+    my_app_state.output_info = format!("Power {} running... \n", if up { "up" } else { "down" });
+    std::thread::spawn(move || {
+        let mut my_output = String::new();
+        let result = if up {
+            power_up(&steps, &rails, &port_config, &mut my_output)
+        } else {
+            power_down(&steps, &rails, &port_config, &mut my_output)
+        };
+        match result {
+            Ok(()) => {
+                my_output.push_str(if up {
+                    "Power up done! \n"
+                } else {
+                    "Power down done! \n"
+                });
+                info!("{}", my_output.trim());
+            }
+            Err(e) => {
+                warn!("{}", e.trim());
+                my_output.push_str(&format!("Sequence failed: {} \n", e));
+            }
+        }
+        let _ = sink.submit_command(SEQUENCE_DONE, my_output, Target::Auto);
+    });
+}
+
+// run the sweep of the sweep window on its supply, on its own thread so the GUI keeps going;
+// every point comes back as SWEEP_POINT, the end as SWEEP_DONE
+fn run_iv_sweep(my_app_state: &mut TheAppState, sink: ExtEventSink) {
+    if my_app_state.sweep_form.running {
+        return; // one sweep at a time
+    }
+    let sweep_form = my_app_state.sweep_form.clone();
+    let my_instrument = match my_app_state
+        .instruments
+        .iter()
+        .find(|i| i.id == sweep_form.instrument_id)
+    {
+        Some(my_instrument) => my_instrument,
+        None => {
+            my_app_state.sweep_form.message = "The supply was removed! \n".to_string();
+            return;
+        }
+    };
+    let settings = match sweep_form.to_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            my_app_state.sweep_form.message = format!("Sweep failed: {} \n", e.trim());
+            return;
+        }
+    };
+    let current_port = my_instrument.current_port.clone();
+    let limits = my_instrument.limits.clone(); // the soft limits count here too
+    let port_config = my_app_state.port_config.clone();
+    let watchdog = my_app_state.watchdog.clone();
+    my_app_state.sweep_form.running = true;
+    my_app_state.sweep_form.swept = Some(settings.clone());
+    my_app_state.sweep_form.points = Arc::new(Vec::new());
+    my_app_state.sweep_form.message = format!("Sweep of {} running... \n", my_instrument.label);
+    std::thread::spawn(move || {
+        let mut my_output = String::new();
+        let result = Supply::open(&current_port, &port_config).and_then(|mut supply| {
+            supply.limits = limits;
+            run_sweep(&mut supply, &settings, &mut my_output, &mut |point| {
+                // the sweep is a sign of life too, it may take longer than the watchdog time
+                if let Some(watchdog) = &watchdog {
+                    watchdog.feed();
+                }
+                let _ = sink.submit_command(SWEEP_POINT, *point, Target::Auto);
+            })
+        });
+        let _ = sink.submit_command(SWEEP_DONE, (result, my_output), Target::Auto);
+    });
+}
+
+// the end of a sweep: all points or why it failed, with what happened on the way
+fn finish_iv_sweep(my_app_state: &mut TheAppState, result: &SweepResult) {
+    let (result, my_output) = result;
+    let mut my_output = my_output.clone();
+    let sweep_form = &mut my_app_state.sweep_form;
+    sweep_form.running = false;
+    match result {
+        Ok(points) => sweep_form.points = Arc::new(points.clone()),
+        Err(e) => {
+            warn!("{}", e.trim());
+            my_output.push_str(&format!("Sweep failed: {} \n", e.trim()));
+        }
+    }
+    info!("{}", my_output.trim());
+    sweep_form.message = my_output;
+}
+
+// send the command of the console as it is and log the raw and the decoded answer
+fn send_console_command(my_app_state: &mut TheAppState) {
+    let command = my_app_state.console_form.command.trim().to_string();
+    if command.is_empty() {
+        return;
+    }
+    let console_form = &mut my_app_state.console_form;
+    console_form.remember(&command);
+    console_form.command.clear();
+    let my_instrument = match my_app_state
+        .instruments
+        .iter()
+        .find(|i| i.id == console_form.instrument_id)
+    {
+        Some(my_instrument) => my_instrument,
+        None => {
+            console_form.log.push_str("The supply was removed! \n");
+            return;
+        }
+    };
+    // the soft limits hold for the console too: settings above them are not sent, and the
+    // output only goes on when the settings on the supply are inside them
+    let mut supply = Supply::with_model(
+        &my_instrument.current_port,
+        &my_app_state.port_config,
+        my_instrument.model_info(),
+    );
+    supply.limits = my_instrument.limits.clone();
+    let checked = supply.limits.check_command(&command).and_then(|_| {
+        if command.eq_ignore_ascii_case(ON_COMMAND) {
+            supply.check_settings()
+        } else {
+            Ok(())
+        }
+    });
+    if let Err(e) = checked {
+        let entry = format!(">> {}\n   not sent: {}\n", command, e.trim());
+        warn!("Console: {}", entry.trim());
+        console_form.log.push_str(&entry);
+        return;
+    }
+    let entry = match send_command(
+        &my_instrument.current_port,
+        &my_app_state.port_config,
+        &command,
+    ) {
+        Ok(answer) => format!(
+            ">> {}\n<< {:?}\n   {}\n",
+            command,
+            answer,
+            decode_answer(&command, &answer, my_instrument.model_info().channels)
+        ),
+        Err(e) => format!(">> {}\n   failed: {}\n", command, e.trim()),
+    };
+    info!("Console: {}", entry.trim());
+    console_form.log.push_str(&entry);
+    // drop the oldest lines, so the log does not grow forever
+    let lines: Vec<&str> = console_form.log.lines().collect();
+    if lines.len() > CONSOLE_LINES {
+        console_form.log = lines[lines.len() - CONSOLE_LINES..].join("\n") + "\n";
+    }
+}
+
+// handles the commands that change the list of instruments
+struct InstrumentListController;
+
+impl<W: Widget<TheAppState>> Controller<TheAppState, W> for InstrumentListController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut TheAppState,
+        env: &Env,
+    ) {
+        match event {
+            Event::WindowSize(size) => {
+                // saved with the settings, the next start opens the window this big
+                data.settings.window_width = size.width;
+                data.settings.window_height = size.height;
+                child.event(ctx, event, data, env)
+            }
+            Event::Command(cmd) if cmd.is(REMOVE_INSTRUMENT) => {
+                data.remove_instrument(*cmd.get_unchecked(REMOVE_INSTRUMENT));
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(OPEN_SWEEP) => {
+                let id = *cmd.get_unchecked(OPEN_SWEEP);
+                if let Some(my_instrument) = data.instruments.iter().find(|i| i.id == id) {
+                    data.sweep_form.instrument_id = id;
+                    data.sweep_form.label = my_instrument.label.clone();
+                    if my_instrument.limits.max_power > 0.0 {
+                        // the soft limit of the supply is the default power guard
+                        data.sweep_form.max_power = my_instrument.limits.max_power.to_string();
+                    }
+                    data.sweep_form.message.clear();
+                    ctx.new_window(
+                        WindowDesc::new(sweep_dialog_builder)
+                            .title(format!("I-V sweep: {}", my_instrument.label))
+                            .window_size((SWEEP_WIDTH, SWEEP_HEIGHT)),
+                    );
+                }
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(SWEEP_POINT) => {
+                let point = cmd.get_unchecked(SWEEP_POINT);
+                let sweep_form = &mut data.sweep_form;
+                Arc::make_mut(&mut sweep_form.points).push(*point);
+                sweep_form.message = format!(
+                    "Point {}: {} {} -> {:.2} V / {:.3} A / {:.3} W \n",
+                    sweep_form.points.len(),
+                    point.setpoint,
+                    sweep_form
+                        .swept
+                        .as_ref()
+                        .map_or("V", |settings| settings.unit()),
+                    point.measurement.voltage,
+                    point.measurement.amperage,
+                    point.measurement.power
+                );
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(SWEEP_DONE) => {
+                finish_iv_sweep(data, cmd.get_unchecked(SWEEP_DONE));
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(SEQUENCE_DONE) => {
+                data.sequence_running = false;
+                data.output_info = cmd.get_unchecked(SEQUENCE_DONE).clone();
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(OPEN_CONSOLE) => {
+                let id = *cmd.get_unchecked(OPEN_CONSOLE);
+                if let Some(my_instrument) = data.instruments.iter().find(|i| i.id == id) {
+                    if data.console_form.instrument_id != id {
+                        // another supply, start with an empty log but keep the history
+                        data.console_form.log.clear();
+                    }
+                    data.console_form.instrument_id = id;
+                    data.console_form.label = my_instrument.label.clone();
+                    ctx.new_window(
+                        WindowDesc::new(console_dialog_builder)
+                            .title(format!("Console: {}", my_instrument.label))
+                            .window_size((CONSOLE_WIDTH, CONSOLE_HEIGHT)),
+                    );
+                }
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(OPEN_PROFILES) => {
+                let id = *cmd.get_unchecked(OPEN_PROFILES);
+                if let Some(my_instrument) = data.instruments.iter().find(|i| i.id == id) {
+                    data.profile_form.instrument_id = id;
+                    data.profile_form.label = my_instrument.label.clone();
+                    data.profile_form.refresh_names(&data.settings);
+                    data.profile_form.message.clear();
+                    ctx.new_window(
+                        WindowDesc::new(profiles_dialog_builder)
+                            .title(format!("Profiles: {}", my_instrument.label))
+                            .window_size((PROFILES_WIDTH, PROFILES_HEIGHT)),
+                    );
+                }
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
+
+// saves the settings when the main window is closed
+pub struct SettingsDelegate {
+    main_window: WindowId,
+}
+
+impl SettingsDelegate {
+    pub fn new(main_window: WindowId) -> Self {
+        SettingsDelegate { main_window }
+    }
+}
+
+impl AppDelegate<TheAppState> for SettingsDelegate {
+    fn window_removed(
+        &mut self,
+        id: WindowId,
+        data: &mut TheAppState,
+        _env: &Env,
+        _ctx: &mut DelegateCtx,
+    ) {
+        if id != self.main_window {
+            return; // one of the dialogs
+        }
+        match data.save_settings() {
+            Ok(()) => info!(
+                "Settings saved to {}",
+                data.settings_path.as_deref().unwrap_or_default()
+            ),
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+pub fn ui_builder(watchdog: Option<Watchdog>) -> impl Widget<TheAppState> {
+    let add_button = Button::new("Add supply".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            my_app_state.add_instrument();
+        })
+        .padding(5.0); //button
+
+    // master switch for all supplies
+    let all_off_button = Button::new("All outputs OFF".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            turn_all_off(my_app_state);
+        })
+        .padding(5.0); //button
+
+    // define button to open the dialog for the advanced port settings
+    let advanced_button = Button::new("Advanced...".to_string())
+        .on_click(move |ctx, my_app_state: &mut TheAppState, _env| {
+            // start the dialog with the settings that are in use
+            my_app_state.port_config_form = PortConfigForm::from_config(&my_app_state.port_config);
+            ctx.new_window(
+                WindowDesc::new(advanced_dialog_builder)
+                    .title("Advanced port settings")
+                    .window_size((ADVANCED_WIDTH, ADVANCED_HEIGHT)),
+            );
+        })
+        .padding(5.0); //button
+    let top_row = Flex::row()
+        .with_child(add_button)
+        .with_child(all_off_button)
+        .with_child(advanced_button);
+
+    // define the power sequence box, the text names the rails by their labels
+    let sequence_text = TextBox::multiline()
+        .with_placeholder("Rail 3V3 on, wait 50 ms, Rail 1V8 on")
+        .lens(TheAppState::sequence_text)
+        .expand_width()
+        .padding(5.0); // text field
+    let power_up_button = Button::new("Power UP".to_string())
+        .on_click(move |ctx, my_app_state: &mut TheAppState, _env| {
+            run_sequence(my_app_state, true, ctx.get_external_handle());
+        })
+        .padding(5.0); //button
+    let power_down_button = Button::new("Power DOWN".to_string())
+        .on_click(move |ctx, my_app_state: &mut TheAppState, _env| {
+            run_sequence(my_app_state, false, ctx.get_external_handle());
+        })
+        .padding(5.0); //button
+    let sequence_col = Flex::column()
+        .with_child(Label::new("Power sequence".to_string()).padding(5.0))
+        .with_child(sequence_text)
+        .with_child(
+            Flex::row()
+                .with_child(power_up_button)
+                .with_child(power_down_button),
+        );
+
+    let info_label = Label::raw() // label for the messages of the master actions
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
         .lens(TheAppState::output_info)
         .padding(5.0);
 
-    // the row containing all the serial stuff
-    let mut serial_row = Flex::row();
-    // add it all to the main container row, with borders and padding
+    // one panel per instrument, all of them share the port settings
+    let instrument_list = List::new(instrument_panel).lens(lens::Map::new(
+        |my_app_state: &TheAppState| {
+            (
+                my_app_state.port_config.clone(),
+                my_app_state.instruments.clone(),
+            )
+        },
+        |my_app_state: &mut TheAppState, instrument_data: (PortConfig, Arc<Vec<Instrument>>)| {
+            my_app_state.instruments = instrument_data.1;
+        },
+    ));
 
-    serial_row.add_child(
-        Container::new(select_col)
-            .border(Color::grey8(0x55), 2.0)
-            .padding(5.0),
-    );
-    serial_row.add_child(
-        Container::new(settings_col)
-            .border(Color::grey8(0x55), 2.0)
-            .padding(5.0),
+    Flex::column()
+        .with_child(top_row)
+        .with_child(
+            Container::new(sequence_col)
+                .border(Color::grey8(0x55), 2.0)
+                .padding(5.0),
+        )
+        .with_child(info_label)
+        .with_flex_child(Scroll::new(instrument_list), 1.0)
+        .with_child(
+            Container::new(log_box())
+                .border(Color::grey8(0x55), 2.0)
+                .padding(5.0),
+        )
+        .controller(InstrumentListController)
+        .controller(MonitorController::new(watchdog))
+}
+
+// the log of the driver and the GUI, the newest line on top
+fn log_box() -> impl Widget<TheAppState> {
+    let mut filter_row = Flex::row().with_child(Label::new("Log".to_string()).padding(5.0));
+    for (name, filter) in [
+        ("Info", LogFilter::Info),
+        ("Errors", LogFilter::Errors),
+        ("Traffic", LogFilter::Traffic),
+        ("All", LogFilter::All),
+    ] {
+        filter_row.add_child(
+            Radio::new(name, filter)
+                .lens(TheAppState::log_filter)
+                .padding(2.0),
+        );
+    }
+
+    // the lines of the filter, not only the shown ones
+    let copy_button = Button::new("Copy".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            Application::global()
+                .clipboard()
+                .put_string(log_text(my_app_state.log_filter));
+            my_app_state.log_message = "Log copied to the clipboard".to_string();
+        })
+        .padding(5.0); //button
+    let save_button = Button::new("Save log".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            let path = my_app_state.log_path.trim().to_string();
+            my_app_state.log_message =
+                match std::fs::write(&path, log_text(my_app_state.log_filter)) {
+                    Ok(()) => format!("Log saved to {}", path),
+                    Err(e) => format!("Could not write {}: {}", path, e),
+                };
+        })
+        .padding(5.0); //button
+    let log_path_text = TextBox::new().lens(TheAppState::log_path).padding(5.0); // text field
+    let clear_button = Button::new("Clear".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            clear_log();
+            my_app_state.log_message.clear();
+        })
+        .padding(5.0); //button
+    let action_row = Flex::row()
+        .with_child(copy_button)
+        .with_child(save_button)
+        .with_child(log_path_text)
+        .with_child(clear_button)
+        .with_child(Label::dynamic(|my_app_state: &TheAppState, _env| {
+            my_app_state.log_message.clone()
+        }));
+
+    // one label per line, coloured by its level
+    let log_list = List::new(|| {
+        EnvScope::new(
+            |env, entry: &LogEntry| env.set(theme::LABEL_COLOR, level_color(&entry.level)),
+            Label::dynamic(|entry: &LogEntry, _env| {
+                format!("{} {:>5} {}", entry.time, entry.level, entry.text)
+            })
+            .with_font(FontDescriptor::new(FontFamily::MONOSPACE)),
+        )
+    })
+    .lens(TheAppState::log_entries);
+
+    Flex::column()
+        .with_child(filter_row)
+        .with_child(action_row)
+        .with_child(
+            Scroll::new(log_list.padding(5.0))
+                .fix_height(LOG_HEIGHT)
+                .expand_width(),
+        )
+        .controller(LogController::new())
+}
+
+fn level_color(level: &str) -> Color {
+    match level {
+        "ERROR" => Color::rgb8(0xff, 0x55, 0x55),
+        "WARN" => Color::rgb8(0xff, 0xbb, 0x33),
+        "INFO" => Color::grey8(0xee),
+        _ => Color::grey8(0x99), // debug and trace
+    }
+}
+
+// one line of a settings form: a label and the text field for the setting
+fn setting_row<T: Data>(label: &str, text_box: impl Widget<T> + 'static) -> impl Widget<T> {
+    Flex::row()
+        .with_child(Label::new(label.to_string()).fix_width(130.0))
+        .with_child(text_box)
+        .padding(2.0)
+}
+
+fn advanced_dialog_builder() -> impl Widget<TheAppState> {
+    // the column with all text fields
+    let settings_col = Flex::column()
+        .with_child(setting_row(
+            "Baud rate",
+            TextBox::new().lens(PortConfigForm::baud_rate),
+        ))
+        .with_child(setting_row(
+            "Data bits (5-8)",
+            TextBox::new().lens(PortConfigForm::data_bits),
+        ))
+        .with_child(setting_row(
+            "Parity",
+            TextBox::new().lens(PortConfigForm::parity),
+        ))
+        .with_child(setting_row(
+            "Stop bits (1/2)",
+            TextBox::new().lens(PortConfigForm::stop_bits),
+        ))
+        .with_child(setting_row(
+            "Flow control",
+            TextBox::new().lens(PortConfigForm::flow_control),
+        ))
+        .with_child(setting_row(
+            "Timeout in [ms]",
+            TextBox::new().lens(PortConfigForm::timeout_ms),
+        ))
+        .lens(TheAppState::port_config_form);
+
+    // take over the settings, but only if all of them are valid
+    let apply_button = Button::new("Apply".to_string())
+        .on_click(move |ctx, my_app_state: &mut TheAppState, _env| {
+            match my_app_state.port_config_form.to_config() {
+                Ok(port_config) => {
+                    // saved with the settings when the program ends
+                    my_app_state.settings.port_config = port_config.clone();
+                    my_app_state.port_config = port_config;
+                    info!("New port settings applied");
+                    my_app_state.output_info.clear();
+                    my_app_state
+                        .output_info
+                        .push_str("New port settings applied! \n");
+                    ctx.submit_command(commands::CLOSE_WINDOW);
+                }
+                Err(e) => {
+                    warn!("{}", e.trim());
+                    my_app_state.port_config_form.message = e;
+                }
+            }
+        })
+        .padding(5.0); //button
+
+    // go back to the KD3005P defaults (9600 baud, 8N1)
+    let defaults_button = Button::new("Defaults".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            my_app_state.port_config_form = PortConfigForm::from_config(&PortConfig::default());
+        })
+        .padding(5.0); //button
+
+    let cancel_button = Button::new("Cancel".to_string())
+        .on_click(move |ctx, _my_app_state: &mut TheAppState, _env| {
+            ctx.submit_command(commands::CLOSE_WINDOW);
+        })
+        .padding(5.0); //button
+
+    let button_row = Flex::row()
+        .with_child(apply_button)
+        .with_child(defaults_button)
+        .with_child(cancel_button);
+
+    let message_label = Label::raw() // shows what is wrong with the settings
+        .with_text_color(Color::rgb8(0xff, 0x55, 0x55))
+        .lens(TheAppState::port_config_form.then(PortConfigForm::message))
+        .padding(5.0);
+
+    Flex::column()
+        .with_child(settings_col)
+        .with_child(button_row)
+        .with_child(message_label)
+        .padding(5.0)
+}
+
+fn sweep_dialog_builder() -> impl Widget<TheAppState> {
+    let label =
+        Label::dynamic(|sweep_form: &SweepForm, _env| format!("Sweep of {}", sweep_form.label))
+            .padding(5.0);
+
+    let mode_checkbox = Checkbox::new("Step the current limit instead of the voltage")
+        .lens(SweepForm::sweep_current)
+        .padding(5.0);
+
+    let settings_col = Flex::column()
+        .with_child(setting_row(
+            "Channel",
+            TextBox::new().lens(SweepForm::channel),
+        ))
+        .with_child(setting_row(
+            "Start [V/A]",
+            TextBox::new().lens(SweepForm::start),
+        ))
+        .with_child(setting_row(
+            "Stop [V/A]",
+            TextBox::new().lens(SweepForm::stop),
+        ))
+        .with_child(setting_row(
+            "Step [V/A]",
+            TextBox::new().lens(SweepForm::step),
+        ))
+        .with_child(setting_row(
+            "Fixed I / V",
+            TextBox::new().lens(SweepForm::fixed),
+        ))
+        .with_child(setting_row(
+            "Settle [ms]",
+            TextBox::new().lens(SweepForm::settle_ms),
+        ))
+        .with_child(setting_row(
+            "Max. power [W]",
+            TextBox::new().lens(SweepForm::max_power),
+        ));
+
+    let run_button = Button::new("Run sweep".to_string())
+        .on_click(move |ctx, my_app_state: &mut TheAppState, _env| {
+            run_iv_sweep(my_app_state, ctx.get_external_handle());
+        })
+        .padding(5.0); //button
+
+    // write the points of the last sweep to the file in the text field
+    let save_button = Button::new("Save CSV".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            let sweep_form = &mut my_app_state.sweep_form;
+            // the settings the points were measured with, not what is in the form now
+            let settings = match &sweep_form.swept {
+                Some(settings) => settings,
+                None => {
+                    sweep_form.message = "No sweep to save yet! \n".to_string();
+                    return;
+                }
+            };
+            let csv = to_csv(settings, &sweep_form.points);
+            sweep_form.message = match std::fs::write(sweep_form.csv_path.trim(), csv) {
+                Ok(()) => format!(
+                    "{} points saved to {} \n",
+                    sweep_form.points.len(),
+                    sweep_form.csv_path.trim()
+                ),
+                Err(e) => format!("Could not write {}: {} \n", sweep_form.csv_path.trim(), e),
+            };
+        })
+        .padding(5.0); //button
+    let csv_text = TextBox::new()
+        .lens(TheAppState::sweep_form.then(SweepForm::csv_path))
+        .padding(5.0); // text field
+
+    let close_button = Button::new("Close".to_string())
+        .on_click(move |ctx, _my_app_state: &mut TheAppState, _env| {
+            ctx.submit_command(commands::CLOSE_WINDOW);
+        })
+        .padding(5.0); //button
+
+    let button_row = Flex::row()
+        .with_child(run_button)
+        .with_child(save_button)
+        .with_child(csv_text)
+        .with_child(close_button);
+
+    let plot = CurvePlot
+        .lens(TheAppState::sweep_form.then(SweepForm::points))
+        .padding(5.0);
+
+    let message_label = Label::raw() // shows how the sweep went
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+        .lens(TheAppState::sweep_form.then(SweepForm::message))
+        .padding(5.0);
+
+    Flex::column()
+        .with_child(
+            Flex::column()
+                .with_child(label)
+                .with_child(mode_checkbox)
+                .with_child(settings_col)
+                .lens(TheAppState::sweep_form),
+        )
+        .with_child(button_row)
+        .with_child(plot)
+        .with_child(message_label)
+        .padding(5.0)
+}
+
+// any command typed in is sent to the supply, e.g. to try commands that are not in the manual
+fn console_dialog_builder() -> impl Widget<TheAppState> {
+    let label = Label::dynamic(|console_form: &ConsoleForm, _env| {
+        format!("Raw commands to {}", console_form.label)
+    })
+    .padding(5.0)
+    .lens(TheAppState::console_form);
+
+    let command_text = TextBox::new()
+        .with_placeholder("e.g. OCP1, RCL3, STATUS?")
+        .fix_width(200.0)
+        .lens(TheAppState::console_form.then(ConsoleForm::command))
+        .padding(5.0); // text field
+
+    let send_button = Button::new("Send".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            send_console_command(my_app_state);
+        })
+        .padding(5.0); //button
+
+    // walk through the sent commands, like the arrow keys in a terminal
+    let previous_button = Button::new("Previous".to_string())
+        .on_click(move |_ctx, console_form: &mut ConsoleForm, _env| {
+            console_form.browse(-1);
+        })
+        .padding(5.0) //button
+        .lens(TheAppState::console_form);
+    let next_button = Button::new("Next".to_string())
+        .on_click(move |_ctx, console_form: &mut ConsoleForm, _env| {
+            console_form.browse(1);
+        })
+        .padding(5.0) //button
+        .lens(TheAppState::console_form);
+
+    let clear_button = Button::new("Clear".to_string())
+        .on_click(move |_ctx, console_form: &mut ConsoleForm, _env| {
+            console_form.log.clear();
+        })
+        .padding(5.0) //button
+        .lens(TheAppState::console_form);
+
+    let close_button = Button::new("Close".to_string())
+        .on_click(move |ctx, _my_app_state: &mut TheAppState, _env| {
+            ctx.submit_command(commands::CLOSE_WINDOW);
+        })
+        .padding(5.0); //button
+
+    let command_row = Flex::row()
+        .with_child(command_text)
+        .with_child(send_button)
+        .with_child(previous_button)
+        .with_child(next_button);
+
+    let log_label = Label::raw() // everything sent and received
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+        .lens(TheAppState::console_form.then(ConsoleForm::log))
+        .padding(5.0);
+    let log_scroll = Scroll::new(Container::new(log_label))
+        .vertical()
+        .fix_height(CONSOLE_LOG_HEIGHT);
+
+    Flex::column()
+        .with_child(label)
+        .with_child(command_row)
+        .with_child(
+            Container::new(log_scroll)
+                .border(Color::grey8(0x55), 2.0)
+                .padding(5.0),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(clear_button)
+                .with_child(close_button),
+        )
+        .padding(5.0)
+}
+
+// set the voltage and current of the profile in the form on the supply of the dialog
+fn apply_profile(my_app_state: &mut TheAppState) {
+    let profile = match my_app_state.profile_form.to_profile() {
+        Ok(profile) => profile,
+        Err(e) => {
+            my_app_state.profile_form.message = e;
+            return;
+        }
+    };
+    let channel = match my_app_state.profile_form.channel.trim().parse::<u8>() {
+        Ok(channel) => channel,
+        Err(_) => {
+            my_app_state.profile_form.message =
+                format!("Invalid channel '{}'", my_app_state.profile_form.channel);
+            return;
+        }
+    };
+    let id = my_app_state.profile_form.instrument_id;
+    let my_instrument = match Arc::make_mut(&mut my_app_state.instruments)
+        .iter_mut()
+        .find(|i| i.id == id)
+    {
+        Some(my_instrument) => my_instrument,
+        None => {
+            my_app_state.profile_form.message = "The supply was removed! \n".to_string();
+            return;
+        }
+    };
+    // the driver checks the setting against the model and the soft limits, the limits of the
+    // profile count only for this and are not taken over by the supply
+    let result = Supply::open(&my_instrument.current_port, &my_app_state.port_config).and_then(
+        |mut supply| {
+            supply.limits = my_instrument.limits.clone();
+            profile.apply(&mut supply, channel)
+        },
     );
+    my_app_state.profile_form.message = match result {
+        Ok(()) => {
+            if let Some(my_channel) = Arc::make_mut(&mut my_instrument.channels)
+                .iter_mut()
+                .find(|my_channel| my_channel.number == channel)
+            {
+                my_channel.current_voltage = format!("{:.2}", profile.voltage);
+                my_channel.current_amperage = format!("{:.3}", profile.amperage);
+            }
+            let message = format!(
+                "Profile '{}' applied to {} CH{}: {} V, {} A \n",
+                profile.name, my_instrument.label, channel, profile.voltage, profile.amperage
+            );
+            info!("{}", message.trim());
+            my_instrument.output_info = message.clone();
+            message
+        }
+        Err(e) => {
+            warn!("Profile '{}': {}", profile.name, e.trim());
+            e
+        }
+    };
+}
+
+// the supply of the profiles dialog, with its soft limits
+fn profile_supply(my_app_state: &TheAppState) -> Result<Supply, String> {
+    let id = my_app_state.profile_form.instrument_id;
+    let my_instrument = my_app_state
+        .instruments
+        .iter()
+        .find(|i| i.id == id)
+        .ok_or_else(|| "The supply was removed! \n".to_string())?;
+    let mut supply = Supply::open(&my_instrument.current_port, &my_app_state.port_config)?;
+    supply.limits = my_instrument.limits.clone();
+    Ok(supply)
+}
+
+// recall M1-M5 one after the other, the supply is put back as it was
+fn show_memories(my_app_state: &mut TheAppState) {
+    let mut my_output = String::new();
+    let result = profile_supply(my_app_state)
+        .and_then(|mut supply| read_memories(&mut supply, &mut my_output));
+    my_app_state.profile_form.message = match result {
+        Ok(_) => my_output,
+        Err(e) => {
+            warn!("Reading the memories failed: {}", e.trim());
+            format!("{}{}", my_output, e)
+        }
+    };
+}
+
+// store the profile of the form in the memory of the slot field
+fn store_in_memory(my_app_state: &mut TheAppState) {
+    let profile = match my_app_state.profile_form.to_profile() {
+        Ok(profile) => profile,
+        Err(e) => {
+            my_app_state.profile_form.message = e;
+            return;
+        }
+    };
+    let slot = match my_app_state.profile_form.slot.trim().parse::<u8>() {
+        Ok(slot) => slot,
+        Err(_) => {
+            my_app_state.profile_form.message =
+                format!("Invalid memory '{}'", my_app_state.profile_form.slot);
+            return;
+        }
+    };
+    let mut my_output = String::new();
+    let result = profile_supply(my_app_state)
+        .and_then(|mut supply| write_memories(&mut supply, &[(slot, profile)], &mut my_output));
+    my_app_state.profile_form.message = match result {
+        Ok(()) => {
+            info!("{}", my_output.trim());
+            my_output
+        }
+        Err(e) => {
+            warn!("Storing in M{} failed: {}", slot, e.trim());
+            format!("{}{}", my_output, e)
+        }
+    };
+}
+
+// the profiles are kept in the settings file at once, not only when the program ends
+fn store_profiles(my_app_state: &mut TheAppState, message: String) {
+    my_app_state
+        .profile_form
+        .refresh_names(&my_app_state.settings);
+    my_app_state.profile_form.message = match my_app_state.save_settings() {
+        Ok(()) => message,
+        Err(e) => format!("{}, but {}", message, e),
+    };
+}
+
+// shows a profile of the list in the form of the profiles dialog
+struct ProfileDialogController;
+
+impl<W: Widget<TheAppState>> Controller<TheAppState, W> for ProfileDialogController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut TheAppState,
+        env: &Env,
+    ) {
+        match event {
+            Event::Command(cmd) if cmd.is(SELECT_PROFILE) => {
+                let name = cmd.get_unchecked(SELECT_PROFILE);
+                if let Some(profile) = data.settings.find_profile(name) {
+                    data.profile_form.show(profile);
+                    data.profile_form.message.clear();
+                }
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
+
+fn profiles_dialog_builder() -> impl Widget<TheAppState> {
+    let label = Label::dynamic(|profile_form: &ProfileForm, _env| {
+        format!("Setpoint profiles for {}", profile_form.label)
+    })
+    .padding(5.0)
+    .lens(TheAppState::profile_form);
+
+    // one button per profile, it fills the fields below
+    let profile_list = List::new(|| {
+        Button::dynamic(|name: &String, _env| name.clone())
+            .on_click(move |ctx, name: &mut String, _env| {
+                ctx.submit_command(SELECT_PROFILE.with(name.clone()));
+            })
+            .padding(2.0) //button
+    })
+    .lens(TheAppState::profile_form.then(ProfileForm::names));
+    let list_scroll = Scroll::new(profile_list)
+        .vertical()
+        .fix_height(PROFILES_LIST_HEIGHT)
+        .expand_width();
+
+    let profile_col = Flex::column()
+        .with_child(setting_row(
+            "Name",
+            TextBox::new()
+                .with_placeholder("ESP32 board 3.3V/500mA")
+                .lens(ProfileForm::name),
+        ))
+        .with_child(setting_row(
+            "Voltage [V]",
+            TextBox::new().lens(ProfileForm::voltage),
+        ))
+        .with_child(setting_row(
+            "Current [A]",
+            TextBox::new().lens(ProfileForm::amperage),
+        ))
+        .with_child(setting_row(
+            "Max. voltage [V]",
+            TextBox::new().lens(ProfileForm::limits.then(LimitsForm::max_voltage)),
+        ))
+        .with_child(setting_row(
+            "Max. current [A]",
+            TextBox::new().lens(ProfileForm::limits.then(LimitsForm::max_amperage)),
+        ))
+        .with_child(setting_row(
+            "Max. power [W]",
+            TextBox::new().lens(ProfileForm::limits.then(LimitsForm::max_power)),
+        ))
+        .with_child(setting_row(
+            "Max. on-time [s]",
+            TextBox::new().lens(ProfileForm::limits.then(LimitsForm::max_on_time)),
+        ))
+        .with_child(
+            TextBox::multiline()
+                .with_placeholder("Notes")
+                .lens(ProfileForm::notes)
+                .expand_width()
+                .padding(5.0), // text field
+        )
+        .with_child(setting_row(
+            "Channel",
+            TextBox::new().lens(ProfileForm::channel),
+        ))
+        .lens(TheAppState::profile_form);
+
+    // set V and I of the supply, the limits of the profile on top of its soft limits
+    let apply_button = Button::new("Apply".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            apply_profile(my_app_state);
+        })
+        .padding(5.0); //button
+
+    let save_button = Button::new("Save profile".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            match my_app_state.profile_form.to_profile() {
+                Ok(profile) => {
+                    let message = format!("Profile '{}' saved", profile.name);
+                    my_app_state.settings.add_profile(profile);
+                    store_profiles(my_app_state, message);
+                }
+                Err(e) => my_app_state.profile_form.message = e,
+            }
+        })
+        .padding(5.0); //button
+
+    let delete_button = Button::new("Delete".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            let name = my_app_state.profile_form.name.trim().to_string();
+            if my_app_state.settings.remove_profile(&name) {
+                store_profiles(my_app_state, format!("Profile '{}' deleted", name));
+            } else {
+                my_app_state.profile_form.message = format!("There is no profile '{}'", name);
+            }
+        })
+        .padding(5.0); //button
+
+    // the five memories of the supply itself
+    let read_memories_button = Button::new("Read M1-M5".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            show_memories(my_app_state);
+        })
+        .padding(5.0); //button
+    let slot_text = TextBox::new()
+        .lens(TheAppState::profile_form.then(ProfileForm::slot))
+        .fix_width(40.0)
+        .padding(5.0); // text field
+    let store_button = Button::new("Store in M".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            store_in_memory(my_app_state);
+        })
+        .padding(5.0); //button
+
+    // share the profiles with the team as a file
+    let file_text = TextBox::new()
+        .lens(TheAppState::profile_form.then(ProfileForm::file_path))
+        .fix_width(200.0)
+        .padding(5.0); // text field
+    let import_button = Button::new("Import".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            let path = my_app_state.profile_form.file_path.trim().to_string();
+            match import_profiles(&path) {
+                Ok(profiles) => {
+                    let message = format!("{} profiles imported from {}", profiles.len(), path);
+                    for profile in profiles {
+                        my_app_state.settings.add_profile(profile);
+                    }
+                    info!("{}", message);
+                    store_profiles(my_app_state, message);
+                }
+                Err(e) => my_app_state.profile_form.message = e,
+            }
+        })
+        .padding(5.0); //button
+    let export_button = Button::new("Export".to_string())
+        .on_click(move |_ctx, my_app_state: &mut TheAppState, _env| {
+            let path = my_app_state.profile_form.file_path.trim().to_string();
+            my_app_state.profile_form.message =
+                match export_profiles(&path, &my_app_state.settings.profiles) {
+                    Ok(()) => format!(
+                        "{} profiles exported to {}",
+                        my_app_state.settings.profiles.len(),
+                        path
+                    ),
+                    Err(e) => e,
+                };
+        })
+        .padding(5.0); //button
+
+    let close_button = Button::new("Close".to_string())
+        .on_click(move |ctx, _my_app_state: &mut TheAppState, _env| {
+            ctx.submit_command(commands::CLOSE_WINDOW);
+        })
+        .padding(5.0); //button
 
-    let info_scroll = Scroll::new(Container::new(info_label));
-    let main_col = Flex::column()
-        .with_child(serial_row)
-        .with_child(info_scroll);
-    main_col
+    let message_label =
+        Label::dynamic(|profile_form: &ProfileForm, _env| profile_form.message.clone())
+            .padding(5.0)
+            .lens(TheAppState::profile_form);
+
+    Flex::column()
+        .with_child(label)
+        .with_child(
+            Container::new(list_scroll)
+                .border(Color::grey8(0x55), 2.0)
+                .padding(5.0),
+        )
+        .with_child(profile_col)
+        .with_child(
+            Flex::row()
+                .with_child(apply_button)
+                .with_child(save_button)
+                .with_child(delete_button),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(read_memories_button)
+                .with_child(store_button)
+                .with_child(slot_text),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(file_text)
+                .with_child(import_button)
+                .with_child(export_button),
+        )
+        .with_child(message_label)
+        .with_child(close_button)
+        .padding(5.0)
+        .controller(ProfileDialogController)
 }
@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{info, warn};
+
+use crate::driver::{Measurement, Supply};
+use crate::limits::{start_safety, SafetyLimits};
+use crate::metrics::render_metrics;
+use crate::port_config::PortConfig;
+
+// the readings of the last 10 minutes are kept for GET /api/history
+const HISTORY_INTERVAL: Duration = Duration::from_millis(1000);
+const HISTORY_LENGTH: usize = 600;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+// one point of the history, all channels read at the same time
+#[derive(Clone, Serialize)]
+struct HistoryPoint {
+    time: f64, // seconds since the server started
+    channels: Vec<Measurement>,
+}
+
+#[derive(Serialize)]
+struct ChannelState {
+    channel: u8,
+    voltage_setting: f64,
+    current_setting: f64,
+    measurement: Measurement,
+    mode: &'static str,
+}
+
+#[derive(Serialize)]
+struct SupplyState {
+    model: &'static str,
+    port: String,
+    output_on: bool,
+    channels: Vec<ChannelState>,
+}
+
+// body of PUT /api/setpoints, a missing value is left as it is
+#[derive(Deserialize)]
+struct Setpoints {
+    #[serde(default = "first_channel")]
+    channel: u8,
+    voltage: Option<f64>,
+    current: Option<f64>,
+}
+
+fn first_channel() -> u8 {
+    1
+}
+
+// body of POST /api/output
+#[derive(Deserialize)]
+struct OutputSwitch {
+    on: bool,
+}
+
+// hold the serial connection and serve the REST API and the dashboard until the process is stopped
+pub fn run_http_server(
+    address: &str,
+    current_port: &str,
+    port_config: &PortConfig,
+    limits: &SafetyLimits,
+) -> Result<(), String> {
+    // find out what is connected, so the right limits are used
+    let mut supply = Supply::open(current_port, port_config)?;
+    supply.limits = limits.clone();
+
+    let server =
+        Server::http(address).map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+    info!(
+        "HTTP server for the {} on {} is listening on http://{}/",
+        supply.model.name, current_port, address
+    );
+    if !(address.starts_with("127.") || address.starts_with("localhost")) {
+        // there is no login, everyone who reaches the port can switch the output
+        warn!("The HTTP server on {} has no authentication", address);
+    }
+
+    let supply = Arc::new(Mutex::new(supply));
+    let history = Arc::new(Mutex::new(VecDeque::new()));
+
+    // read the outputs in the background, so the history also fills without a browser open
+    {
+        let supply = supply.clone();
+        let history = history.clone();
+        thread::spawn(move || record_history(supply, history));
+    }
+
+    let watchdog = start_safety(&supply);
+    for request in server.incoming_requests() {
+        if let Some(watchdog) = &watchdog {
+            watchdog.feed(); // every request is a sign of life of the controller
+        }
+        handle_request(request, &supply, &history);
+    }
+    Ok(())
+}
+
+// only one request or the history talks to the supply at a time
+fn lock_supply(supply: &Mutex<Supply>) -> MutexGuard<'_, Supply> {
+    supply
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn record_history(supply: Arc<Mutex<Supply>>, history: Arc<Mutex<VecDeque<HistoryPoint>>>) {
+    let start = Instant::now();
+    loop {
+        thread::sleep(HISTORY_INTERVAL);
+        let point = {
+            let supply = lock_supply(&supply);
+            let channels: Result<Vec<Measurement>, String> = (1..=supply.model.channels)
+                .map(|channel| supply.measure(channel))
+                .collect();
+            match channels {
+                Ok(channels) => HistoryPoint {
+                    time: start.elapsed().as_secs_f64(),
+                    channels,
+                },
+                Err(e) => {
+                    warn!("Reading for the history failed: {}", e.trim());
+                    continue;
+                }
+            }
+        };
+        let mut history = history
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if history.len() >= HISTORY_LENGTH {
+            history.pop_front();
+        }
+        history.push_back(point);
+    }
+}
+
+// everything the dashboard shows, read from the supply
+fn read_state(supply: &Supply) -> Result<SupplyState, String> {
+    let status = supply.status()?;
+    let mut channels = Vec::new();
+    for channel in 1..=supply.model.channels {
+        channels.push(ChannelState {
+            channel,
+            voltage_setting: supply.voltage_setting(channel)?,
+            current_setting: supply.amperage_setting(channel)?,
+            measurement: supply.measure(channel)?,
+            mode: if status.constant_voltage(channel) {
+                "CV"
+            } else {
+                "CC"
+            },
+        });
+    }
+    Ok(SupplyState {
+        model: supply.model.name,
+        port: supply.current_port.clone(),
+        output_on: status.output_on(),
+        channels,
+    })
+}
+
+// check everything first, so a bad request does not leave half of it applied
+fn apply_setpoints(supply: &Supply, setpoints: &Setpoints) -> Result<(), (u16, String)> {
+    if setpoints.voltage.is_none() && setpoints.current.is_none() {
+        return Err((400, "Give a voltage, a current or both".to_string()));
+    }
+    if setpoints.channel < 1 || setpoints.channel > supply.model.channels {
+        return Err((
+            400,
+            format!(
+                "The {} has no channel {}",
+                supply.model.name, setpoints.channel
+            ),
+        ));
+    }
+    if let Some(voltage) = setpoints.voltage {
+        supply
+            .model
+            .check_voltage(voltage)
+            .and_then(|_| supply.limits.check_voltage(voltage))
+            .map_err(|e| (400, e))?;
+    }
+    if let Some(current) = setpoints.current {
+        supply
+            .model
+            .check_amperage(current)
+            .and_then(|_| supply.limits.check_amperage(current))
+            .map_err(|e| (400, e))?;
+    }
+    if let Some(voltage) = setpoints.voltage {
+        supply
+            .set_voltage(setpoints.channel, voltage)
+            .map_err(|e| (502, e))?;
+    }
+    if let Some(current) = setpoints.current {
+        supply
+            .set_amperage(setpoints.channel, current)
+            .map_err(|e| (502, e))?;
+    }
+    Ok(())
+}
+
+// read the JSON body of a request
+fn parse_body<T: serde::de::DeserializeOwned>(request: &mut Request) -> Result<T, String> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .map_err(|e| format!("Could not read the request: {}", e))?;
+    serde_json::from_str(&body).map_err(|e| format!("Invalid JSON: {}", e))
+}
+
+fn respond(request: Request, status: u16, content_type: &str, body: String) {
+    let header = Header::from_bytes("Content-Type", content_type).unwrap();
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        warn!("Could not answer the request: {}", e);
+    }
+}
+
+fn respond_json<T: Serialize>(request: Request, result: Result<T, (u16, String)>) {
+    let (status, body) = match result {
+        Ok(value) => (200, serde_json::to_string(&value).unwrap_or_default()),
+        Err((status, e)) => (status, serde_json::json!({ "error": e.trim() }).to_string()),
+    };
+    respond(request, status, "application/json", body);
+}
+
+// the supply is locked only for the commands, a body is read and checked before, so a slow
+// or broken client does not hold up the history and the watchdog
+fn handle_request(
+    mut request: Request,
+    supply: &Mutex<Supply>,
+    history: &Mutex<VecDeque<HistoryPoint>>,
+) {
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap_or("");
+    // 400 for a bad request, 502 when the supply does not play along
+    match (request.method(), path) {
+        (Method::Get, "/") | (Method::Get, "/index.html") => {
+            respond(
+                request,
+                200,
+                "text/html; charset=utf-8",
+                DASHBOARD.to_string(),
+            );
+        }
+        (Method::Get, "/api/state") => {
+            let result = read_state(&lock_supply(supply)).map_err(|e| (502, e));
+            respond_json(request, result);
+        }
+        (Method::Put, "/api/setpoints") => {
+            let result = parse_body::<Setpoints>(&mut request)
+                .map_err(|e| (400, e))
+                .and_then(|setpoints| {
+                    let supply = lock_supply(supply);
+                    apply_setpoints(&supply, &setpoints)?;
+                    read_state(&supply).map_err(|e| (502, e))
+                });
+            respond_json(request, result);
+        }
+        (Method::Post, "/api/output") => {
+            let result = parse_body::<OutputSwitch>(&mut request)
+                .map_err(|e| (400, e))
+                .and_then(|switch| {
+                    let mut supply = lock_supply(supply);
+                    supply.set_output(switch.on).map_err(|e| (502, e))?;
+                    read_state(&supply).map_err(|e| (502, e))
+                });
+            respond_json(request, result);
+        }
+        (Method::Get, "/metrics") => {
+            respond(
+                request,
+                200,
+                "text/plain; version=0.0.4",
+                render_metrics(&lock_supply(supply)),
+            );
+        }
+        (Method::Get, "/api/history") => {
+            let points: Vec<HistoryPoint> = history
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .iter()
+                .cloned()
+                .collect();
+            respond_json::<Vec<HistoryPoint>>(request, Ok(points));
+        }
+        _ => {
+            respond_json::<()>(request, Err((404, format!("No such resource {}", path))));
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// the supply without the GUI: serial protocol, models and the drivers, for use from other programs
+pub mod capture;
+pub mod charger;
+pub mod driver;
+pub mod energy;
+pub mod limits;
+pub mod memories;
+pub mod model;
+pub mod port_config;
+pub mod profiles;
+pub mod protocol;
+pub mod rules;
+pub mod sequence;
+pub mod serial;
+pub mod settings;
+pub mod simulator;
+pub mod stats;
+pub mod status;
+pub mod sweep;
+
+// async API on tokio-serial, see README
+#[cfg(feature = "async")]
+pub mod async_driver;
@@ -0,0 +1,389 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "gui")]
+use druid::Data;
+use tracing::{error, warn};
+
+use crate::driver::{Measurement, Supply};
+use crate::model::MODELS;
+use crate::port_config::PortConfig;
+use crate::serial::*;
+
+// the GUI and the servers check the limits this often
+pub const LIMIT_CHECK_INTERVAL: Duration = Duration::from_millis(1000);
+
+// the watchdog must give the controlling process at least this long between two signs of life
+const MIN_WATCHDOG_SECONDS: f64 = 2.0;
+
+// names of the limits, used by the command line, the config file and the GUI
+pub const MAX_VOLTAGE_KEY: &str = "max_voltage";
+pub const MAX_AMPERAGE_KEY: &str = "max_current";
+pub const MAX_POWER_KEY: &str = "max_power";
+pub const MAX_ON_TIME_KEY: &str = "max_on_time";
+pub const WATCHDOG_KEY: &str = "watchdog";
+
+// soft limits on top of the OVP/OCP of the supply, 0 means no limit
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SafetyLimits {
+    pub max_voltage: f64,      // V, highest voltage setting that is sent
+    pub max_amperage: f64,     // A, highest current setting that is sent
+    pub max_power: f64,        // W, measured on any channel
+    pub max_on_seconds: f64,   // s, the output is switched off after this time
+    pub watchdog_seconds: f64, // s, the output is switched off when the controller is silent this long
+}
+
+// the limits can be compared directly, so the GUI can hold them
+#[cfg(feature = "gui")]
+impl Data for SafetyLimits {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl SafetyLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_voltage < 0.0
+            || self.max_amperage < 0.0
+            || self.max_power < 0.0
+            || self.max_on_seconds < 0.0
+            || self.watchdog_seconds < 0.0
+        {
+            return Err("The limits must not be negative".to_string());
+        }
+        if self.watchdog_seconds > 0.0 && self.watchdog_seconds < MIN_WATCHDOG_SECONDS {
+            return Err(format!(
+                "The watchdog needs at least {} s",
+                MIN_WATCHDOG_SECONDS
+            ));
+        }
+        Ok(())
+    }
+
+    // change a single limit by its name, the value is checked before it is taken over
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let number = value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid limit '{}' for {}", value.trim(), key.trim()))?;
+        let mut new_limits = self.clone(); // work on a copy, so a bad value changes nothing
+        match key.trim() {
+            MAX_VOLTAGE_KEY => new_limits.max_voltage = number,
+            MAX_AMPERAGE_KEY => new_limits.max_amperage = number,
+            MAX_POWER_KEY => new_limits.max_power = number,
+            MAX_ON_TIME_KEY => new_limits.max_on_seconds = number,
+            WATCHDOG_KEY => new_limits.watchdog_seconds = number,
+            _ => return Err(format!("Unknown limit '{}'", key)),
+        }
+        new_limits.validate()?;
+        *self = new_limits;
+        Ok(())
+    }
+
+    // get a limit as text, the counterpart of set()
+    pub fn get(&self, key: &str) -> String {
+        match key {
+            MAX_VOLTAGE_KEY => self.max_voltage.to_string(),
+            MAX_AMPERAGE_KEY => self.max_amperage.to_string(),
+            MAX_POWER_KEY => self.max_power.to_string(),
+            MAX_ON_TIME_KEY => self.max_on_seconds.to_string(),
+            WATCHDOG_KEY => self.watchdog_seconds.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // a voltage setting must not be above the soft limit
+    pub fn check_voltage(&self, voltage: f64) -> Result<(), String> {
+        if self.max_voltage > 0.0 && voltage > self.max_voltage {
+            return Err(format!(
+                "{} V is above the soft limit of {} V! \n",
+                voltage, self.max_voltage
+            ));
+        }
+        Ok(())
+    }
+
+    // a current setting must not be above the soft limit
+    pub fn check_amperage(&self, amperage: f64) -> Result<(), String> {
+        if self.max_amperage > 0.0 && amperage > self.max_amperage {
+            return Err(format!(
+                "{} A is above the soft limit of {} A! \n",
+                amperage, self.max_amperage
+            ));
+        }
+        Ok(())
+    }
+
+    // a raw command like "VSET1:31" must keep to the soft limits too; RCL is refused while a
+    // voltage or current limit is set, the settings of a memory are not known before it is recalled
+    pub fn check_command(&self, command: &str) -> Result<(), String> {
+        let command = command.trim().to_uppercase();
+        // the value of e.g. "VSET2:5.00", None for other commands and queries
+        let setting = |prefix: &str| {
+            command
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.split_once(':'))
+                .map(|(_, value)| value.trim().parse::<f64>())
+        };
+        if let Some(voltage) = setting("VSET") {
+            return match voltage {
+                Ok(voltage) => self.check_voltage(voltage),
+                Err(_) if self.max_voltage > 0.0 => Err(format!(
+                    "Cannot check '{}' against the soft limits! \n",
+                    command
+                )),
+                Err(_) => Ok(()),
+            };
+        }
+        if let Some(amperage) = setting("ISET") {
+            return match amperage {
+                Ok(amperage) => self.check_amperage(amperage),
+                Err(_) if self.max_amperage > 0.0 => Err(format!(
+                    "Cannot check '{}' against the soft limits! \n",
+                    command
+                )),
+                Err(_) => Ok(()),
+            };
+        }
+        if command.starts_with(RECALL_COMMAND)
+            && (self.max_voltage > 0.0 || self.max_amperage > 0.0)
+        {
+            return Err(format!(
+                "{} could load settings above the soft limits, it is blocked while they are set! \n",
+                command
+            ));
+        }
+        Ok(())
+    }
+
+    // the highest voltage that may be set: the soft limit, but never above the supply
+    pub fn voltage_limit(&self, model_max: f64) -> f64 {
+        if self.max_voltage > 0.0 {
+            self.max_voltage.min(model_max)
+        } else {
+            model_max
+        }
+    }
+
+    // the highest current that may be set
+    pub fn amperage_limit(&self, model_max: f64) -> f64 {
+        if self.max_amperage > 0.0 {
+            self.max_amperage.min(model_max)
+        } else {
+            model_max
+        }
+    }
+
+    // true if the running output has to be watched
+    pub fn watch_output(&self) -> bool {
+        self.max_power > 0.0 || self.max_on_seconds > 0.0
+    }
+}
+
+// remembers since when the output is on, to find the limits that are exceeded
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LimitGuard {
+    on_since: Option<Instant>,
+}
+
+#[cfg(feature = "gui")]
+impl Data for LimitGuard {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl LimitGuard {
+    pub fn new() -> Self {
+        LimitGuard::default()
+    }
+
+    // give the reason if the output has to go off, the measurements are from all channels
+    pub fn check(
+        &mut self,
+        limits: &SafetyLimits,
+        output_on: bool,
+        measurements: &[Measurement],
+    ) -> Option<String> {
+        if !output_on {
+            self.on_since = None;
+            return None;
+        }
+        let on_since = *self.on_since.get_or_insert_with(Instant::now);
+        if limits.max_power > 0.0 {
+            for measurement in measurements {
+                if measurement.power > limits.max_power {
+                    return Some(format!(
+                        "{:.3} W is above the limit of {} W",
+                        measurement.power, limits.max_power
+                    ));
+                }
+            }
+        }
+        if limits.max_on_seconds > 0.0 && on_since.elapsed().as_secs_f64() > limits.max_on_seconds {
+            return Some(format!(
+                "the output was on longer than {} s",
+                limits.max_on_seconds
+            ));
+        }
+        None
+    }
+}
+
+// switches the outputs off when the controlling process did not feed it for too long
+#[derive(Clone)]
+pub struct Watchdog {
+    last_feed: Arc<Mutex<Instant>>,
+    current_ports: Arc<Mutex<Vec<String>>>,
+}
+
+impl Watchdog {
+    // start the watching thread, it runs until the process ends
+    pub fn start(seconds: f64, port_config: &PortConfig) -> Watchdog {
+        let watchdog = Watchdog {
+            last_feed: Arc::new(Mutex::new(Instant::now())),
+            current_ports: Arc::new(Mutex::new(Vec::new())),
+        };
+        let timeout = Duration::from_secs_f64(seconds);
+        let port_config = port_config.clone();
+        let thread_watchdog = watchdog.clone();
+        thread::spawn(move || {
+            // the ports that are not confirmed OFF yet, None as long as it is fed
+            let mut pending_ports: Option<Vec<String>> = None;
+            loop {
+                thread::sleep(timeout / 4);
+                if thread_watchdog.lock_feed().elapsed() <= timeout {
+                    pending_ports = None; // after a new sign of life it watches again
+                    continue;
+                }
+                let current_ports = pending_ports.get_or_insert_with(|| {
+                    error!("Watchdog: no sign of life for {} s, outputs OFF!", seconds);
+                    thread_watchdog
+                        .current_ports
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .clone()
+                });
+                // try again and again until the status byte says OFF
+                current_ports.retain(|current_port| !switch_off(current_port, &port_config));
+            }
+        });
+        watchdog
+    }
+
+    fn lock_feed(&self) -> std::sync::MutexGuard<'_, Instant> {
+        self.last_feed
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // the controlling process is still alive
+    pub fn feed(&self) {
+        *self.lock_feed() = Instant::now();
+    }
+
+    // the ports that are switched off when the watchdog bites
+    pub fn set_ports(&self, current_ports: Vec<String>) {
+        *self
+            .current_ports
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = current_ports;
+    }
+}
+
+// send OFF and read the status back, true when the output really is off
+fn switch_off(current_port: &str, port_config: &PortConfig) -> bool {
+    // the model does not matter for OUT0 and STATUS?
+    let mut supply = Supply::with_model(current_port, port_config, &MODELS[0]);
+    match supply.switch_off() {
+        Ok(()) => {
+            warn!("Watchdog: {}: output is OFF", current_port);
+            true
+        }
+        Err(e) => {
+            error!("Watchdog: {}: {}, trying again", current_port, e);
+            false
+        }
+    }
+}
+
+// for the servers: watch the power and on-time of the supply in the background and start the
+// watchdog, which the server feeds with every request
+pub fn start_safety(supply: &Arc<Mutex<Supply>>) -> Option<Watchdog> {
+    let (limits, current_port, port_config) = {
+        let supply = supply
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        (
+            supply.limits.clone(),
+            supply.current_port.clone(),
+            supply.port_config.clone(),
+        )
+    };
+    if limits.watch_output() {
+        let supply = supply.clone();
+        thread::spawn(move || {
+            let mut guard = LimitGuard::new();
+            loop {
+                thread::sleep(LIMIT_CHECK_INTERVAL);
+                let mut supply = supply
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                match supply.enforce_limits(&mut guard) {
+                    Ok(Some(reason)) => error!("Output forced OFF: {}", reason),
+                    Ok(None) => {}
+                    Err(e) => warn!("Checking the limits failed: {}", e.trim()),
+                }
+            }
+        });
+    }
+    if limits.watchdog_seconds > 0.0 {
+        let watchdog = Watchdog::start(limits.watchdog_seconds, &port_config);
+        watchdog.set_ports(vec![current_port]);
+        Some(watchdog)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> SafetyLimits {
+        SafetyLimits {
+            max_voltage: 5.0,
+            max_amperage: 0.5,
+            ..SafetyLimits::default()
+        }
+    }
+
+    #[test]
+    fn set_and_validate() {
+        let mut limits = SafetyLimits::default();
+        limits.set(MAX_VOLTAGE_KEY, "5.5").unwrap();
+        assert_eq!(limits.get(MAX_VOLTAGE_KEY), "5.5");
+        assert!(limits.set(WATCHDOG_KEY, "1").is_err()); // below the minimum
+        assert!(limits.set(MAX_POWER_KEY, "-1").is_err());
+        assert!(limits.set("max_temperature", "1").is_err());
+        assert_eq!(limits.watchdog_seconds, 0.0); // a bad value changes nothing
+    }
+
+    #[test]
+    fn raw_commands_keep_to_the_limits() {
+        let limits = limits();
+        assert!(limits.check_command("VSET1:05.00").is_ok());
+        assert!(limits.check_command("vset2:12").is_err());
+        assert!(limits.check_command("ISET1:0.600").is_err());
+        assert!(limits.check_command("ISET1:x").is_err());
+        assert!(limits.check_command("RCL1").is_err());
+        assert!(limits.check_command("VSET1?").is_ok());
+        assert!(limits.check_command("OUT1").is_ok());
+        // without limits everything goes to the supply as it is
+        let no_limits = SafetyLimits::default();
+        assert!(no_limits.check_command("VSET1:31").is_ok());
+        assert!(no_limits.check_command("ISET1:x").is_ok());
+        assert!(no_limits.check_command("RCL1").is_ok());
+    }
+}
@@ -0,0 +1,301 @@
+use core::time::Duration;
+use druid::widget::Controller;
+use druid::{Env, Event, EventCtx, TimerToken, Widget};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event as TracingEvent, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::data::{LogEntry, LogFilter, TheAppState};
+use crate::serial::TRAFFIC_TARGET;
+
+// the GUI keeps this many log lines, the oldest are dropped
+pub const LOG_LINES: usize = 1000;
+
+// how often the log view looks for new lines
+const LOG_INTERVAL: Duration = Duration::from_millis(250);
+
+// the log view shows only the newest lines, a list with all of them is too slow;
+// copy and save take all lines of the filter
+const SHOWN_LINES: usize = 300;
+
+// one event of the driver or the GUI
+#[derive(Clone, Debug)]
+struct LogLine {
+    time: String, // UTC, like the terminal log
+    level: Level,
+    traffic: bool, // one command with its answer, see serial.rs
+    text: String,
+}
+
+static LOG_BUFFER: Mutex<VecDeque<LogLine>> = Mutex::new(VecDeque::new());
+static LOG_GENERATION: AtomicU64 = AtomicU64::new(0); // counts up with every new line
+
+// collects the message and the fields of an event into one line
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+// puts every event into the ring buffer of the log view
+struct GuiLayer;
+
+impl<S: Subscriber> Layer<S> for GuiLayer {
+    fn on_event(&self, event: &TracingEvent<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+        let line = LogLine {
+            time: time_of_day(SystemTime::now()),
+            level: *event.metadata().level(),
+            traffic: event.metadata().target() == TRAFFIC_TARGET,
+            text: format!("{}{}", visitor.message, visitor.fields),
+        };
+        let mut buffer = lock_buffer();
+        buffer.push_back(line);
+        while buffer.len() > LOG_LINES {
+            buffer.pop_front();
+        }
+        LOG_GENERATION.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// -q only warnings and errors, nothing for info, -v adds the traffic, -vv everything
+pub fn level_for(verbosity: i8) -> LevelFilter {
+    match verbosity {
+        i8::MIN..=-1 => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+// the terminal gets the events up to the verbosity, the log view always up to debug,
+// so the traffic can be switched on there without a restart
+pub fn init_logging(verbosity: i8) {
+    let terminal = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(level_for(verbosity));
+    let gui = GuiLayer.with_filter(level_for(verbosity).max(LevelFilter::DEBUG));
+    if let Err(e) = tracing_subscriber::registry()
+        .with(terminal)
+        .with(gui)
+        .try_init()
+    {
+        eprintln!("Logging could not be started: {}", e);
+    }
+}
+
+// "hh:mm:ss.mmm" in UTC
+fn time_of_day(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+impl LogFilter {
+    fn matches(&self, line: &LogLine) -> bool {
+        match self {
+            LogFilter::All => true,
+            LogFilter::Info => line.level <= Level::INFO,
+            LogFilter::Errors => line.level <= Level::WARN,
+            LogFilter::Traffic => line.traffic,
+        }
+    }
+}
+
+fn lock_buffer() -> std::sync::MutexGuard<'static, VecDeque<LogLine>> {
+    LOG_BUFFER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// the newest lines of the filter for the log view, the newest first
+fn log_entries(filter: LogFilter) -> Vec<LogEntry> {
+    lock_buffer()
+        .iter()
+        .rev()
+        .filter(|line| filter.matches(line))
+        .take(SHOWN_LINES)
+        .map(|line| LogEntry {
+            time: line.time.clone(),
+            level: line.level.to_string(),
+            text: line.text.clone(),
+        })
+        .collect()
+}
+
+// all lines of the filter as text, the oldest first, for the clipboard and the file
+pub fn log_text(filter: LogFilter) -> String {
+    let mut text = String::new();
+    for line in lock_buffer().iter().filter(|line| filter.matches(line)) {
+        let _ = writeln!(text, "{} {:>5} {}", line.time, line.level, line.text);
+    }
+    text
+}
+
+pub fn clear_log() {
+    lock_buffer().clear();
+    LOG_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+// sits on the log view and copies new lines into the app state
+pub struct LogController {
+    timer_id: TimerToken,
+    generation: u64,
+    filter: LogFilter,
+}
+
+impl LogController {
+    pub fn new() -> Self {
+        LogController {
+            timer_id: TimerToken::INVALID,
+            generation: u64::MAX, // fill the view at the first tick
+            filter: LogFilter::Info,
+        }
+    }
+}
+
+impl<W: Widget<TheAppState>> Controller<TheAppState, W> for LogController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut TheAppState,
+        env: &Env,
+    ) {
+        match event {
+            Event::WindowConnected => {
+                self.timer_id = ctx.request_timer(LOG_INTERVAL);
+            }
+            Event::Timer(id) if *id == self.timer_id => {
+                let generation = LOG_GENERATION.load(Ordering::Relaxed);
+                if generation != self.generation || data.log_filter != self.filter {
+                    // only build the list again when something changed
+                    self.generation = generation;
+                    self.filter = data.log_filter;
+                    data.log_entries = Arc::new(log_entries(data.log_filter));
+                }
+                self.timer_id = ctx.request_timer(LOG_INTERVAL); // and again
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{debug, info, warn};
+
+    // the ring buffer is shared, the tests that fill it take turns
+    static BUFFER_TEST: Mutex<()> = Mutex::new(());
+
+    // run `log` with only the GUI layer, on an empty buffer
+    fn with_gui_layer(log: impl FnOnce()) -> std::sync::MutexGuard<'static, ()> {
+        let turn = BUFFER_TEST
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        clear_log();
+        let subscriber = tracing_subscriber::registry().with(GuiLayer);
+        tracing::subscriber::with_default(subscriber, log);
+        turn
+    }
+
+    #[test]
+    fn verbosity_to_level() {
+        assert_eq!(level_for(-1), LevelFilter::WARN);
+        assert_eq!(level_for(0), LevelFilter::INFO);
+        assert_eq!(level_for(1), LevelFilter::DEBUG);
+        assert_eq!(level_for(2), LevelFilter::TRACE);
+    }
+
+    #[test]
+    fn the_oldest_lines_are_dropped() {
+        let _turn = with_gui_layer(|| {
+            for number in 0..LOG_LINES + 5 {
+                info!("line {}", number);
+            }
+        });
+        let buffer = lock_buffer();
+        assert_eq!(buffer.len(), LOG_LINES);
+        assert_eq!(buffer.front().unwrap().text, "line 5");
+        assert_eq!(
+            buffer.back().unwrap().text,
+            format!("line {}", LOG_LINES + 4)
+        );
+    }
+
+    #[test]
+    fn the_view_shows_the_newest_lines_first() {
+        let _turn = with_gui_layer(|| {
+            for number in 0..SHOWN_LINES + 10 {
+                info!("line {}", number);
+            }
+        });
+        let entries = log_entries(LogFilter::All);
+        assert_eq!(entries.len(), SHOWN_LINES);
+        assert_eq!(entries[0].text, format!("line {}", SHOWN_LINES + 9));
+        // copy and save take all lines
+        assert_eq!(log_text(LogFilter::All).lines().count(), SHOWN_LINES + 10);
+    }
+
+    #[test]
+    fn filters_and_fields() {
+        let _turn = with_gui_layer(|| {
+            info!(port = "SIMULATED", "opened");
+            warn!("no answer");
+            debug!(target: TRAFFIC_TARGET, port = "SIMULATED", "Send ID command");
+            debug!("details");
+        });
+        assert_eq!(log_entries(LogFilter::All).len(), 4);
+        assert_eq!(log_entries(LogFilter::Info).len(), 2);
+        let errors = log_entries(LogFilter::Errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].text, "no answer");
+        assert_eq!(errors[0].level, "WARN");
+        let traffic = log_entries(LogFilter::Traffic);
+        assert_eq!(traffic.len(), 1);
+        assert_eq!(traffic[0].text, "Send ID command port=SIMULATED");
+        assert!(log_text(LogFilter::Info).contains("opened port=SIMULATED"));
+    }
+
+    #[test]
+    fn time_of_day_in_utc() {
+        let time = UNIX_EPOCH + Duration::from_millis(86_400_000 + 3_661_500);
+        assert_eq!(time_of_day(time), "01:01:01.500");
+    }
+}
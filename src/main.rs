@@ -1,32 +1,213 @@
 use druid::{AppLauncher, PlatformError, WindowDesc};
-use serialport::SerialPortInfo;
 
+mod cli;
 mod data;
 mod gui;
-mod serial;
-use data::TheAppState;
+mod http;
+mod logging;
+mod metrics;
+mod monitor;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod plot;
+mod scpi;
+use kd3005p_rs::{
+    capture, charger, driver, energy, limits, memories, model, port_config, profiles, protocol,
+    rules, sequence, serial, settings, simulator, stats, sweep,
+};
 
-static WINDOW_WIDTH: f64 = 450.0;
-static WINDOW_HEIGHT: f64 = 450.0;
+use data::{
+    ConsoleForm, Instrument, LogFilter, PortConfigForm, ProfileForm, SweepForm, TheAppState,
+};
+use limits::Watchdog;
+use std::sync::Arc;
+use std::time::Duration;
 
 fn main() -> Result<(), PlatformError> {
+    // read the command line
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cli_options = match cli::parse_args(&args) {
+        Ok(cli_options) => cli_options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if cli_options.show_help {
+        print!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    // the driver logs through tracing, to the terminal and to the log view of the GUI
+    logging::init_logging(cli_options.verbosity);
+    if let Some(warning) = &cli_options.settings_warning {
+        tracing::warn!("{}", warning);
+    }
+
+    // record the traffic of all modes, the GUI too
+    if let Some(path) = &cli_options.capture_file {
+        if let Err(e) = capture::start_capture(path) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        tracing::info!("Recording all serial traffic to {}", path);
+    }
+
+    // one measurement on the command line
+    if let (Some(samples), Some(port)) = (cli_options.measure_samples, &cli_options.port) {
+        if let Err(e) = run_measure(port, samples, &cli_options) {
+            eprintln!("{}", e.trim());
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // read the memories of the supply, after storing profiles in them
+    if let (true, Some(port)) = (cli_options.memories, &cli_options.port) {
+        if let Err(e) = run_memories(port, &cli_options) {
+            eprintln!("{}", e.trim());
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // daemon mode, no GUI at all
+    if let (Some(address), Some(port)) = (&cli_options.scpi_address, &cli_options.port) {
+        if let Err(e) =
+            scpi::run_scpi_server(address, port, &cli_options.port_config, &cli_options.limits)
+        {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let (Some(address), Some(port)) = (&cli_options.http_address, &cli_options.port) {
+        if let Err(e) =
+            http::run_http_server(address, port, &cli_options.port_config, &cli_options.limits)
+        {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let (Some(broker), Some(port)) = (&cli_options.mqtt_broker, &cli_options.port) {
+        run_mqtt(broker, port, &cli_options);
+        return Ok(());
+    }
+
+    // the first supply starts on the port from the command line or the settings, if there is one
+    let mut first_instrument =
+        Instrument::new(1, "Supply 1", &cli_options.limits, &cli_options.settings);
+    if let Some(port) = &cli_options.port {
+        first_instrument.current_port = port.to_string();
+    }
+
+    // the watchdog switches everything off when the GUI hangs
+    let watchdog = if cli_options.limits.watchdog_seconds > 0.0 {
+        Some(Watchdog::start(
+            cli_options.limits.watchdog_seconds,
+            &cli_options.port_config,
+        ))
+    } else {
+        None
+    };
+
     // Initialize the AppState
     let my_app_state = TheAppState {
-        current_port: "None selected".to_string(),
-        port_open: false,
-        current_voltage: "12.00".to_string(),
-        current_amperage: "1.000".to_string(),
+        instruments: Arc::new(vec![first_instrument]),
+        next_instrument_id: 1,
         output_info: "Welcome to KD3005P-rs! \n".to_string(),
-        the_ports: Vec::<SerialPortInfo>::new(),
+        sequence_text: String::new(),
+        sequence_running: false,
+        port_config_form: PortConfigForm::from_config(&cli_options.port_config),
+        port_config: cli_options.port_config.clone(),
+        limits: cli_options.limits.clone(),
+        settings: cli_options.settings.clone(),
+        settings_path: cli_options.settings_path.clone(),
+        sweep_form: SweepForm::new(),
+        console_form: ConsoleForm::new(),
+        profile_form: ProfileForm::new(),
+        log_entries: Arc::new(Vec::new()),
+        log_filter: LogFilter::Info,
+        log_path: "kd3005p.log".to_string(),
+        log_message: String::new(),
+        watchdog: watchdog.clone(),
     };
 
-    // Window builder. We set title and size
-    let main_window = WindowDesc::new(gui::ui_builder)
+    // Window builder. We set title and size, the size of the last run
+    let main_window = WindowDesc::new(move || gui::ui_builder(watchdog))
         .title("Korad KD3005P-rs")
-        .window_size((WINDOW_WIDTH, WINDOW_HEIGHT));
+        .window_size((
+            cli_options.settings.window_width,
+            cli_options.settings.window_height,
+        ));
+    let main_window_id = main_window.id;
 
-    // Run the app
+    // Run the app, the settings are saved when the main window is closed
     AppLauncher::with_window(main_window)
-        .use_simple_logger() // Neat!
+        .delegate(gui::SettingsDelegate::new(main_window_id))
         .launch(my_app_state)
 }
+
+// read the output several times and print the statistics
+fn run_measure(port: &str, samples: usize, cli_options: &cli::CliOptions) -> Result<(), String> {
+    let supply = driver::Supply::open(port, &cli_options.port_config)?;
+    let statistics = supply.measure_statistics(
+        cli_options.measure_channel,
+        samples,
+        Duration::from_millis(cli_options.measure_interval_ms),
+    )?;
+    print!(
+        "{} CH{}: {}",
+        supply.model.name,
+        cli_options.measure_channel,
+        statistics.describe()
+    );
+    Ok(())
+}
+
+// store the profiles of --store in the memories, then print all memories
+fn run_memories(port: &str, cli_options: &cli::CliOptions) -> Result<(), String> {
+    // all names must be known before anything is sent
+    let mut assignments = Vec::new();
+    for (slot, name) in &cli_options.memory_assignments {
+        match cli_options.settings.find_profile(name) {
+            Some(profile) => assignments.push((*slot, profile.clone())),
+            None => return Err(format!("There is no profile '{}' in the settings", name)),
+        }
+    }
+    let mut supply = driver::Supply::open(port, &cli_options.port_config)?;
+    supply.limits = cli_options.limits.clone();
+    let mut my_output = String::new();
+    if !assignments.is_empty() {
+        memories::write_memories(&mut supply, &assignments, &mut my_output)?;
+    }
+    memories::read_memories(&mut supply, &mut my_output)?;
+    print!("{} {}\n{}", supply.model.name, port, my_output);
+    Ok(())
+}
+
+#[cfg(feature = "mqtt")]
+fn run_mqtt(broker: &str, port: &str, cli_options: &cli::CliOptions) {
+    let settings = mqtt::MqttSettings {
+        broker: broker.to_string(),
+        topic_prefix: cli_options.mqtt_topic.clone(),
+        interval_ms: cli_options.mqtt_interval_ms,
+    };
+    if let Err(e) = mqtt::run_mqtt_bridge(
+        &settings,
+        port,
+        &cli_options.port_config,
+        &cli_options.limits,
+    ) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+// MQTT is optional, so it has to be asked for when building
+#[cfg(not(feature = "mqtt"))]
+fn run_mqtt(_broker: &str, _port: &str, _cli_options: &cli::CliOptions) {
+    eprintln!("This build has no MQTT, build it with: cargo build --features mqtt");
+    std::process::exit(1);
+}
@@ -0,0 +1,226 @@
+use core::time::Duration;
+
+use serde::Serialize;
+
+use crate::driver::Supply;
+use crate::profiles::Profile;
+use crate::protocol::*;
+use crate::serial::{send_command, MEMORY_SLOTS};
+
+// the supply needs a moment after RCL and SAV before it takes the next command
+const MEMORY_SETTLE: Duration = Duration::from_millis(50);
+
+// the memories hold the settings of channel 1
+const MEMORY_CHANNEL: u8 = 1;
+
+// the settings of one memory M1-M5
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct MemorySlot {
+    pub slot: u8,
+    pub voltage: f64,
+    pub amperage: f64,
+}
+
+impl MemorySlot {
+    pub fn describe(&self) -> String {
+        format!(
+            "M{}: {:.2} V / {:.3} A \n",
+            self.slot, self.voltage, self.amperage
+        )
+    }
+}
+
+// the settings and the output before the memories were touched
+struct SupplyState {
+    voltage: f64,
+    amperage: f64,
+    output_on: bool,
+}
+
+// the output goes off first, so the DUT never sees the settings of a memory
+fn save_state(supply: &mut Supply) -> Result<SupplyState, String> {
+    let state = SupplyState {
+        voltage: supply.voltage_setting(MEMORY_CHANNEL)?,
+        amperage: supply.amperage_setting(MEMORY_CHANNEL)?,
+        output_on: supply.status()?.output_on(),
+    };
+    if state.output_on {
+        supply.set_output(false)?;
+    }
+    Ok(state)
+}
+
+// the old settings first, then the output on again if it was on; the settings are sent as
+// they were read, without the soft limits: they were on the supply before, and a limit that is
+// lower now must not leave the settings of a memory behind
+fn restore_state(
+    supply: &mut Supply,
+    state: &SupplyState,
+    my_output: &mut String,
+) -> Result<(), String> {
+    // both are tried, one failed setting is no reason to skip the other
+    let voltage_result = set_voltage_command(supply.model, MEMORY_CHANNEL, state.voltage)
+        .and_then(|command| send_command(&supply.current_port, &supply.port_config, &command));
+    let amperage_result = set_amperage_command(supply.model, MEMORY_CHANNEL, state.amperage)
+        .and_then(|command| send_command(&supply.current_port, &supply.port_config, &command));
+    match (voltage_result, amperage_result) {
+        (Ok(_), Ok(_)) => {}
+        (Err(e), Ok(_)) => return Err(format!("Could not put back the voltage: {}", e)),
+        (Ok(_), Err(e)) => return Err(format!("Could not put back the current: {}", e)),
+        (Err(voltage_error), Err(amperage_error)) => {
+            return Err(format!(
+                "Could not put back the voltage: {}, nor the current: {}",
+                voltage_error, amperage_error
+            ))
+        }
+    }
+    // the output stays off when the old settings are not back, or they are above the soft limits
+    if state.output_on {
+        supply.set_output(true)?;
+    }
+    my_output.push_str(&format!(
+        "Settings back at {:.2} V / {:.3} A, output {} \n",
+        state.voltage,
+        state.amperage,
+        if state.output_on { "ON" } else { "OFF" }
+    ));
+    Ok(())
+}
+
+// the error of the work on the memories comes first, the one of putting the settings back
+// is added to it
+fn with_restore_error(
+    result: Result<(), String>,
+    restored: Result<(), String>,
+) -> Result<(), String> {
+    match (result, restored) {
+        (Err(e), Err(restore_error)) => Err(format!("{}; {}", e, restore_error)),
+        (Err(e), Ok(())) | (Ok(()), Err(e)) => Err(e),
+        (Ok(()), Ok(())) => Ok(()),
+    }
+}
+
+// recall every memory and read its settings back, the supply is left as it was
+pub fn read_memories(
+    supply: &mut Supply,
+    my_output: &mut String,
+) -> Result<Vec<MemorySlot>, String> {
+    let state = save_state(supply)?;
+    let mut slots = Vec::new();
+    let result = read_slots(supply, &mut slots);
+    // no matter how the reading ended
+    let mut restore_output = String::new();
+    let restored = restore_state(supply, &state, &mut restore_output);
+    with_restore_error(result, restored)?;
+    for slot in &slots {
+        my_output.push_str(&slot.describe());
+    }
+    my_output.push_str(&restore_output);
+    Ok(slots)
+}
+
+fn read_slots(supply: &mut Supply, slots: &mut Vec<MemorySlot>) -> Result<(), String> {
+    for slot in 1..=MEMORY_SLOTS {
+        supply.recall(slot)?;
+        std::thread::sleep(MEMORY_SETTLE);
+        slots.push(MemorySlot {
+            slot,
+            voltage: supply.voltage_setting(MEMORY_CHANNEL)?,
+            amperage: supply.amperage_setting(MEMORY_CHANNEL)?,
+        });
+    }
+    Ok(())
+}
+
+// store profiles in memories, e.g. [(1, esp32), (2, led_strip)]; every profile is checked
+// against the model and the soft limits before the first memory is changed, and the
+// supply is left as it was
+pub fn write_memories(
+    supply: &mut Supply,
+    assignments: &[(u8, Profile)],
+    my_output: &mut String,
+) -> Result<(), String> {
+    for (number, (slot, profile)) in assignments.iter().enumerate() {
+        check_slot(*slot)?;
+        if assignments[..number].iter().any(|(other, _)| other == slot) {
+            return Err(format!("M{} is given twice", slot));
+        }
+        profile.validate()?;
+        supply.limits.check_voltage(profile.voltage)?;
+        supply.limits.check_amperage(profile.amperage)?;
+        supply.model.check_voltage(profile.voltage)?;
+        supply.model.check_amperage(profile.amperage)?;
+    }
+    let state = save_state(supply)?;
+    let result = write_slots(supply, assignments, my_output);
+    let restored = restore_state(supply, &state, my_output);
+    with_restore_error(result, restored)
+}
+
+fn write_slots(
+    supply: &mut Supply,
+    assignments: &[(u8, Profile)],
+    my_output: &mut String,
+) -> Result<(), String> {
+    for (slot, profile) in assignments {
+        supply.set_voltage(MEMORY_CHANNEL, profile.voltage)?;
+        supply.set_amperage(MEMORY_CHANNEL, profile.amperage)?;
+        supply.save(*slot)?;
+        std::thread::sleep(MEMORY_SETTLE);
+        my_output.push_str(&format!(
+            "M{}: '{}' stored, {:.2} V / {:.3} A \n",
+            slot, profile.name, profile.voltage, profile.amperage
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port_config::PortConfig;
+
+    fn open(current_port: &str) -> Supply {
+        Supply::open(current_port, &PortConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn read_memories_puts_the_settings_back() {
+        let mut supply = open("SIMULATED:KD3005P read memories");
+        supply.set_voltage(1, 5.0).unwrap();
+        supply.set_output(true).unwrap();
+        let mut my_output = String::new();
+        let slots = read_memories(&mut supply, &mut my_output).unwrap();
+        assert_eq!(slots.len(), MEMORY_SLOTS as usize);
+        assert_eq!(supply.voltage_setting(1).unwrap(), 5.0);
+        assert!(supply.status().unwrap().output_on());
+    }
+
+    #[test]
+    fn the_old_settings_come_back_even_above_a_new_limit() {
+        let mut supply = open("SIMULATED:KD3005P restore above limit");
+        supply.set_voltage(1, 5.0).unwrap();
+        let mut profile = Profile::new("low");
+        profile.voltage = 1.0;
+        profile.amperage = 0.1;
+        supply.limits.max_voltage = 3.3; // lower than the setting on the supply
+        let mut my_output = String::new();
+        write_memories(&mut supply, &[(1, profile)], &mut my_output).unwrap();
+        assert_eq!(supply.voltage_setting(1).unwrap(), 5.0);
+        supply.recall(1).unwrap();
+        assert_eq!(supply.voltage_setting(1).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn both_errors_are_reported() {
+        assert_eq!(
+            with_restore_error(Err("a".to_string()), Err("b".to_string())),
+            Err("a; b".to_string())
+        );
+        assert_eq!(
+            with_restore_error(Ok(()), Err("b".to_string())),
+            Err("b".to_string())
+        );
+        assert_eq!(with_restore_error(Ok(()), Ok(())), Ok(()));
+    }
+}
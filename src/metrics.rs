@@ -0,0 +1,183 @@
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use tracing::warn;
+
+use crate::driver::Supply;
+use crate::serial::{SERIAL_ERRORS, SERIAL_RETRIES, SERIAL_TIMEOUTS};
+
+// one metric in the Prometheus text format, values with their labels
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str, // gauge or counter
+    samples: Vec<(String, f64)>,
+}
+
+impl Family {
+    fn new(name: &'static str, kind: &'static str, help: &'static str) -> Self {
+        Family {
+            name,
+            help,
+            kind,
+            samples: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, labels: String, value: f64) {
+        self.samples.push((labels, value));
+    }
+
+    fn add_channel(&mut self, channel: u8, value: f64) {
+        self.add(format!("{{channel=\"{}\"}}", channel), value);
+    }
+
+    fn write_to(&self, my_output: &mut String) {
+        if self.samples.is_empty() {
+            return; // nothing known, e.g. OVP before it was switched
+        }
+        let _ = writeln!(my_output, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(my_output, "# TYPE {} {}", self.name, self.kind);
+        for (labels, value) in &self.samples {
+            let _ = writeln!(my_output, "{}{} {}", self.name, labels, value);
+        }
+    }
+}
+
+fn flag(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+// read the supply and give all metrics for GET /metrics
+pub fn render_metrics(supply: &Supply) -> String {
+    let mut up = Family::new(
+        "kd3005p_up",
+        "gauge",
+        "1 if the supply answered this scrape.",
+    );
+    let mut voltage_setting = Family::new(
+        "kd3005p_voltage_setting_volts",
+        "gauge",
+        "Set voltage of the channel.",
+    );
+    let mut current_setting = Family::new(
+        "kd3005p_current_setting_amperes",
+        "gauge",
+        "Set current limit of the channel.",
+    );
+    let mut voltage = Family::new(
+        "kd3005p_voltage_volts",
+        "gauge",
+        "Measured output voltage of the channel.",
+    );
+    let mut current = Family::new(
+        "kd3005p_current_amperes",
+        "gauge",
+        "Measured output current of the channel.",
+    );
+    let mut power = Family::new(
+        "kd3005p_power_watts",
+        "gauge",
+        "Measured output power of the channel.",
+    );
+    let mut constant_current = Family::new(
+        "kd3005p_constant_current",
+        "gauge",
+        "1 if the channel is in CC mode, 0 in CV mode.",
+    );
+    let mut output_on = Family::new("kd3005p_output_on", "gauge", "1 if the output is on.");
+    let mut ovp_on = Family::new(
+        "kd3005p_ovp_enabled",
+        "gauge",
+        "1 if over voltage protection was switched on.",
+    );
+    let mut ocp_on = Family::new(
+        "kd3005p_ocp_enabled",
+        "gauge",
+        "1 if over current protection was switched on.",
+    );
+    let mut tripped = Family::new(
+        "kd3005p_protection_tripped",
+        "gauge",
+        "1 if the output went off without being switched off.",
+    );
+
+    let mut read_supply = || -> Result<(), String> {
+        let status = supply.status()?;
+        for channel in 1..=supply.model.channels {
+            // read everything first, so a failed scrape gives no half channel
+            let set_voltage = supply.voltage_setting(channel)?;
+            let set_current = supply.amperage_setting(channel)?;
+            let measurement = supply.measure(channel)?;
+            voltage_setting.add_channel(channel, set_voltage);
+            current_setting.add_channel(channel, set_current);
+            voltage.add_channel(channel, measurement.voltage);
+            current.add_channel(channel, measurement.amperage);
+            power.add_channel(channel, measurement.power);
+            constant_current.add_channel(channel, flag(!status.constant_voltage(channel)));
+        }
+        output_on.add(String::new(), flag(status.output_on()));
+        tripped.add(String::new(), flag(supply.protection_tripped(&status)));
+        Ok(())
+    };
+    match read_supply() {
+        Ok(()) => up.add(String::new(), 1.0),
+        Err(e) => {
+            warn!("Reading for the metrics failed: {}", e.trim());
+            up.add(String::new(), 0.0);
+        }
+    }
+    if let Some(on) = supply.ovp_on {
+        ovp_on.add(String::new(), flag(on));
+    }
+    if let Some(on) = supply.ocp_on {
+        ocp_on.add(String::new(), flag(on));
+    }
+
+    // the transport counters count on all ports since the start
+    let mut serial_errors = Family::new(
+        "kd3005p_serial_errors_total",
+        "counter",
+        "Failed port openings, reads and writes.",
+    );
+    serial_errors.add(String::new(), SERIAL_ERRORS.load(Ordering::Relaxed) as f64);
+    let mut serial_retries = Family::new(
+        "kd3005p_serial_retries_total",
+        "counter",
+        "Queries sent again after getting no answer.",
+    );
+    serial_retries.add(String::new(), SERIAL_RETRIES.load(Ordering::Relaxed) as f64);
+    let mut serial_timeouts = Family::new(
+        "kd3005p_serial_timeouts_total",
+        "counter",
+        "Queries that got no answer before the timeout.",
+    );
+    serial_timeouts.add(
+        String::new(),
+        SERIAL_TIMEOUTS.load(Ordering::Relaxed) as f64,
+    );
+
+    let mut my_output = String::new();
+    for family in [
+        &up,
+        &voltage_setting,
+        &current_setting,
+        &voltage,
+        &current,
+        &power,
+        &constant_current,
+        &output_on,
+        &ovp_on,
+        &ocp_on,
+        &tripped,
+        &serial_errors,
+        &serial_retries,
+        &serial_timeouts,
+    ] {
+        family.write_to(&mut my_output);
+    }
+    my_output
+}
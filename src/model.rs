@@ -0,0 +1,190 @@
+// what a supply of the Korad family can do, all of them speak the same protocol
+#[derive(Clone, Debug, PartialEq)]
+pub struct Model {
+    pub name: &'static str,
+    pub id_patterns: &'static [&'static str], // parts of the *IDN? answer, without spaces
+    pub channels: u8,
+    pub max_voltage: f64,
+    pub max_amperage: f64,
+    pub ovp_ocp: bool,  // supports over voltage / over current protection
+    pub tracking: bool, // supports series / parallel tracking of the channels
+}
+
+// the one this tool was written for, also used if a supply is not known
+pub const KD3005P: Model = Model {
+    name: "KD3005P",
+    id_patterns: &["KD3005P"],
+    channels: 1,
+    max_voltage: 30.0,
+    max_amperage: 5.0,
+    ovp_ocp: true,
+    tracking: false,
+};
+
+// the model registry, the first match on the *IDN? answer wins
+pub const MODELS: &[Model] = &[
+    KD3005P,
+    Model {
+        name: "KA3005P",
+        id_patterns: &["KA3005P"],
+        channels: 1,
+        max_voltage: 31.0,
+        max_amperage: 5.1,
+        ovp_ocp: true,
+        tracking: false,
+    },
+    Model {
+        name: "KD3305P",
+        id_patterns: &["KD3305P"],
+        channels: 2,
+        max_voltage: 30.0,
+        max_amperage: 5.0,
+        ovp_ocp: true,
+        tracking: true,
+    },
+    Model {
+        name: "KA3305P",
+        id_patterns: &["KA3305P"],
+        channels: 2,
+        max_voltage: 31.0,
+        max_amperage: 5.1,
+        ovp_ocp: true,
+        tracking: true,
+    },
+    Model {
+        name: "Tenma 72-2535",
+        id_patterns: &["72-2535"],
+        channels: 1,
+        max_voltage: 30.0,
+        max_amperage: 3.0,
+        ovp_ocp: true,
+        tracking: false,
+    },
+    Model {
+        name: "Tenma 72-2540",
+        id_patterns: &["72-2540"],
+        channels: 1,
+        max_voltage: 30.0,
+        max_amperage: 5.0,
+        ovp_ocp: true,
+        tracking: false,
+    },
+    Model {
+        name: "Tenma 72-2545",
+        id_patterns: &["72-2545"],
+        channels: 1,
+        max_voltage: 60.0,
+        max_amperage: 2.0,
+        ovp_ocp: true,
+        tracking: false,
+    },
+    Model {
+        name: "Tenma 72-2550",
+        id_patterns: &["72-2550"],
+        channels: 1,
+        max_voltage: 60.0,
+        max_amperage: 3.0,
+        ovp_ocp: true,
+        tracking: false,
+    },
+    Model {
+        name: "Velleman PS3005D",
+        id_patterns: &["PS3005D"],
+        channels: 1,
+        max_voltage: 30.0,
+        max_amperage: 5.0,
+        ovp_ocp: true,
+        tracking: false,
+    },
+];
+
+// pick the model from the *IDN? answer, e.g. "KORAD KD3005P V2.0" or "VELLEMANPS3005DV2.0"
+pub fn identify(id_answer: &str) -> Option<&'static Model> {
+    let id_answer: String = id_answer
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    MODELS.iter().find(|model| {
+        model
+            .id_patterns
+            .iter()
+            .any(|pattern| id_answer.contains(pattern))
+    })
+}
+
+// look a model up by the name stored in the instrument
+pub fn find_model(name: &str) -> &'static Model {
+    MODELS
+        .iter()
+        .find(|model| model.name == name)
+        .unwrap_or(&MODELS[0])
+}
+
+impl Model {
+    pub fn describe(&self) -> String {
+        format!(
+            "{}: {} channel(s), {} V / {} A{}{} \n",
+            self.name,
+            self.channels,
+            self.max_voltage,
+            self.max_amperage,
+            if self.ovp_ocp { ", OVP/OCP" } else { "" },
+            if self.tracking { ", tracking" } else { "" },
+        )
+    }
+
+    // check a voltage setting before it is sent to the supply
+    pub fn check_voltage(&self, value: f64) -> Result<(), String> {
+        check_range(value, self.max_voltage, "V", self.name)
+    }
+
+    // check an amperage setting before it is sent to the supply
+    pub fn check_amperage(&self, value: f64) -> Result<(), String> {
+        check_range(value, self.max_amperage, "A", self.name)
+    }
+}
+
+fn check_range(value: f64, max: f64, unit: &str, name: &str) -> Result<(), String> {
+    if (0.0..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} {} is out of range for the {} (0 - {} {})! \n",
+            value, unit, name, max, unit
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identify_from_the_id_answer() {
+        assert_eq!(identify("KORAD KD3005P V2.0").unwrap().name, "KD3005P");
+        assert_eq!(identify("korad ka3305p v4.2").unwrap().name, "KA3305P");
+        assert_eq!(
+            identify("VELLEMANPS3005DV2.0").unwrap().name,
+            "Velleman PS3005D"
+        );
+        assert_eq!(identify("TENMA 72-2545 V2.1").unwrap().channels, 1);
+        assert_eq!(identify("RIGOL DP832"), None);
+        assert_eq!(identify(""), None);
+    }
+
+    #[test]
+    fn find_model_falls_back_to_the_kd3005p() {
+        assert_eq!(find_model("KD3305P").channels, 2);
+        assert_eq!(find_model("nothing").name, KD3005P.name);
+    }
+
+    #[test]
+    fn settings_are_checked_against_the_model() {
+        assert!(KD3005P.check_voltage(30.0).is_ok());
+        assert!(KD3005P.check_voltage(30.01).is_err());
+        assert!(KD3005P.check_amperage(-0.1).is_err());
+        assert!(KD3005P.check_amperage(f64::NAN).is_err());
+        assert!(find_model("KA3005P").check_voltage(31.0).is_ok());
+    }
+}
@@ -0,0 +1,433 @@
+use core::time::Duration;
+use druid::widget::Controller;
+use druid::{Data, Env, Event, EventCtx, ExtEventSink, Selector, Target, TimerToken, Widget};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, error, warn};
+
+use crate::data::*;
+use crate::driver::{Measurement, Supply};
+use crate::limits::Watchdog;
+use crate::port_config::PortConfig;
+use crate::rules::*;
+
+// the rules are checked this often between the polls, so "for 200 ms" is not counted in whole
+// poll intervals; a supply that answers slower just gets fewer checks
+const RULES_INTERVAL: Duration = Duration::from_millis(100);
+
+// the polling thread gives back the instruments it worked on
+const POLL_DONE: Selector<Vec<Instrument>> = Selector::new("kd3005p.poll-done");
+
+// what the polling thread gets: copies of the instruments to poll and how
+struct PollJob {
+    instruments: Vec<Instrument>,
+    port_config: PortConfig,
+    poll_interval: Duration,
+    full_poll: bool, // false: only the rules, between two polls
+}
+
+// sits on the main window and polls all instruments with "Live" checked, the serial traffic
+// runs on a thread of its own, so a slow or missing supply does not hold up the window;
+// every tick also tells the watchdog that the GUI is alive
+pub struct MonitorController {
+    timer_id: TimerToken,
+    watchdog: Option<Watchdog>,
+    jobs: Option<Sender<PollJob>>, // to the polling thread, started with the window
+    polling: Option<Vec<Instrument>>, // the instruments as they were when the job was sent
+    last_poll: Option<Instant>,
+}
+
+impl MonitorController {
+    pub fn new(watchdog: Option<Watchdog>) -> Self {
+        MonitorController {
+            timer_id: TimerToken::INVALID,
+            watchdog,
+            jobs: None,
+            polling: None,
+            last_poll: None,
+        }
+    }
+}
+
+impl<W: Widget<TheAppState>> Controller<TheAppState, W> for MonitorController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut TheAppState,
+        env: &Env,
+    ) {
+        match event {
+            Event::WindowConnected => {
+                self.jobs = Some(start_polling_thread(ctx.get_external_handle()));
+                // start polling as soon as the window is there
+                self.timer_id = ctx.request_timer(RULES_INTERVAL);
+            }
+            Event::Timer(id) if *id == self.timer_id => {
+                if let Some(watchdog) = &self.watchdog {
+                    watchdog.feed();
+                    // only the supplies with a port, the others can not be switched off
+                    watchdog.set_ports(
+                        data.instruments
+                            .iter()
+                            .filter(|my_instrument| my_instrument.has_port())
+                            .map(|my_instrument| my_instrument.current_port.clone())
+                            .collect(),
+                    );
+                }
+                // one job at a time, a supply that takes longer just gets fewer polls; a full
+                // poll as often as the settings say, only the rules in between
+                if self.polling.is_none() {
+                    let full_poll = match self.last_poll {
+                        Some(last_poll) => last_poll.elapsed() >= data.settings.poll_interval(),
+                        None => true,
+                    };
+                    self.send_job(data, full_poll);
+                }
+                self.timer_id = ctx.request_timer(RULES_INTERVAL);
+                // and again
+            }
+            Event::Command(cmd) if cmd.is(POLL_DONE) => {
+                let polled: &Vec<Instrument> = cmd.get_unchecked(POLL_DONE);
+                if let Some(before) = self.polling.take() {
+                    take_over_polled(data, &before, polled);
+                }
+                ctx.set_handled();
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+impl MonitorController {
+    // give copies of the instruments with a port to the polling thread
+    fn send_job(&mut self, data: &TheAppState, full_poll: bool) {
+        let jobs = match &self.jobs {
+            Some(jobs) => jobs,
+            None => return,
+        };
+        let instruments: Vec<Instrument> = data
+            .instruments
+            .iter()
+            // no supply picked yet, every command would fail
+            .filter(|my_instrument| my_instrument.has_port())
+            // the sweep has the port, it checks the power itself
+            .filter(|my_instrument| {
+                !(data.sweep_form.running && data.sweep_form.instrument_id == my_instrument.id)
+            })
+            // between the polls only the ones with rules to check
+            .filter(|my_instrument| {
+                full_poll || (my_instrument.watch_rules && !my_instrument.rules.rules.is_empty())
+            })
+            .cloned()
+            .collect();
+        if full_poll {
+            self.last_poll = Some(Instant::now());
+        }
+        if instruments.is_empty() {
+            return;
+        }
+        let job = PollJob {
+            instruments: instruments.clone(),
+            port_config: data.port_config.clone(),
+            poll_interval: data.settings.poll_interval(),
+            full_poll,
+        };
+        if jobs.send(job).is_ok() {
+            self.polling = Some(instruments);
+        }
+    }
+}
+
+// the thread that does all the serial traffic of the polling, it ends with the window
+fn start_polling_thread(sink: ExtEventSink) -> Sender<PollJob> {
+    let (jobs, next_jobs) = channel::<PollJob>();
+    std::thread::spawn(move || {
+        for mut job in next_jobs {
+            for my_instrument in job.instruments.iter_mut() {
+                if job.full_poll {
+                    poll_instrument(my_instrument, &job.port_config, job.poll_interval);
+                } else {
+                    check_rules(my_instrument, &job.port_config, &[]);
+                }
+            }
+            if sink
+                .submit_command(POLL_DONE, job.instruments, Target::Auto)
+                .is_err()
+            {
+                break; // the window is gone
+            }
+        }
+    });
+    jobs
+}
+
+// everything that is done with one supply per poll
+fn poll_instrument(
+    my_instrument: &mut Instrument,
+    port_config: &PortConfig,
+    poll_interval: Duration,
+) {
+    watch_limits(my_instrument, port_config);
+    step_chargers(my_instrument, port_config, poll_interval);
+    let mut live_readings = Vec::new();
+    if my_instrument.live {
+        live_readings = read_live_values(my_instrument, port_config, poll_interval);
+    }
+    // the rules look at the same readings, no extra traffic when "Live" is on
+    if my_instrument.watch_rules {
+        check_rules(my_instrument, port_config, &live_readings);
+    }
+}
+
+// take over what the polling changed, but nothing the user changed in the meantime (e.g. new
+// rules, a reset of the energy counter or another port), that was done after the reading
+fn take_over_polled(data: &mut TheAppState, before: &[Instrument], polled: &[Instrument]) {
+    for (old, new) in before.iter().zip(polled) {
+        let my_instrument = match Arc::make_mut(&mut data.instruments)
+            .iter_mut()
+            .find(|my_instrument| my_instrument.id == old.id)
+        {
+            Some(my_instrument) => my_instrument,
+            None => continue, // removed while it was polled
+        };
+        if my_instrument.current_port != old.current_port || my_instrument.model != old.model {
+            continue; // the readings are of another supply
+        }
+        take_over(&mut my_instrument.live, &old.live, &new.live);
+        take_over(&mut my_instrument.guard, &old.guard, &new.guard);
+        take_over(&mut my_instrument.rules, &old.rules, &new.rules);
+        if !new.output_info.same(&old.output_info) {
+            // news of the polling, e.g. an output that was forced off, always shows
+            my_instrument.output_info = new.output_info.clone();
+        }
+        for (old_channel, new_channel) in old.channels.iter().zip(new.channels.iter()) {
+            let my_channel = match Arc::make_mut(&mut my_instrument.channels)
+                .iter_mut()
+                .find(|my_channel| my_channel.number == old_channel.number)
+            {
+                Some(my_channel) => my_channel,
+                None => continue,
+            };
+            take_over(
+                &mut my_channel.measured_voltage,
+                &old_channel.measured_voltage,
+                &new_channel.measured_voltage,
+            );
+            take_over(
+                &mut my_channel.measured_amperage,
+                &old_channel.measured_amperage,
+                &new_channel.measured_amperage,
+            );
+            take_over(
+                &mut my_channel.energy,
+                &old_channel.energy,
+                &new_channel.energy,
+            );
+            take_over(
+                &mut my_channel.statistics,
+                &old_channel.statistics,
+                &new_channel.statistics,
+            );
+            take_over(
+                &mut my_channel.charger,
+                &old_channel.charger,
+                &new_channel.charger,
+            );
+        }
+    }
+}
+
+// the polled value, if the value is still the one the polling started with
+fn take_over<T: Data>(value: &mut T, old: &T, new: &T) {
+    if value.same(old) {
+        *value = new.clone();
+    }
+}
+
+// switch the output off when it runs above the power limit or longer than allowed, through
+// the driver, which reads the status back to see that the output really went off
+fn watch_limits(my_instrument: &mut Instrument, port_config: &PortConfig) {
+    if !my_instrument.limits.watch_output() {
+        return;
+    }
+    let mut supply = Supply::with_model(
+        &my_instrument.current_port,
+        port_config,
+        my_instrument.model_info(),
+    );
+    supply.limits = my_instrument.limits.clone();
+    let message = match supply.enforce_limits(&mut my_instrument.guard) {
+        Ok(None) => return,
+        Ok(Some(reason)) => {
+            error!("{}: output forced OFF, {}", my_instrument.label, reason);
+            format!("Output forced OFF: {}! \n", reason)
+        }
+        Err(e) => {
+            error!(
+                "{}: checking the limits failed: {}",
+                my_instrument.label,
+                e.trim()
+            );
+            format!("Checking the limits failed: {}! \n", e.trim())
+        }
+    };
+    my_instrument.output_info.clear();
+    my_instrument.output_info.push_str(&message);
+}
+
+// read status and output of every channel with rules and do what the rules say when they fire,
+// a channel that was just read for the live readout is not read again
+fn check_rules(
+    my_instrument: &mut Instrument,
+    port_config: &PortConfig,
+    live_readings: &[(u8, Measurement)],
+) {
+    if my_instrument.rules.rules.is_empty() {
+        return;
+    }
+    let supply = Supply::with_model(
+        &my_instrument.current_port,
+        port_config,
+        my_instrument.model_info(),
+    );
+    let status = supply.status().ok();
+    let channels: Vec<u8> = my_instrument
+        .channels
+        .iter()
+        .map(|my_channel| my_channel.number)
+        .filter(|number| {
+            my_instrument
+                .rules
+                .rules
+                .iter()
+                .any(|rule| rule.channel == *number)
+        })
+        .collect();
+    let mut alarms = Vec::new();
+    for channel in channels {
+        let reading = match live_readings.iter().find(|(number, _)| *number == channel) {
+            Some((_, measurement)) => Some(*measurement),
+            None => supply.measure(channel).ok(),
+        };
+        let measurement = match reading {
+            Some(measurement) => measurement,
+            None => continue, // no reading, the rules keep their state
+        };
+        let input = RuleInput {
+            channel,
+            measurement,
+            status,
+        };
+        alarms.extend(my_instrument.rules.evaluate(&input, Instant::now()));
+    }
+    if alarms.is_empty() {
+        return;
+    }
+    let mut alarm_output = String::new();
+    for alarm in alarms {
+        warn!("{}: rule '{}' fired", my_instrument.label, alarm.rule);
+        execute_alarm(
+            &alarm,
+            &my_instrument.label,
+            &my_instrument.current_port,
+            port_config,
+            &mut alarm_output,
+        );
+    }
+    my_instrument.output_info.clear();
+    my_instrument.output_info.push_str(&alarm_output);
+}
+
+// let every running battery charge take its next reading, it stops by itself when done
+fn step_chargers(
+    my_instrument: &mut Instrument,
+    port_config: &PortConfig,
+    poll_interval: Duration,
+) {
+    if !my_instrument
+        .channels
+        .iter()
+        .any(|my_channel| my_channel.charging())
+    {
+        return; // nothing to do, keep the channels as they are
+    }
+    for my_channel in Arc::make_mut(&mut my_instrument.channels).iter_mut() {
+        if let Some(charger) = my_channel
+            .charger
+            .as_mut()
+            .filter(|charger| charger.running())
+        {
+            let mut my_output = String::new();
+            charger.energy.set_poll_interval(poll_interval);
+            charger.step(port_config, &mut my_output);
+            debug!(
+                "{} CH{}: {}",
+                charger.label,
+                my_channel.number,
+                charger.describe()
+            );
+            if !charger.running() {
+                // show why the charge ended
+                my_instrument.output_info.clear();
+                my_instrument.output_info.push_str(&my_output);
+            }
+        }
+    }
+}
+
+// ask the supply for the actual output of every channel and put it into the readouts
+fn read_live_values(
+    my_instrument: &mut Instrument,
+    port_config: &PortConfig,
+    poll_interval: Duration,
+) -> Vec<(u8, Measurement)> {
+    let supply = Supply::with_model(
+        &my_instrument.current_port,
+        port_config,
+        my_instrument.model_info(),
+    );
+    let mut first_error = None;
+    let mut readings = Vec::new();
+    for my_channel in Arc::make_mut(&mut my_instrument.channels).iter_mut() {
+        match supply.measure(my_channel.number) {
+            Ok(measurement) => {
+                // count the energy and log it together with the reading
+                my_channel.energy.set_poll_interval(poll_interval);
+                my_channel.energy.add(measurement, Instant::now());
+                my_channel.statistics.add(measurement);
+                readings.push((my_channel.number, measurement));
+                debug!(
+                    "{} CH{}: {:.2} V, {:.3} A, {:.3} W, {}",
+                    my_instrument.label,
+                    my_channel.number,
+                    measurement.voltage,
+                    measurement.amperage,
+                    measurement.power,
+                    my_channel.energy.describe()
+                );
+                my_channel.measured_voltage = format!("{:.2}", measurement.voltage);
+                my_channel.measured_amperage = format!("{:.3}", measurement.amperage);
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+                my_channel.energy.interrupt(); // do not count the time without readings
+                my_channel.measured_voltage = "--".to_string();
+                my_channel.measured_amperage = "--".to_string();
+            }
+        }
+    }
+    if let Some(e) = first_error {
+        // no answer, stop polling and show why
+        my_instrument.live = false;
+        my_instrument.output_info.clear();
+        my_instrument.output_info.push_str(&format!(
+            "Live readout stopped, no answer from the supply: {}! \n",
+            e.trim()
+        ));
+    }
+    readings
+}
@@ -0,0 +1,225 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use tracing::{info, warn};
+
+use crate::driver::Supply;
+use crate::limits::{start_safety, SafetyLimits};
+use crate::port_config::PortConfig;
+
+const MQTT_PORT: u16 = 1883;
+
+// where and how often the bridge publishes
+pub struct MqttSettings {
+    pub broker: String, // host or host:port
+    pub topic_prefix: String,
+    pub interval_ms: u64,
+}
+
+// "localhost" or "localhost:1884" into host and port
+fn split_broker(broker: &str) -> Result<(String, u16), String> {
+    match broker.rsplit_once(':') {
+        Some((host, port)) => port
+            .parse::<u16>()
+            .map(|port| (host.to_string(), port))
+            .map_err(|_| format!("Invalid MQTT broker port '{}'", port)),
+        None => Ok((broker.to_string(), MQTT_PORT)),
+    }
+}
+
+// hold the serial connection, publish the readings and listen for commands until the process is stopped
+pub fn run_mqtt_bridge(
+    settings: &MqttSettings,
+    current_port: &str,
+    port_config: &PortConfig,
+    limits: &SafetyLimits,
+) -> Result<(), String> {
+    // find out what is connected, so the right limits are used
+    let mut supply = Supply::open(current_port, port_config)?;
+    supply.limits = limits.clone();
+
+    let (host, port) = split_broker(&settings.broker)?;
+    let prefix = settings.topic_prefix.trim_end_matches('/').to_string();
+    let mut options = MqttOptions::new(format!("kd3005p-rs-{}", prefix), host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    // the broker tells everyone when we are gone
+    options.set_last_will(LastWill::new(
+        format!("{}/status", prefix),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    let (client, mut connection) = Client::new(options, 10);
+    info!(
+        "MQTT bridge for the {} on {} publishes to {} under {}/",
+        supply.model.name, current_port, settings.broker, prefix
+    );
+
+    let supply = Arc::new(Mutex::new(supply));
+    let watchdog = start_safety(&supply);
+
+    // the connection has to be polled all the time, it also brings in the commands
+    {
+        let supply = supply.clone();
+        let client = client.clone();
+        let prefix = prefix.clone();
+        thread::spawn(move || {
+            for event in connection.iter() {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        // subscribe again after every reconnect, the broker forgets us
+                        info!("MQTT connected");
+                        let subscribed = client
+                            .subscribe(format!("{}/+/set", prefix), QoS::AtLeastOnce)
+                            .and_then(|_| {
+                                client.subscribe(format!("{}/+/+/set", prefix), QoS::AtLeastOnce)
+                            })
+                            .and_then(|_| {
+                                client.publish(
+                                    format!("{}/status", prefix),
+                                    QoS::AtLeastOnce,
+                                    true,
+                                    "online",
+                                )
+                            });
+                        if let Err(e) = subscribed {
+                            warn!("MQTT subscribe failed: {}", e);
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if let Some(watchdog) = &watchdog {
+                            watchdog.feed(); // every command is a sign of life of the controller
+                        }
+                        let payload = String::from_utf8_lossy(&publish.payload).to_string();
+                        let mut supply = supply
+                            .lock()
+                            .unwrap_or_else(|poisoned| poisoned.into_inner());
+                        match handle_command(&mut supply, &prefix, &publish.topic, payload.trim()) {
+                            Ok(()) => info!("MQTT {} = {}", publish.topic, payload.trim()),
+                            Err(e) => warn!("MQTT {} failed: {}", publish.topic, e.trim()),
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // rumqttc connects again on the next poll
+                        warn!("MQTT connection error: {}", e);
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            }
+        });
+    }
+
+    loop {
+        {
+            let supply = supply
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Err(e) = publish_state(&client, &supply, &prefix) {
+                warn!("MQTT publish failed: {}", e.trim());
+            }
+        }
+        thread::sleep(Duration::from_millis(settings.interval_ms));
+    }
+}
+
+// read the supply and send every value to its own topic
+fn publish_state(client: &Client, supply: &Supply, prefix: &str) -> Result<(), String> {
+    let status = supply.status()?;
+    let mut values = vec![(
+        format!("{}/output", prefix),
+        if status.output_on() { "ON" } else { "OFF" }.to_string(),
+    )];
+    for channel in 1..=supply.model.channels {
+        let measurement = supply.measure(channel)?;
+        let topic = format!("{}/ch{}", prefix, channel);
+        values.push((
+            format!("{}/voltage", topic),
+            format!("{:.2}", measurement.voltage),
+        ));
+        values.push((
+            format!("{}/current", topic),
+            format!("{:.3}", measurement.amperage),
+        ));
+        values.push((
+            format!("{}/power", topic),
+            format!("{:.3}", measurement.power),
+        ));
+        values.push((
+            format!("{}/mode", topic),
+            if status.constant_voltage(channel) {
+                "CV"
+            } else {
+                "CC"
+            }
+            .to_string(),
+        ));
+    }
+    for (topic, value) in values {
+        client
+            .publish(topic, QoS::AtMostOnce, false, value)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// <prefix>/output/set, <prefix>/ch1/voltage/set and <prefix>/ch1/current/set
+fn handle_command(
+    supply: &mut Supply,
+    prefix: &str,
+    topic: &str,
+    payload: &str,
+) -> Result<(), String> {
+    let command = topic
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .and_then(|rest| rest.strip_suffix("/set"))
+        .ok_or_else(|| format!("Unknown topic {}", topic))?;
+
+    if command == "output" {
+        let turn_on = match payload.to_uppercase().as_str() {
+            "ON" | "1" | "TRUE" => true,
+            "OFF" | "0" | "FALSE" => false,
+            _ => return Err(format!("'{}' is not ON or OFF", payload)),
+        };
+        return supply.set_output(turn_on);
+    }
+
+    let (channel, setting) = command
+        .split_once('/')
+        .ok_or_else(|| format!("Unknown topic {}", topic))?;
+    let channel = channel
+        .strip_prefix("ch")
+        .and_then(|number| number.parse::<u8>().ok())
+        .ok_or_else(|| format!("Unknown channel in {}", topic))?;
+    if channel < 1 || channel > supply.model.channels {
+        return Err(format!(
+            "The {} has no channel {}",
+            supply.model.name, channel
+        ));
+    }
+    let value = payload
+        .parse::<f64>()
+        .map_err(|_| format!("'{}' is not a number", payload))?;
+    match setting {
+        "voltage" => supply.set_voltage(channel, value),
+        "current" => supply.set_amperage(channel, value),
+        _ => Err(format!("Unknown setting in {}", topic)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_only_go_to_channels_of_the_model() {
+        let mut supply = Supply::open("SIMULATED:KD3005P mqtt", &PortConfig::default()).unwrap();
+        assert!(handle_command(&mut supply, "kd3005p", "kd3005p/ch2/voltage/set", "5").is_err());
+        assert!(handle_command(&mut supply, "kd3005p", "kd3005p/ch0/current/set", "1").is_err());
+        handle_command(&mut supply, "kd3005p", "kd3005p/ch1/voltage/set", "5").unwrap();
+        assert_eq!(supply.voltage_setting(1).unwrap(), 5.0);
+    }
+}
@@ -0,0 +1,134 @@
+use druid::kurbo::{BezPath, Line};
+use druid::piet::{FontFamily, Text, TextLayoutBuilder};
+use druid::widget::prelude::*;
+use druid::{Color, Point, Rect};
+use std::sync::Arc;
+
+use crate::sweep::SweepPoint;
+
+static PLOT_WIDTH: f64 = 460.0;
+static PLOT_HEIGHT: f64 = 260.0;
+static MARGIN: f64 = 40.0; // room for the axis labels
+
+// draws the I-V curve of a sweep: measured voltage to the right, measured current upwards
+pub struct CurvePlot;
+
+impl Widget<Arc<Vec<SweepPoint>>> for CurvePlot {
+    fn event(
+        &mut self,
+        _ctx: &mut EventCtx,
+        _event: &Event,
+        _data: &mut Arc<Vec<SweepPoint>>,
+        _env: &Env,
+    ) {
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &Arc<Vec<SweepPoint>>,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &Arc<Vec<SweepPoint>>,
+        data: &Arc<Vec<SweepPoint>>,
+        _env: &Env,
+    ) {
+        if !old_data.same(data) {
+            ctx.request_paint(); // a new sweep
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &Arc<Vec<SweepPoint>>,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain((PLOT_WIDTH, PLOT_HEIGHT))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Arc<Vec<SweepPoint>>, _env: &Env) {
+        let size = ctx.size();
+        ctx.fill(size.to_rect(), &Color::grey8(0x22));
+
+        // the axes, the origin is bottom left
+        let origin = Point::new(MARGIN, size.height - MARGIN);
+        let width = size.width - 2.0 * MARGIN;
+        let height = size.height - 2.0 * MARGIN;
+        let axis_color = Color::grey8(0xaa);
+        ctx.stroke(
+            Line::new(origin, (origin.x + width, origin.y)),
+            &axis_color,
+            1.0,
+        );
+        ctx.stroke(
+            Line::new(origin, (origin.x, origin.y - height)),
+            &axis_color,
+            1.0,
+        );
+
+        // scale to the highest values, so the whole curve fits
+        let max_voltage = data
+            .iter()
+            .map(|point| point.measurement.voltage)
+            .fold(0.0, f64::max);
+        let max_amperage = data
+            .iter()
+            .map(|point| point.measurement.amperage)
+            .fold(0.0, f64::max);
+        let labels = [
+            ("0".to_string(), Point::new(origin.x - 12.0, origin.y + 4.0)),
+            (
+                format!("{:.2} V", max_voltage),
+                Point::new(origin.x + width - 40.0, origin.y + 8.0),
+            ),
+            (
+                format!("{:.3} A", max_amperage),
+                Point::new(4.0, origin.y - height - 20.0),
+            ),
+        ];
+        for (text, position) in labels.iter() {
+            if let Ok(layout) = ctx
+                .text()
+                .new_text_layout(text.clone())
+                .font(FontFamily::MONOSPACE, 11.0)
+                .text_color(axis_color.clone())
+                .build()
+            {
+                ctx.draw_text(&layout, *position);
+            }
+        }
+        if data.is_empty() || max_voltage <= 0.0 || max_amperage <= 0.0 {
+            return; // nothing to draw yet
+        }
+
+        let to_screen = |voltage: f64, amperage: f64| {
+            Point::new(
+                origin.x + voltage / max_voltage * width,
+                origin.y - amperage / max_amperage * height,
+            )
+        };
+        let mut curve = BezPath::new();
+        for (number, point) in data.iter().enumerate() {
+            let screen_point = to_screen(point.measurement.voltage, point.measurement.amperage);
+            if number == 0 {
+                curve.move_to(screen_point);
+            } else {
+                curve.line_to(screen_point);
+            }
+            // mark the measured points
+            ctx.fill(
+                Rect::from_center_size(screen_point, (4.0, 4.0)),
+                &Color::rgb8(0xff, 0xcc, 0x33),
+            );
+        }
+        ctx.stroke(curve, &Color::rgb8(0x33, 0xcc, 0xff), 1.5);
+    }
+}
@@ -0,0 +1,234 @@
+use core::time::Duration;
+#[cfg(feature = "gui")]
+use druid::Data;
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+// define the defaults of the KD3005P serial interface (9600 baud, 8N1, no flow control)
+pub const DEFAULT_BAUD_RATE: u32 = 9_600;
+pub const DEFAULT_TIMEOUT_MS: u64 = 100;
+
+// limits for the user defined values
+const MIN_BAUD_RATE: u32 = 300;
+const MAX_BAUD_RATE: u32 = 921_600;
+const MIN_TIMEOUT_MS: u64 = 10;
+const MAX_TIMEOUT_MS: u64 = 10_000;
+
+// names of the settings, used by the command line, the config file and the GUI
+pub const BAUD_RATE_KEY: &str = "baud_rate";
+pub const DATA_BITS_KEY: &str = "data_bits";
+pub const PARITY_KEY: &str = "parity";
+pub const STOP_BITS_KEY: &str = "stop_bits";
+pub const FLOW_CONTROL_KEY: &str = "flow_control";
+pub const TIMEOUT_KEY: &str = "timeout_ms";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub timeout_ms: u64,
+}
+
+// the port settings can be compared directly, so the GUI can hold them
+#[cfg(feature = "gui")]
+impl Data for PortConfig {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl Default for PortConfig {
+    fn default() -> Self {
+        PortConfig {
+            baud_rate: DEFAULT_BAUD_RATE,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+        }
+    }
+}
+
+impl PortConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    // check that the values are in a range the port can work with
+    pub fn validate(&self) -> Result<(), String> {
+        if self.baud_rate < MIN_BAUD_RATE || self.baud_rate > MAX_BAUD_RATE {
+            return Err(format!(
+                "Baud rate {} is out of range ({} - {})",
+                self.baud_rate, MIN_BAUD_RATE, MAX_BAUD_RATE
+            ));
+        }
+        if self.timeout_ms < MIN_TIMEOUT_MS || self.timeout_ms > MAX_TIMEOUT_MS {
+            return Err(format!(
+                "Timeout {} ms is out of range ({} - {} ms)",
+                self.timeout_ms, MIN_TIMEOUT_MS, MAX_TIMEOUT_MS
+            ));
+        }
+        Ok(())
+    }
+
+    // change a single setting by its name, the value is checked before it is taken over
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let mut new_config = self.clone(); // work on a copy, so a bad value changes nothing
+        match key.trim() {
+            BAUD_RATE_KEY => {
+                new_config.baud_rate = value
+                    .parse()
+                    .map_err(|_| format!("Invalid baud rate '{}'", value))?;
+            }
+            DATA_BITS_KEY => {
+                new_config.data_bits = match value {
+                    "5" => DataBits::Five,
+                    "6" => DataBits::Six,
+                    "7" => DataBits::Seven,
+                    "8" => DataBits::Eight,
+                    _ => return Err(format!("Invalid data bits '{}' (5, 6, 7 or 8)", value)),
+                };
+            }
+            PARITY_KEY => {
+                new_config.parity = match value.to_lowercase().as_str() {
+                    "none" | "n" => Parity::None,
+                    "odd" | "o" => Parity::Odd,
+                    "even" | "e" => Parity::Even,
+                    _ => return Err(format!("Invalid parity '{}' (none, odd or even)", value)),
+                };
+            }
+            STOP_BITS_KEY => {
+                new_config.stop_bits = match value {
+                    "1" => StopBits::One,
+                    "2" => StopBits::Two,
+                    _ => return Err(format!("Invalid stop bits '{}' (1 or 2)", value)),
+                };
+            }
+            FLOW_CONTROL_KEY => {
+                new_config.flow_control = match value.to_lowercase().as_str() {
+                    "none" => FlowControl::None,
+                    "software" | "xonxoff" => FlowControl::Software,
+                    "hardware" | "rtscts" => FlowControl::Hardware,
+                    _ => {
+                        return Err(format!(
+                            "Invalid flow control '{}' (none, software or hardware)",
+                            value
+                        ))
+                    }
+                };
+            }
+            TIMEOUT_KEY => {
+                new_config.timeout_ms = value
+                    .parse()
+                    .map_err(|_| format!("Invalid timeout '{}'", value))?;
+            }
+            _ => return Err(format!("Unknown port setting '{}'", key)),
+        };
+        new_config.validate()?;
+        *self = new_config;
+        Ok(())
+    }
+
+    // read the settings from a file with one "key = value" per line, a line starting with #
+    // is a comment, the same as in the settings file
+    pub fn from_file(path: &str) -> Result<PortConfig, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read port config {}: {}", path, e))?;
+        let mut port_config = PortConfig::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) => port_config
+                    .set(key, value)
+                    .map_err(|e| format!("{}:{}: {}", path, number + 1, e))?,
+                None => return Err(format!("{}:{}: expected 'key = value'", path, number + 1)),
+            }
+        }
+        Ok(port_config)
+    }
+
+    // get a setting as text, the counterpart of set()
+    pub fn get(&self, key: &str) -> String {
+        match key {
+            BAUD_RATE_KEY => self.baud_rate.to_string(),
+            DATA_BITS_KEY => match self.data_bits {
+                DataBits::Five => "5",
+                DataBits::Six => "6",
+                DataBits::Seven => "7",
+                DataBits::Eight => "8",
+            }
+            .to_string(),
+            PARITY_KEY => match self.parity {
+                Parity::None => "none",
+                Parity::Odd => "odd",
+                Parity::Even => "even",
+            }
+            .to_string(),
+            STOP_BITS_KEY => match self.stop_bits {
+                StopBits::One => "1",
+                StopBits::Two => "2",
+            }
+            .to_string(),
+            FLOW_CONTROL_KEY => match self.flow_control {
+                FlowControl::None => "none",
+                FlowControl::Software => "software",
+                FlowControl::Hardware => "hardware",
+            }
+            .to_string(),
+            TIMEOUT_KEY => self.timeout_ms.to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_checks_the_value() {
+        let mut port_config = PortConfig::default();
+        port_config.set(BAUD_RATE_KEY, " 115200 ").unwrap();
+        port_config.set(PARITY_KEY, "E").unwrap();
+        port_config.set(FLOW_CONTROL_KEY, "rtscts").unwrap();
+        assert_eq!(port_config.baud_rate, 115_200);
+        assert_eq!(port_config.get(PARITY_KEY), "even");
+        assert_eq!(port_config.get(FLOW_CONTROL_KEY), "hardware");
+        // a bad value changes nothing
+        assert!(port_config.set(BAUD_RATE_KEY, "12").is_err());
+        assert!(port_config.set(DATA_BITS_KEY, "9").is_err());
+        assert!(port_config.set(TIMEOUT_KEY, "5").is_err());
+        assert!(port_config.set("speed", "1").is_err());
+        assert_eq!(port_config.baud_rate, 115_200);
+        assert_eq!(port_config.timeout_ms, DEFAULT_TIMEOUT_MS);
+    }
+
+    #[test]
+    fn from_file_with_comments() {
+        let path = std::env::temp_dir().join(format!("kd3005p-port-{}.conf", std::process::id()));
+        std::fs::write(
+            &path,
+            "# the USB adapter\nbaud_rate = 19200\n\n  # parity = even\nstop_bits = 2\n",
+        )
+        .unwrap();
+        let port_config = PortConfig::from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(port_config.baud_rate, 19_200);
+        assert_eq!(port_config.get(STOP_BITS_KEY), "2");
+        assert_eq!(port_config.get(PARITY_KEY), "none");
+        // a comment after a value is part of the value
+        std::fs::write(&path, "baud_rate = 19200 # faster\n").unwrap();
+        assert!(PortConfig::from_file(path.to_str().unwrap()).is_err());
+        std::fs::write(&path, "baud_rate 19200\n").unwrap();
+        let error = PortConfig::from_file(path.to_str().unwrap()).unwrap_err();
+        assert!(error.ends_with(":1: expected 'key = value'"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(PortConfig::from_file(path.to_str().unwrap()).is_err());
+    }
+}
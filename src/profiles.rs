@@ -0,0 +1,227 @@
+use crate::driver::Supply;
+use crate::limits::*;
+use crate::settings::*;
+
+// names of the values of a profile, the limits have the same names as in the settings
+pub const NOTES_KEY: &str = "notes";
+
+// a section "[profile <name>]" starts a profile, its keys follow until the next section
+pub const PROFILE_SECTION: &str = "profile";
+
+// a named voltage and current setting, e.g. "ESP32 board 3.3V/500mA", kept on the host
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub voltage: f64,
+    pub amperage: f64,
+    pub limits: SafetyLimits, // checked together with the limits of the supply, 0 = none
+    pub notes: String,        // free text, one "notes =" line per line
+}
+
+impl Profile {
+    pub fn new(name: &str) -> Self {
+        Profile {
+            name: name.trim().to_string(),
+            voltage: DEFAULT_VOLTAGE,
+            amperage: DEFAULT_AMPERAGE,
+            limits: SafetyLimits::default(),
+            notes: String::new(),
+        }
+    }
+
+    // change a single value of the profile by its name
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key.trim() {
+            VOLTAGE_KEY => self.voltage = parse_setpoint(key, value)?,
+            AMPERAGE_KEY => self.amperage = parse_setpoint(key, value)?,
+            MAX_VOLTAGE_KEY | MAX_AMPERAGE_KEY | MAX_POWER_KEY | MAX_ON_TIME_KEY => {
+                self.limits.set(key, value)?
+            }
+            NOTES_KEY => {
+                if !self.notes.is_empty() {
+                    self.notes.push('\n');
+                }
+                self.notes.push_str(value.trim());
+            }
+            _ => return Err(format!("Unknown profile setting '{}'", key.trim())),
+        }
+        Ok(())
+    }
+
+    // the setpoints must be inside the limits of the profile itself
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("The profile needs a name".to_string());
+        }
+        if self.name.contains(['[', ']', '#']) {
+            return Err(format!(
+                "The name '{}' must not contain [, ] or #",
+                self.name
+            ));
+        }
+        self.limits.validate()?;
+        self.limits.check_voltage(self.voltage)?;
+        self.limits.check_amperage(self.amperage)?;
+        Ok(())
+    }
+
+    // the section of the profile, as in the settings file
+    pub fn to_text(&self) -> String {
+        let mut text = format!("[{} {}]\n", PROFILE_SECTION, self.name);
+        text.push_str(&format!("{} = {}\n", VOLTAGE_KEY, self.voltage));
+        text.push_str(&format!("{} = {}\n", AMPERAGE_KEY, self.amperage));
+        // only the limits the profile has, 0 means none
+        for key in [
+            MAX_VOLTAGE_KEY,
+            MAX_AMPERAGE_KEY,
+            MAX_POWER_KEY,
+            MAX_ON_TIME_KEY,
+        ] {
+            if self.limits.get(key) != "0" {
+                text.push_str(&format!("{} = {}\n", key, self.limits.get(key)));
+            }
+        }
+        for line in self.notes.lines() {
+            text.push_str(&format!("{} = {}\n", NOTES_KEY, line));
+        }
+        text
+    }
+
+    // the soft limits of the supply and the profile together: where both have a limit the
+    // lower one counts, so a profile can make the limits of the supply stricter but never looser
+    pub fn merged_limits(&self, limits: &SafetyLimits) -> SafetyLimits {
+        let mut merged = limits.clone();
+        merged.max_voltage = stricter_limit(limits.max_voltage, self.limits.max_voltage);
+        merged.max_amperage = stricter_limit(limits.max_amperage, self.limits.max_amperage);
+        merged.max_power = stricter_limit(limits.max_power, self.limits.max_power);
+        merged.max_on_seconds = stricter_limit(limits.max_on_seconds, self.limits.max_on_seconds);
+        merged
+    }
+
+    // set the voltage and current of a channel, through the checks of the driver: the model
+    // and the soft limits of the supply together with the ones of the profile; the merged limits
+    // are only used for this, the supply keeps its own limits afterwards
+    pub fn apply(&self, supply: &mut Supply, channel: u8) -> Result<(), String> {
+        self.validate()?;
+        let base_limits = supply.limits.clone();
+        supply.limits = self.merged_limits(&base_limits);
+        let result = supply
+            .set_voltage(channel, self.voltage)
+            .and_then(|_| supply.set_amperage(channel, self.amperage));
+        supply.limits = base_limits;
+        result
+    }
+}
+
+// the lower of two limits, 0 means no limit
+fn stricter_limit(first: f64, second: f64) -> f64 {
+    if first <= 0.0 {
+        second
+    } else if second <= 0.0 {
+        first
+    } else {
+        first.min(second)
+    }
+}
+
+// all profiles as text, to share them as a file
+pub fn profiles_to_text(profiles: &[Profile]) -> String {
+    let mut text = String::from("# kd3005p-rs profiles\n");
+    for profile in profiles {
+        text.push('\n');
+        text.push_str(&profile.to_text());
+    }
+    text
+}
+
+pub fn export_profiles(path: &str, profiles: &[Profile]) -> Result<(), String> {
+    std::fs::write(path, profiles_to_text(profiles))
+        .map_err(|e| format!("Could not write profiles {}: {}", path, e))
+}
+
+// read the profiles of a file, a whole settings file works too, its other settings are
+// not taken over
+pub fn import_profiles(path: &str) -> Result<Vec<Profile>, String> {
+    let profiles = Settings::from_file(path)?.profiles;
+    for profile in &profiles {
+        profile
+            .validate()
+            .map_err(|e| format!("{}: profile '{}': {}", path, profile.name, e.trim()))?;
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port_config::PortConfig;
+
+    #[test]
+    fn to_text_and_back() {
+        let mut profile = Profile::new("USB hub");
+        profile.voltage = 5.1;
+        profile.amperage = 0.9;
+        profile.limits.max_voltage = 5.5;
+        profile.limits.max_on_seconds = 60.0;
+        profile.notes = "hub #2 on the left\nthe # stays".to_string();
+        let settings = Settings::parse(&profile.to_text()).unwrap();
+        assert_eq!(settings.profiles, vec![profile.clone()]);
+        assert_eq!(
+            settings.profiles[0].notes,
+            "hub #2 on the left\nthe # stays"
+        );
+    }
+
+    #[test]
+    fn merged_limits_take_the_lower_one() {
+        let mut profile = Profile::new("board");
+        profile.limits.max_voltage = 3.6;
+        profile.limits.max_amperage = 2.0;
+        let limits = SafetyLimits {
+            max_voltage: 5.0,
+            max_amperage: 1.0,
+            max_power: 4.0,
+            watchdog_seconds: 10.0,
+            ..SafetyLimits::default()
+        };
+        let merged = profile.merged_limits(&limits);
+        assert_eq!(merged.max_voltage, 3.6); // stricter profile
+        assert_eq!(merged.max_amperage, 1.0); // stricter supply
+        assert_eq!(merged.max_power, 4.0); // only the supply has one
+        assert_eq!(merged.max_on_seconds, 0.0); // none has one
+        assert_eq!(merged.watchdog_seconds, 10.0);
+        profile.limits.max_on_seconds = 30.0;
+        assert_eq!(profile.merged_limits(&limits).max_on_seconds, 30.0);
+    }
+
+    #[test]
+    fn apply_to_a_simulated_supply() {
+        let mut supply =
+            Supply::open("SIMULATED:KD3005P apply profile", &PortConfig::default()).unwrap();
+        let mut profile = Profile::new("board");
+        profile.voltage = 3.3;
+        profile.amperage = 0.5;
+        profile.apply(&mut supply, 1).unwrap();
+        assert_eq!(supply.voltage_setting(1).unwrap(), 3.3);
+        assert_eq!(supply.amperage_setting(1).unwrap(), 0.5);
+
+        // the supply keeps its own limits, the profile cannot go above them
+        supply.limits.max_voltage = 3.0;
+        assert!(profile.apply(&mut supply, 1).is_err());
+        assert_eq!(supply.limits.max_voltage, 3.0);
+        // and the profile limits count too
+        supply.limits.max_voltage = 0.0;
+        profile.voltage = 2.5;
+        profile.limits.max_amperage = 0.4;
+        assert!(profile.validate().is_err());
+        profile.amperage = 0.3;
+        supply.limits.max_amperage = 0.2;
+        assert!(profile.apply(&mut supply, 1).is_err());
+        assert_eq!(supply.limits.max_amperage, 0.2);
+        supply.limits.max_amperage = 0.0;
+        profile.apply(&mut supply, 1).unwrap();
+        assert_eq!(supply.amperage_setting(1).unwrap(), 0.3);
+        assert_eq!(supply.limits, SafetyLimits::default());
+        assert!(profile.apply(&mut supply, 2).is_err()); // a single channel model
+    }
+}
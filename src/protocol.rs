@@ -0,0 +1,201 @@
+use crate::model::Model;
+use crate::serial::*;
+use crate::status::Status;
+
+// the commands and answers of the supply without any port, shared by the blocking
+// driver and the async one, so both talk to the supply the same way
+
+// a command that ends in '?' wants an answer
+pub fn is_query(command: &str) -> bool {
+    command.ends_with('?')
+}
+
+pub fn check_channel(model: &Model, channel: u8) -> Result<(), String> {
+    if channel < 1 || channel > model.channels {
+        return Err(format!("The {} has no channel {}", model.name, channel));
+    }
+    Ok(())
+}
+
+pub fn voltage_setting_command(model: &Model, channel: u8) -> Result<String, String> {
+    check_channel(model, channel)?;
+    Ok(channel_command(VGET_COMMAND, channel))
+}
+
+pub fn amperage_setting_command(model: &Model, channel: u8) -> Result<String, String> {
+    check_channel(model, channel)?;
+    Ok(channel_command(IGET_COMMAND, channel))
+}
+
+// the two queries of a measurement, voltage first
+pub fn measure_commands(model: &Model, channel: u8) -> Result<(String, String), String> {
+    check_channel(model, channel)?;
+    Ok((
+        channel_command(VOUT_COMMAND, channel),
+        channel_command(IOUT_COMMAND, channel),
+    ))
+}
+
+// checked against the limits of the model before anything is sent
+pub fn set_voltage_command(model: &Model, channel: u8, voltage: f64) -> Result<String, String> {
+    check_channel(model, channel)?;
+    model.check_voltage(voltage)?;
+    Ok(format!(
+        "{}{:.2}",
+        channel_command(VSET_COMMAND, channel),
+        voltage
+    ))
+}
+
+pub fn set_amperage_command(model: &Model, channel: u8, amperage: f64) -> Result<String, String> {
+    check_channel(model, channel)?;
+    model.check_amperage(amperage)?;
+    Ok(format!(
+        "{}{:.3}",
+        channel_command(ISET_COMMAND, channel),
+        amperage
+    ))
+}
+
+pub fn check_slot(slot: u8) -> Result<(), String> {
+    if !(1..=MEMORY_SLOTS).contains(&slot) {
+        return Err(format!(
+            "There is no memory M{}, only M1 - M{}",
+            slot, MEMORY_SLOTS
+        ));
+    }
+    Ok(())
+}
+
+pub fn recall_command(slot: u8) -> Result<String, String> {
+    check_slot(slot)?;
+    Ok(format!("{}{}", RECALL_COMMAND, slot))
+}
+
+pub fn save_command(slot: u8) -> Result<String, String> {
+    check_slot(slot)?;
+    Ok(format!("{}{}", SAVE_COMMAND, slot))
+}
+
+pub fn output_command(turn_on: bool) -> &'static str {
+    if turn_on {
+        ON_COMMAND
+    } else {
+        OFF_COMMAND
+    }
+}
+
+pub fn ovp_command(model: &Model, turn_on: bool) -> Result<&'static str, String> {
+    if !model.ovp_ocp {
+        return Err(format!("The {} has no OVP", model.name));
+    }
+    Ok(if turn_on {
+        OVP_ON_COMMAND
+    } else {
+        OVP_OFF_COMMAND
+    })
+}
+
+pub fn ocp_command(model: &Model, turn_on: bool) -> Result<&'static str, String> {
+    if !model.ovp_ocp {
+        return Err(format!("The {} has no OCP", model.name));
+    }
+    Ok(if turn_on {
+        OCP_ON_COMMAND
+    } else {
+        OCP_OFF_COMMAND
+    })
+}
+
+// the answer of a query as text, it must not be empty
+pub fn parse_text(answer: &[u8], command: &str) -> Result<String, String> {
+    if answer.is_empty() {
+        return Err(format!("No answer from the supply to {}", command));
+    }
+    Ok(String::from_utf8_lossy(answer).trim().to_string())
+}
+
+// e.g. "05.00" from VSET1?
+pub fn parse_number(answer: &[u8], command: &str) -> Result<f64, String> {
+    let text = parse_text(answer, command)?;
+    text.parse::<f64>()
+        .map_err(|_| format!("Invalid answer '{}' to {}", text, command))
+}
+
+pub fn parse_status(answer: &[u8]) -> Result<Status, String> {
+    Status::from_answer(answer).ok_or_else(|| "No valid status byte received".to_string())
+}
+
+// the answer to any command in readable form, for the raw console
+pub fn decode_answer(command: &str, answer: &[u8], channels: u8) -> String {
+    if answer.is_empty() {
+        return if is_query(command) {
+            "no answer".to_string()
+        } else {
+            "no answer (none expected)".to_string()
+        };
+    }
+    if command.trim() == STATUS_COMMAND {
+        if let Some(status) = Status::from_answer(answer) {
+            return format!(
+                "{:#010b} = {}",
+                status.byte,
+                status.describe(channels).trim()
+            );
+        }
+    }
+    if answer
+        .iter()
+        .all(|byte| byte.is_ascii_graphic() || *byte == b' ')
+    {
+        format!("'{}'", String::from_utf8_lossy(answer))
+    } else {
+        // not text, show the bytes
+        let hex: Vec<String> = answer.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("hex {}", hex.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{find_model, KD3005P};
+
+    #[test]
+    fn commands_for_the_channels_of_the_model() {
+        assert_eq!(set_voltage_command(&KD3005P, 1, 5.0).unwrap(), "VSET1:5.00");
+        assert_eq!(
+            set_amperage_command(find_model("KA3305P"), 2, 0.25).unwrap(),
+            "ISET2:0.250"
+        );
+        assert!(set_voltage_command(&KD3005P, 2, 5.0).is_err());
+        assert!(set_voltage_command(&KD3005P, 1, 31.0).is_err());
+        assert_eq!(
+            measure_commands(&KD3005P, 1).unwrap(),
+            ("VOUT1?".to_string(), "IOUT1?".to_string())
+        );
+        assert!(recall_command(0).is_err());
+        assert_eq!(save_command(5).unwrap(), "SAV5");
+    }
+
+    #[test]
+    fn parse_the_answers() {
+        assert_eq!(parse_number(b"05.00", "VSET1?").unwrap(), 5.0);
+        assert!(parse_number(b"", "VSET1?").is_err());
+        assert!(parse_number(b"x", "VSET1?").is_err());
+        assert_eq!(parse_text(b" KORAD \n", "*IDN?").unwrap(), "KORAD");
+        assert!(parse_status(&[0x40]).unwrap().output_on());
+        assert!(parse_status(b"").is_err());
+    }
+
+    #[test]
+    fn decode_answer_in_readable_form() {
+        assert_eq!(decode_answer("VOUT1?", b"", 1), "no answer");
+        assert_eq!(decode_answer("OUT1", b"", 1), "no answer (none expected)");
+        assert_eq!(decode_answer("VOUT1?", b"05.00", 1), "'05.00'");
+        assert_eq!(decode_answer("FOO", &[0x01, 0xFF], 1), "hex 01 FF");
+        let status = decode_answer("STATUS?", &[0x51], 1);
+        assert!(status.starts_with("0b01010001 = CH1: CV"));
+        assert!(status.contains("Output: ON"));
+    }
+}
@@ -0,0 +1,463 @@
+use std::io::Write;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "gui")]
+use druid::Data;
+use tracing::{error, warn};
+
+use crate::driver::Measurement;
+use crate::port_config::PortConfig;
+use crate::serial::*;
+use crate::status::Status;
+
+// what a rule looks at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quantity {
+    Voltage,
+    Current,
+    Power,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    Above(Quantity, f64),
+    Below(Quantity, f64),
+    ConstantCurrent,
+    ConstantVoltage,
+}
+
+// what happens when a rule fires
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    OutputOff,
+    Log,    // a marker in the log
+    Beep,   // the terminal bell
+    Notify, // a desktop notification
+    Run(String),
+}
+
+// one rule like "ch1 current > 1.2 for 200 ms: off, notify"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub text: String,
+    pub channel: u8,
+    pub condition: Condition,
+    pub hold: Duration, // the condition must be true this long
+    pub actions: Vec<Action>,
+    true_since: Option<Instant>,
+    fired: bool, // fires once, again only after the condition was false
+}
+
+// what the monitoring gives the rules for one channel
+pub struct RuleInput {
+    pub channel: u8,
+    pub measurement: Measurement,
+    pub status: Option<Status>,
+}
+
+// a rule that fired, with the value that made it fire
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alarm {
+    pub rule: String,
+    pub channel: u8,
+    pub measurement: Measurement,
+    pub actions: Vec<Action>,
+}
+
+// all rules of one supply
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+// the rules change state with every reading, so the GUI can compare them directly
+#[cfg(feature = "gui")]
+impl Data for RuleSet {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+fn parse_quantity(word: &str) -> Option<Quantity> {
+    match word.to_lowercase().as_str() {
+        "voltage" | "v" => Some(Quantity::Voltage),
+        "current" | "amperage" | "i" | "a" => Some(Quantity::Current),
+        "power" | "p" | "w" => Some(Quantity::Power),
+        _ => None,
+    }
+}
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    match words.as_slice() {
+        [mode, value] | [mode, "==", value] | [mode, "=", value] | [mode, "is", value]
+            if mode.eq_ignore_ascii_case("mode") =>
+        {
+            match value.to_uppercase().as_str() {
+                "CC" => Ok(Condition::ConstantCurrent),
+                "CV" => Ok(Condition::ConstantVoltage),
+                _ => Err(format!("The mode is CC or CV, not '{}'", value)),
+            }
+        }
+        [quantity, operator, value] => {
+            let quantity = parse_quantity(quantity).ok_or_else(|| {
+                format!(
+                    "Unknown quantity '{}' (voltage, current, power or mode)",
+                    quantity
+                )
+            })?;
+            let value = value
+                .trim_end_matches(|c: char| c.is_ascii_alphabetic()) // allow units like "1.2A"
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid value '{}'", value))?;
+            match *operator {
+                ">" => Ok(Condition::Above(quantity, value)),
+                "<" => Ok(Condition::Below(quantity, value)),
+                _ => Err(format!("Unknown comparison '{}', use > or <", operator)),
+            }
+        }
+        _ => Err(format!("Cannot read the condition '{}'", text.trim())),
+    }
+}
+
+fn parse_action(text: &str) -> Result<Action, String> {
+    let text = text.trim();
+    let (word, rest) = match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    };
+    match word.to_lowercase().as_str() {
+        "off" => Ok(Action::OutputOff),
+        "log" | "mark" => Ok(Action::Log),
+        "beep" => Ok(Action::Beep),
+        "notify" | "alert" => Ok(Action::Notify),
+        "run" if !rest.is_empty() => Ok(Action::Run(rest.to_string())),
+        "run" => Err("'run' needs a command".to_string()),
+        _ => Err(format!(
+            "Unknown action '{}' (off, log, beep, notify or run <command>)",
+            word
+        )),
+    }
+}
+
+impl Rule {
+    // "[chN] <condition> [for <N> ms]: <action>, <action>"
+    pub fn parse(text: &str) -> Result<Rule, String> {
+        let (condition_text, actions_text) = text
+            .split_once(':')
+            .ok_or_else(|| format!("'{}' has no ':' before the actions", text.trim()))?;
+        let mut condition_text = condition_text.trim();
+
+        let mut channel = 1;
+        if let Some((first, rest)) = condition_text.split_once(char::is_whitespace) {
+            if let Some(number) = first.to_lowercase().strip_prefix("ch") {
+                channel = number
+                    .parse::<u8>()
+                    .map_err(|_| format!("Invalid channel '{}'", first))?;
+                condition_text = rest.trim();
+            }
+        }
+
+        let mut hold = Duration::from_millis(0);
+        if let Some((condition, hold_text)) = condition_text.split_once(" for ") {
+            let hold_ms = hold_text
+                .trim()
+                .trim_end_matches("ms")
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| {
+                    format!(
+                        "Invalid time '{}', expected e.g. 'for 200 ms'",
+                        hold_text.trim()
+                    )
+                })?;
+            hold = Duration::from_millis(hold_ms);
+            condition_text = condition.trim();
+        }
+
+        let actions = actions_text
+            .split(',')
+            .filter(|action| !action.trim().is_empty())
+            .map(parse_action)
+            .collect::<Result<Vec<Action>, String>>()?;
+        if actions.is_empty() {
+            return Err(format!("'{}' has no action", text.trim()));
+        }
+
+        Ok(Rule {
+            text: text.trim().to_string(),
+            channel,
+            condition: parse_condition(condition_text)?,
+            hold,
+            actions,
+            true_since: None,
+            fired: false,
+        })
+    }
+
+    fn holds(&self, input: &RuleInput) -> bool {
+        let value = |quantity: &Quantity| match quantity {
+            Quantity::Voltage => input.measurement.voltage,
+            Quantity::Current => input.measurement.amperage,
+            Quantity::Power => input.measurement.power,
+        };
+        match &self.condition {
+            Condition::Above(quantity, limit) => value(quantity) > *limit,
+            Condition::Below(quantity, limit) => value(quantity) < *limit,
+            // without a status byte the mode is not known, so it does not hold
+            Condition::ConstantCurrent => input
+                .status
+                .is_some_and(|status| status.output_on() && !status.constant_voltage(self.channel)),
+            Condition::ConstantVoltage => input
+                .status
+                .is_some_and(|status| status.output_on() && status.constant_voltage(self.channel)),
+        }
+    }
+}
+
+impl RuleSet {
+    // one rule per line, empty lines and lines starting with # are skipped
+    pub fn parse(text: &str) -> Result<RuleSet, String> {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            rules.push(Rule::parse(line).map_err(|e| format!("Rule {}: {}", number + 1, e))?);
+        }
+        Ok(RuleSet { rules })
+    }
+
+    // look at one reading, gives the rules that fire now
+    pub fn evaluate(&mut self, input: &RuleInput, time: Instant) -> Vec<Alarm> {
+        let mut alarms = Vec::new();
+        for rule in self
+            .rules
+            .iter_mut()
+            .filter(|rule| rule.channel == input.channel)
+        {
+            if !rule.holds(input) {
+                rule.true_since = None;
+                rule.fired = false;
+                continue;
+            }
+            let true_since = *rule.true_since.get_or_insert(time);
+            if !rule.fired && time.duration_since(true_since) >= rule.hold {
+                rule.fired = true;
+                alarms.push(Alarm {
+                    rule: rule.text.clone(),
+                    channel: input.channel,
+                    measurement: input.measurement,
+                    actions: rule.actions.clone(),
+                });
+            }
+        }
+        alarms
+    }
+}
+
+// do what the rule says, everything that happened goes to my_output
+pub fn execute_alarm(
+    alarm: &Alarm,
+    label: &str,
+    current_port: &str,
+    port_config: &PortConfig,
+    my_output: &mut String,
+) {
+    let message = format!(
+        "{} CH{}: rule '{}' at {:.2} V / {:.3} A / {:.3} W",
+        label,
+        alarm.channel,
+        alarm.rule,
+        alarm.measurement.voltage,
+        alarm.measurement.amperage,
+        alarm.measurement.power
+    );
+    warn!(port = current_port, rule = %alarm.rule, "Alarm: {}", message);
+    for action in &alarm.actions {
+        match action {
+            Action::OutputOff => {
+                if let Err(e) = turn_on_off(current_port, port_config, OFF_COMMAND) {
+                    error!(port = current_port, rule = %alarm.rule, "Output OFF failed: {}", e);
+                    my_output.push_str(&format!("{}: output OFF failed: {} \n", label, e));
+                    continue;
+                }
+                error!(port = current_port, rule = %alarm.rule, "Output OFF by rule");
+                my_output.push_str(&format!(
+                    "{}: output OFF by rule '{}' \n",
+                    label, alarm.rule
+                ));
+            }
+            Action::Log => {
+                warn!("=== MARK === {}", message);
+                my_output.push_str(&format!("=== MARK === {} \n", message));
+            }
+            Action::Beep => {
+                // the bell of the terminal, stdout is only written out at a new line otherwise
+                let mut stdout = std::io::stdout();
+                if let Err(e) = stdout.write_all(b"\x07").and_then(|_| stdout.flush()) {
+                    warn!("Beep failed: {}", e);
+                }
+                my_output.push_str(&format!("Beep: {} \n", message));
+            }
+            Action::Notify => {
+                if let Err(e) = notify("KD3005P alarm", &message) {
+                    warn!("Notification failed: {}", e);
+                    my_output.push_str(&format!("Notification failed: {} \n", e));
+                }
+                my_output.push_str(&format!("Alarm: {} \n", message));
+            }
+            Action::Run(command) => match run_command(command, &message) {
+                Ok(()) => my_output.push_str(&format!("Started '{}' \n", command)),
+                Err(e) => {
+                    warn!("Could not start '{}': {}", command, e);
+                    my_output.push_str(&format!("Could not start '{}': {} \n", command, e));
+                }
+            },
+        }
+    }
+}
+
+// a desktop notification with the tool of the system, nothing is waited for
+fn notify(title: &str, message: &str) -> Result<(), String> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification {:?} with title {:?}",
+            message, title
+        ));
+        command
+    } else if cfg!(target_os = "windows") {
+        let mut command = Command::new("msg");
+        command.arg("*").arg(format!("{}: {}", title, message));
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.arg(title).arg(message);
+        command
+    };
+    command
+        .spawn()
+        .map(wait_in_background)
+        .map_err(|e| e.to_string())
+}
+
+// run the command of a rule in the shell, the message is in $KD3005P_ALARM
+fn run_command(command: &str, message: &str) -> Result<(), String> {
+    let mut shell = if cfg!(target_os = "windows") {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    };
+    shell
+        .env("KD3005P_ALARM", message)
+        .spawn()
+        .map(wait_in_background)
+        .map_err(|e| e.to_string())
+}
+
+// a started program is waited for on its own thread, so it does not stay behind as a zombie
+// and the rules do not wait for it
+fn wait_in_background(mut child: Child) {
+    std::thread::spawn(move || match child.wait() {
+        Ok(status) if !status.success() => warn!("Alarm program ended with {}", status),
+        Ok(_) => {}
+        Err(e) => warn!("Could not wait for the alarm program: {}", e),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(channel: u8, voltage: f64, amperage: f64, status: Option<Status>) -> RuleInput {
+        RuleInput {
+            channel,
+            measurement: Measurement::new(voltage, amperage),
+            status,
+        }
+    }
+
+    #[test]
+    fn parse_a_rule() {
+        let rule = Rule::parse("ch2 current > 1.2A for 200 ms: off, notify, run echo hi").unwrap();
+        assert_eq!(rule.channel, 2);
+        assert_eq!(rule.condition, Condition::Above(Quantity::Current, 1.2));
+        assert_eq!(rule.hold, Duration::from_millis(200));
+        assert_eq!(
+            rule.actions,
+            vec![
+                Action::OutputOff,
+                Action::Notify,
+                Action::Run("echo hi".to_string())
+            ]
+        );
+        let rule = Rule::parse("mode is CC: beep").unwrap();
+        assert_eq!(rule.channel, 1);
+        assert_eq!(rule.condition, Condition::ConstantCurrent);
+    }
+
+    #[test]
+    fn parse_rejects_bad_rules() {
+        assert!(Rule::parse("current > 1").is_err()); // no actions
+        assert!(Rule::parse("current > 1:").is_err());
+        assert!(Rule::parse("current = 1: off").is_err());
+        assert!(Rule::parse("temperature > 1: off").is_err());
+        assert!(Rule::parse("chx current > 1: off").is_err());
+        assert!(Rule::parse("current > 1 for ever: off").is_err());
+        assert!(Rule::parse("current > 1: explode").is_err());
+        assert!(Rule::parse("current > 1: run").is_err());
+        let error = RuleSet::parse("# comment\n\nvoltage < 1: log\npower > : off").unwrap_err();
+        assert!(error.starts_with("Rule 4: "));
+    }
+
+    #[test]
+    fn a_rule_fires_after_the_hold_time_and_only_once() {
+        let mut rules = RuleSet::parse("power > 10 for 100 ms: log").unwrap();
+        let start = Instant::now();
+        let later = |ms| start + Duration::from_millis(ms);
+        assert!(rules.evaluate(&input(1, 12.0, 1.0, None), start).is_empty());
+        assert!(rules
+            .evaluate(&input(1, 12.0, 1.0, None), later(50))
+            .is_empty());
+        let alarms = rules.evaluate(&input(1, 12.0, 1.0, None), later(100));
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].actions, vec![Action::Log]);
+        assert!(rules
+            .evaluate(&input(1, 12.0, 1.0, None), later(200))
+            .is_empty());
+        // false once, then it can fire again
+        assert!(rules
+            .evaluate(&input(1, 5.0, 1.0, None), later(300))
+            .is_empty());
+        assert!(rules
+            .evaluate(&input(1, 12.0, 1.0, None), later(400))
+            .is_empty());
+        assert_eq!(
+            rules.evaluate(&input(1, 12.0, 1.0, None), later(500)).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn rules_look_at_their_own_channel_and_the_mode() {
+        let mut rules = RuleSet::parse("ch2 voltage < 1: off\nmode CC: beep").unwrap();
+        let now = Instant::now();
+        assert!(rules.evaluate(&input(1, 0.0, 0.0, None), now).is_empty());
+        assert_eq!(rules.evaluate(&input(2, 0.0, 0.0, None), now).len(), 1);
+        // output on, CH1 in CC
+        let status = Status::from_byte(0x60);
+        assert_eq!(
+            rules.evaluate(&input(1, 5.0, 1.0, Some(status)), now).len(),
+            1
+        );
+        let mut rules = RuleSet::parse("mode CV: log").unwrap();
+        assert!(rules.evaluate(&input(1, 5.0, 1.0, None), now).is_empty());
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{info, warn};
+
+use crate::driver::Supply;
+use crate::limits::{start_safety, SafetyLimits, Watchdog};
+use crate::port_config::PortConfig;
+
+// the SCPI error queue holds this many entries, like most instruments
+const ERROR_QUEUE_LENGTH: usize = 16;
+
+// the keywords we understand, the upper case part is the short form
+const KEYWORDS: &[&str] = &[
+    "SOURce",
+    "VOLTage",
+    "CURRent",
+    "POWer",
+    "LEVel",
+    "IMMediate",
+    "AMPLitude",
+    "MEASure",
+    "SCALar",
+    "DC",
+    "OUTPut",
+    "STATe",
+    "PROTection",
+    "INSTrument",
+    "NSELect",
+    "SYSTem",
+    "ERRor",
+    "NEXT",
+];
+
+// what every connection keeps for itself
+struct ScpiSession {
+    channel: u8,
+    errors: VecDeque<String>,
+}
+
+// hold the serial connection and serve SCPI commands on a TCP socket until the process is stopped
+pub fn run_scpi_server(
+    address: &str,
+    current_port: &str,
+    port_config: &PortConfig,
+    limits: &SafetyLimits,
+) -> Result<(), String> {
+    // find out what is connected, so the right limits are used
+    let mut supply = Supply::open(current_port, port_config)?;
+    supply.limits = limits.clone();
+
+    let listener = TcpListener::bind(address)
+        .map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+    info!(
+        "SCPI server for the {} on {} is listening on {}",
+        supply.model.name, current_port, address
+    );
+
+    let target = Arc::new(Mutex::new(supply));
+    let watchdog = start_safety(&target);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let target = target.clone();
+                let watchdog = watchdog.clone();
+                thread::spawn(move || handle_client(stream, target, watchdog)); // one thread per client
+            }
+            Err(e) => warn!("Connection failed: {}", e),
+        }
+    }
+    Ok(())
+}
+
+// read commands line by line, several commands on a line are separated by ';'
+fn handle_client(stream: TcpStream, target: Arc<Mutex<Supply>>, watchdog: Option<Watchdog>) {
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer.to_string(),
+        Err(_) => "unknown".to_string(),
+    };
+    info!("SCPI client {} connected", peer);
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            warn!("SCPI client {}: {}", peer, e);
+            return;
+        }
+    };
+    let mut session = ScpiSession {
+        channel: 1,
+        errors: VecDeque::new(),
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if let Some(watchdog) = &watchdog {
+            watchdog.feed(); // every line from the client is a sign of life
+        }
+        for command in line.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+            // only one client talks to the supply at a time
+            let mut target = target
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            match session.execute(command, &mut target) {
+                Ok(Some(response)) => {
+                    if writeln!(writer, "{}", response).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => session.push_error(e),
+            }
+        }
+    }
+    info!("SCPI client {} disconnected", peer);
+}
+
+// "VOLT" or "VOLTAGE" both give "VOLTage", anything unknown gives None
+fn canonical_keyword(token: &str) -> Option<&'static str> {
+    let token = token.to_uppercase();
+    KEYWORDS.iter().copied().find(|keyword| {
+        let short_form: String = keyword.chars().filter(|c| c.is_uppercase()).collect();
+        token == short_form || token == keyword.to_uppercase()
+    })
+}
+
+// read a number parameter, MIN and MAX are allowed too; the unit may follow, with m, u or k
+// in front of it, e.g. "5V", "500 mV" or "20mA" for `unit` "V" or "A"
+fn parse_number(parameter: Option<&str>, unit: &str, max: f64) -> Result<f64, String> {
+    let parameter = parameter.ok_or("-109,\"Missing parameter\"")?;
+    let number = match parameter.to_uppercase().as_str() {
+        "MIN" | "MINIMUM" => 0.0,
+        "MAX" | "MAXIMUM" => max,
+        value => {
+            let suffix_start = value
+                .rfind(|c: char| !c.is_ascii_alphabetic())
+                .map_or(0, |position| position + 1);
+            let (number, suffix) = value.split_at(suffix_start);
+            let number = number
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| "-104,\"Data type error\"".to_string())?;
+            number * suffix_factor(suffix, unit)?
+        }
+    };
+    if !(0.0..=max).contains(&number) {
+        return Err("-222,\"Data out of range\"".to_string());
+    }
+    Ok(number)
+}
+
+// what a suffix like "MV" or "A" stands for, anything but the unit with or without a prefix
+// is refused instead of being dropped
+fn suffix_factor(suffix: &str, unit: &str) -> Result<f64, String> {
+    if suffix.is_empty() || suffix == unit {
+        return Ok(1.0);
+    }
+    let factor = match suffix.strip_suffix(unit).filter(|_| !unit.is_empty()) {
+        Some("M") => 1e-3,
+        Some("U") => 1e-6,
+        Some("K") => 1e3,
+        _ => return Err("-131,\"Invalid suffix\"".to_string()),
+    };
+    Ok(factor)
+}
+
+// the details go to the console, the client gets the SCPI error
+fn hardware_error(e: String) -> String {
+    warn!("{}", e.trim());
+    "-240,\"Hardware error\"".to_string()
+}
+
+// read an ON/OFF parameter
+fn parse_switch(parameter: Option<&str>) -> Result<bool, String> {
+    match parameter.map(str::to_uppercase).as_deref() {
+        Some("ON") | Some("1") => Ok(true),
+        Some("OFF") | Some("0") => Ok(false),
+        Some(_) => Err("-224,\"Illegal parameter value\"".to_string()),
+        None => Err("-109,\"Missing parameter\"".to_string()),
+    }
+}
+
+impl ScpiSession {
+    fn push_error(&mut self, error: String) {
+        warn!("SCPI error {}", error);
+        if self.errors.len() >= ERROR_QUEUE_LENGTH {
+            self.errors.pop_back();
+            self.errors.push_back("-350,\"Queue overflow\"".to_string());
+        } else {
+            self.errors.push_back(error);
+        }
+    }
+
+    // run one command, a query gives the response line
+    fn execute(&mut self, command: &str, target: &mut Supply) -> Result<Option<String>, String> {
+        let (header, parameter) = match command.split_once(char::is_whitespace) {
+            Some((header, parameter)) => (header, Some(parameter.trim())),
+            None => (command, None),
+        };
+        let query = header.ends_with('?');
+        let header = header.trim_start_matches(':').trim_end_matches('?');
+
+        // the common commands first
+        match header.to_uppercase().as_str() {
+            "*IDN" if query => return target.id().map(Some).map_err(hardware_error),
+            "*CLS" => {
+                self.errors.clear();
+                return Ok(None);
+            }
+            "*OPC" if query => return Ok(Some("1".to_string())),
+            _ => {}
+        }
+
+        let mut keywords = Vec::new();
+        for token in header.split(':') {
+            match canonical_keyword(token) {
+                Some(keyword) => keywords.push(keyword),
+                None => return Err("-113,\"Undefined header\"".to_string()),
+            }
+        }
+        // leave out the optional keywords, "SOUR:VOLT:LEV:IMM" is just "VOLT"
+        if keywords.first() == Some(&"SOURce") {
+            keywords.remove(0);
+        }
+        while let Some(last) = keywords.last() {
+            if [
+                "LEVel",
+                "IMMediate",
+                "AMPLitude",
+                "SCALar",
+                "DC",
+                "STATe",
+                "NEXT",
+            ]
+            .contains(last)
+            {
+                keywords.pop();
+            } else {
+                break;
+            }
+        }
+
+        let channel = self.channel;
+        match (keywords.as_slice(), query) {
+            (["VOLTage"], true) => target
+                .voltage_setting(channel)
+                .map(|voltage| Some(format!("{:.2}", voltage)))
+                .map_err(hardware_error),
+            (["VOLTage"], false) => {
+                let voltage = parse_number(
+                    parameter,
+                    "V",
+                    target.limits.voltage_limit(target.model.max_voltage),
+                )?;
+                target
+                    .set_voltage(channel, voltage)
+                    .map(|_| None)
+                    .map_err(hardware_error)
+            }
+            (["CURRent"], true) => target
+                .amperage_setting(channel)
+                .map(|amperage| Some(format!("{:.3}", amperage)))
+                .map_err(hardware_error),
+            (["CURRent"], false) => {
+                let amperage = parse_number(
+                    parameter,
+                    "A",
+                    target.limits.amperage_limit(target.model.max_amperage),
+                )?;
+                target
+                    .set_amperage(channel, amperage)
+                    .map(|_| None)
+                    .map_err(hardware_error)
+            }
+            (["MEASure", "VOLTage"], true) => target
+                .measure(channel)
+                .map(|measurement| Some(format!("{:.2}", measurement.voltage)))
+                .map_err(hardware_error),
+            (["MEASure", "CURRent"], true) => target
+                .measure(channel)
+                .map(|measurement| Some(format!("{:.3}", measurement.amperage)))
+                .map_err(hardware_error),
+            (["MEASure", "POWer"], true) => target
+                .measure(channel)
+                .map(|measurement| Some(format!("{:.3}", measurement.power)))
+                .map_err(hardware_error),
+            (["OUTPut"], true) => target
+                .status()
+                .map(|status| Some(if status.output_on() { "1" } else { "0" }.to_string()))
+                .map_err(hardware_error),
+            (["OUTPut"], false) => {
+                let turn_on = parse_switch(parameter)?;
+                target
+                    .set_output(turn_on)
+                    .map(|_| None)
+                    .map_err(hardware_error)
+            }
+            (["VOLTage", "PROTection"], false) | (["CURRent", "PROTection"], false) => {
+                if !target.model.ovp_ocp {
+                    return Err("-241,\"Hardware missing\"".to_string());
+                }
+                let turn_on = parse_switch(parameter)?;
+                let result = if keywords[0] == "VOLTage" {
+                    target.set_ovp(turn_on)
+                } else {
+                    target.set_ocp(turn_on)
+                };
+                result.map(|_| None).map_err(hardware_error)
+            }
+            (["INSTrument", "NSELect"], true) => Ok(Some(self.channel.to_string())),
+            (["INSTrument", "NSELect"], false) => {
+                let channel = parse_number(parameter, "", target.model.channels as f64)?;
+                if channel < 1.0 || channel.fract() != 0.0 {
+                    return Err("-222,\"Data out of range\"".to_string());
+                }
+                self.channel = channel as u8;
+                Ok(None)
+            }
+            (["SYSTem", "ERRor"], true) => Ok(Some(
+                self.errors
+                    .pop_front()
+                    .unwrap_or_else(|| "0,\"No error\"".to_string()),
+            )),
+            _ => Err("-113,\"Undefined header\"".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(session: &mut ScpiSession, supply: &mut Supply, command: &str) -> Option<String> {
+        session.execute(command, supply).unwrap()
+    }
+
+    fn open(current_port: &str) -> (ScpiSession, Supply) {
+        let session = ScpiSession {
+            channel: 1,
+            errors: VecDeque::new(),
+        };
+        let supply = Supply::open(current_port, &PortConfig::default()).unwrap();
+        (session, supply)
+    }
+
+    #[test]
+    fn numbers_with_units() {
+        assert_eq!(parse_number(Some("5"), "V", 30.0), Ok(5.0));
+        assert_eq!(parse_number(Some("5 V"), "V", 30.0), Ok(5.0));
+        assert_eq!(parse_number(Some("500mV"), "V", 30.0), Ok(0.5));
+        assert_eq!(parse_number(Some("20mA"), "A", 5.0), Ok(0.02));
+        assert_eq!(parse_number(Some("MAX"), "A", 5.0), Ok(5.0));
+        assert!(parse_number(Some("20m"), "A", 5.0).is_err()); // no unit after the prefix
+        assert!(parse_number(Some("2V"), "A", 5.0).is_err()); // the wrong unit
+        assert!(parse_number(Some("1kV"), "V", 30.0).is_err()); // out of range
+        assert!(parse_number(Some("1V"), "", 2.0).is_err()); // a channel has no unit
+    }
+
+    #[test]
+    fn commands_on_a_simulated_supply() {
+        let (mut session, mut supply) = open("SIMULATED:KD3005P scpi commands");
+        assert!(run(&mut session, &mut supply, "*IDN?").is_some());
+        run(&mut session, &mut supply, "SOUR:VOLT:LEV 5");
+        run(&mut session, &mut supply, "CURR 500mA");
+        assert_eq!(
+            run(&mut session, &mut supply, "VOLT?"),
+            Some("5.00".to_string())
+        );
+        assert_eq!(
+            run(&mut session, &mut supply, "CURRent?"),
+            Some("0.500".to_string())
+        );
+        run(&mut session, &mut supply, "OUTP ON");
+        assert_eq!(
+            run(&mut session, &mut supply, "OUTP?"),
+            Some("1".to_string())
+        );
+        // 5 V into the 10 Ohm load
+        assert_eq!(
+            run(&mut session, &mut supply, "MEAS:CURR?"),
+            Some("0.500".to_string())
+        );
+        run(&mut session, &mut supply, "OUTP OFF");
+        assert_eq!(
+            run(&mut session, &mut supply, "OUTP?"),
+            Some("0".to_string())
+        );
+    }
+
+    #[test]
+    fn errors_go_into_the_queue() {
+        let (mut session, mut supply) = open("SIMULATED:KD3005P scpi errors");
+        for command in ["VOLT 2mA", "FOO", "INST:NSEL 2", "VOLT"] {
+            match session.execute(command, &mut supply) {
+                Err(e) => session.push_error(e),
+                Ok(_) => panic!("'{}' should fail", command),
+            }
+        }
+        assert_eq!(
+            run(&mut session, &mut supply, "SYST:ERR?"),
+            Some("-131,\"Invalid suffix\"".to_string())
+        );
+        assert_eq!(
+            run(&mut session, &mut supply, "SYST:ERR?"),
+            Some("-113,\"Undefined header\"".to_string())
+        );
+        run(&mut session, &mut supply, "*CLS");
+        assert_eq!(
+            run(&mut session, &mut supply, "SYST:ERR?"),
+            Some("0,\"No error\"".to_string())
+        );
+        // nothing was set by the wrong suffix
+        assert_eq!(
+            run(&mut session, &mut supply, "VOLT?"),
+            Some("12.00".to_string())
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use core::time::Duration;
+use std::time::Instant;
+
+use crate::driver::Supply;
+use crate::limits::SafetyLimits;
+use crate::port_config::PortConfig;
+use crate::serial::*;
+
+// a supply in a sequence: the label it is called by, its port and the soft limits its
+// settings are checked against before it is switched on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rail {
+    pub label: String,
+    pub current_port: String,
+    pub limits: SafetyLimits,
+}
+
+impl Rail {
+    pub fn new(label: &str, current_port: &str) -> Self {
+        Rail {
+            label: label.to_string(),
+            current_port: current_port.to_string(),
+            limits: SafetyLimits::default(),
+        }
+    }
+}
+
+// one step of a power sequence: switch a rail (by its label) or wait
+#[derive(Clone, Debug, PartialEq)]
+pub enum SequenceStep {
+    Rail(String),
+    Wait(u64),
+}
+
+// read a sequence like "3.3V on, wait 50 ms, 1.8V on, wait 10, 5V on"
+// steps are separated by commas or new lines, the "on" after a rail label is optional
+pub fn parse_sequence(text: &str) -> Result<Vec<SequenceStep>, String> {
+    let mut steps = Vec::new();
+    for part in text.split([',', '\n']) {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        // only "wait" as a word of its own is a delay, a label like "waiter-psu" is a rail
+        let (first_word, rest) = part.split_once(char::is_whitespace).unwrap_or((part, ""));
+        if first_word.eq_ignore_ascii_case("wait") {
+            let delay = rest.trim().trim_end_matches("ms").trim();
+            let delay_ms = delay.parse::<u64>().map_err(|_| {
+                format!(
+                    "Invalid delay '{}', expected e.g. 'wait 50 ms' (a label must not start with the word 'wait')",
+                    part
+                )
+            })?;
+            steps.push(SequenceStep::Wait(delay_ms));
+        } else {
+            // cut off the "on" after the label
+            let label = match part.rsplit_once(' ') {
+                Some((label, last_word)) if last_word.eq_ignore_ascii_case("on") => label,
+                _ => part,
+            };
+            let label = label.trim().to_string();
+            // a rail switched twice would make power_down() switch it off early
+            if steps.contains(&SequenceStep::Rail(label.clone())) {
+                return Err(format!("The rail '{}' is in the sequence twice", label));
+            }
+            steps.push(SequenceStep::Rail(label));
+        }
+    }
+    if !steps
+        .iter()
+        .any(|step| matches!(step, SequenceStep::Rail(_)))
+    {
+        return Err("The sequence does not switch any rail".to_string());
+    }
+    Ok(steps)
+}
+
+// find the supply of each rail, so a typo is found before anything is switched
+fn find_rails<'a>(
+    steps: &[SequenceStep],
+    instruments: &'a [Rail],
+) -> Result<Vec<&'a Rail>, String> {
+    let mut rails = Vec::new();
+    for step in steps {
+        if let SequenceStep::Rail(label) = step {
+            match instruments
+                .iter()
+                .find(|instrument| &instrument.label == label)
+            {
+                Some(instrument) => rails.push(instrument),
+                None => return Err(format!("No supply with the label '{}'", label)),
+            }
+        }
+    }
+    Ok(rails)
+}
+
+// switch one rail and check with the status byte that it really did
+fn switch_and_verify(
+    instrument: &Rail,
+    port_config: &PortConfig,
+    turn_on: bool,
+    my_output: &mut String,
+) -> Result<(), String> {
+    if turn_on {
+        // through the driver, so the settings are checked against the soft limits first
+        Supply::open(&instrument.current_port, port_config).and_then(|mut supply| {
+            supply.limits = instrument.limits.clone();
+            supply.set_output(true)
+        })
+    } else {
+        turn_on_off(&instrument.current_port, port_config, OFF_COMMAND)
+    }
+    .map_err(|e| format!("{}: {}", instrument.label, e))?;
+    match get_status(&instrument.current_port, port_config) {
+        Ok(status) if status.output_on() == turn_on => {
+            my_output.push_str(&format!(
+                "{}: output {} \n",
+                instrument.label,
+                if turn_on { "ON" } else { "OFF" }
+            ));
+            Ok(())
+        }
+        Ok(_) => Err(format!(
+            "{}: output did not switch {}",
+            instrument.label,
+            if turn_on { "ON" } else { "OFF" }
+        )),
+        Err(e) => Err(format!("{}: no status received, {}", instrument.label, e)),
+    }
+}
+
+// wait until the delay since `since` is over and give the time the wait ended; a wait counts
+// from the start of the switching before it, so the port openings and the status check of that
+// rail are part of the wait, a wait shorter than them takes as long as them
+fn wait_after(since: Instant, delay_ms: u64) -> Instant {
+    std::thread::sleep(Duration::from_millis(delay_ms).saturating_sub(since.elapsed()));
+    Instant::now()
+}
+
+// power up in the given order, if a rail fails everything in the sequence is shut down
+pub fn power_up(
+    steps: &[SequenceStep],
+    instruments: &[Rail],
+    port_config: &PortConfig,
+    my_output: &mut String,
+) -> Result<(), String> {
+    let rails = find_rails(steps, instruments)?;
+    let mut next_rail = rails.iter();
+    let mut last_step = Instant::now();
+    for step in steps {
+        let result = match step {
+            SequenceStep::Wait(delay_ms) => {
+                last_step = wait_after(last_step, *delay_ms);
+                Ok(())
+            }
+            SequenceStep::Rail(_) => match next_rail.next() {
+                Some(instrument) => {
+                    last_step = Instant::now();
+                    switch_and_verify(instrument, port_config, true, my_output)
+                }
+                None => Ok(()),
+            },
+        };
+        if let Err(e) = result {
+            my_output.push_str(&format!("{}! Shutting everything down! \n", e));
+            for instrument in rails.iter().rev() {
+                // no checks here, just try to get everything off
+                match turn_on_off(&instrument.current_port, port_config, OFF_COMMAND) {
+                    Ok(()) => {
+                        my_output.push_str(&format!("{}: output OFF sent \n", instrument.label))
+                    }
+                    Err(e) => my_output.push_str(&format!("{}: {}! \n", instrument.label, e)),
+                }
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// power down in the reverse order, a failing rail does not stop the others from being switched off
+pub fn power_down(
+    steps: &[SequenceStep],
+    instruments: &[Rail],
+    port_config: &PortConfig,
+    my_output: &mut String,
+) -> Result<(), String> {
+    let rails = find_rails(steps, instruments)?;
+    let mut next_rail = rails.iter().rev();
+    let mut first_error = Ok(());
+    let mut last_step = Instant::now();
+    for step in steps.iter().rev() {
+        match step {
+            SequenceStep::Wait(delay_ms) => last_step = wait_after(last_step, *delay_ms),
+            SequenceStep::Rail(_) => {
+                if let Some(instrument) = next_rail.next() {
+                    last_step = Instant::now();
+                    if let Err(e) = switch_and_verify(instrument, port_config, false, my_output) {
+                        my_output.push_str(&format!("{}! \n", e));
+                        if first_error.is_ok() {
+                            first_error = Err(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    first_error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rails_and_waits() {
+        let steps = parse_sequence("3.3V on, wait 50 ms\n1.8V, wait 10, 5V ON").unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                SequenceStep::Rail("3.3V".to_string()),
+                SequenceStep::Wait(50),
+                SequenceStep::Rail("1.8V".to_string()),
+                SequenceStep::Wait(10),
+                SequenceStep::Rail("5V".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_sequences() {
+        assert!(parse_sequence("wait 10").is_err());
+        assert!(parse_sequence("").is_err());
+        assert!(parse_sequence("5V on, wait ten ms").is_err());
+        assert!(parse_sequence("5V on, wait 10, 5V on").is_err()); // twice
+    }
+
+    #[test]
+    fn only_the_word_wait_is_a_delay() {
+        let steps = parse_sequence("waiter-psu on, WAIT 5ms, Waitress").unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                SequenceStep::Rail("waiter-psu".to_string()),
+                SequenceStep::Wait(5),
+                SequenceStep::Rail("Waitress".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_wait_counts_from_the_step_before() {
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(30));
+        let end = wait_after(start, 50);
+        let waited = end.duration_since(start);
+        assert!(waited >= Duration::from_millis(50));
+        assert!(waited < Duration::from_millis(200));
+        // already over, no more waiting
+        let end = wait_after(start, 10);
+        assert!(end.duration_since(start) < Duration::from_millis(200));
+    }
+
+    #[test]
+    fn rails_must_be_known() {
+        let steps = parse_sequence("5V, 12V").unwrap();
+        let instruments = [Rail::new("5V", "SIMULATED")];
+        assert!(find_rails(&steps, &instruments).is_err());
+        let steps = parse_sequence("5V").unwrap();
+        assert_eq!(find_rails(&steps, &instruments).unwrap().len(), 1);
+    }
+
+    fn output_on(current_port: &str) -> bool {
+        get_status(current_port, &PortConfig::default())
+            .unwrap()
+            .output_on()
+    }
+
+    #[test]
+    fn power_up_and_down_on_simulated_rails() {
+        let rails = [
+            Rail::new("3V3", "SIMULATED:KD3005P sequence 3V3"),
+            Rail::new("5V", "SIMULATED:KD3005P sequence 5V"),
+        ];
+        let steps = parse_sequence("3V3 on, wait 10 ms, 5V on").unwrap();
+        let mut my_output = String::new();
+        power_up(&steps, &rails, &PortConfig::default(), &mut my_output).unwrap();
+        assert!(output_on(&rails[0].current_port));
+        assert!(output_on(&rails[1].current_port));
+        power_down(&steps, &rails, &PortConfig::default(), &mut my_output).unwrap();
+        assert!(!output_on(&rails[0].current_port));
+        assert!(!output_on(&rails[1].current_port));
+        // the 5V rail went off first
+        assert!(my_output.rfind("5V: output OFF") < my_output.rfind("3V3: output OFF"));
+    }
+
+    #[test]
+    fn a_failing_rail_shuts_everything_down() {
+        let mut rails = [
+            Rail::new("first", "SIMULATED:KD3005P sequence first"),
+            Rail::new("refused", "SIMULATED:KD3005P sequence refused"),
+        ];
+        // the simulated supply starts at 12 V, above this limit the output must stay off
+        rails[1].limits.max_voltage = 5.0;
+        let steps = parse_sequence("first on, refused on").unwrap();
+        let mut my_output = String::new();
+        let result = power_up(&steps, &rails, &PortConfig::default(), &mut my_output);
+        assert!(result.unwrap_err().starts_with("refused:"));
+        assert!(my_output.contains("Shutting everything down"));
+        assert!(!output_on(&rails[0].current_port));
+        assert!(!output_on(&rails[1].current_port));
+    }
+
+    #[test]
+    fn an_unknown_rail_switches_nothing() {
+        let rails = [Rail::new("known", "SIMULATED:KD3005P sequence known")];
+        let steps = parse_sequence("known on, unknown on").unwrap();
+        let mut my_output = String::new();
+        assert!(power_up(&steps, &rails, &PortConfig::default(), &mut my_output).is_err());
+        assert!(!output_on(&rails[0].current_port));
+    }
+}
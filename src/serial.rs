@@ -1,7 +1,19 @@
-use core::time::Duration;
-use serialport::{DataBits, FlowControl, Parity, SerialPort, SerialPortInfo, StopBits};
+use serialport::SerialPortInfo;
+use std::collections::BTreeSet;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+use tracing::{debug, error, warn};
 
-// define the strings for the supported commands
+use crate::capture::*;
+use crate::port_config::PortConfig;
+use crate::protocol::is_query;
+use crate::simulator::{is_simulated, SimulatedPort};
+use crate::status::Status;
+
+// define the strings for the supported commands, the channel commands are written
+// for channel 1 and turned into the other channels by channel_command()
 pub const ISET_COMMAND: &str = "ISET1:";
 pub const IGET_COMMAND: &str = "ISET1?";
 pub const VSET_COMMAND: &str = "VSET1:";
@@ -13,263 +25,340 @@ pub const VOUT_COMMAND: &str = "VOUT1?";
 pub const ON_COMMAND: &str = "OUT1";
 pub const OFF_COMMAND: &str = "OUT0";
 
-const ID_COMMAND: &str = "*IDN?";
-const STATUS_COMMAND: &str = "STATUS?";
+// over voltage / over current protection, not on every model
+pub const OVP_ON_COMMAND: &str = "OVP1";
+pub const OVP_OFF_COMMAND: &str = "OVP0";
+pub const OCP_ON_COMMAND: &str = "OCP1";
+pub const OCP_OFF_COMMAND: &str = "OCP0";
+
+// tracking of the two channels on dual output models
+pub const TRACK_INDEPENDENT_COMMAND: &str = "TRACK0";
+pub const TRACK_SERIES_COMMAND: &str = "TRACK1";
+pub const TRACK_PARALLEL_COMMAND: &str = "TRACK2";
+
+// the memories M1-M5: RCL3 takes the settings of M3, SAV3 stores the settings in M3
+pub const RECALL_COMMAND: &str = "RCL";
+pub const SAVE_COMMAND: &str = "SAV";
+pub const MEMORY_SLOTS: u8 = 5;
+
+pub const ID_COMMAND: &str = "*IDN?";
+pub const STATUS_COMMAND: &str = "STATUS?";
+
+// the target of the per-command traffic events, to filter them in or out
+pub const TRAFFIC_TARGET: &str = "kd3005p::traffic";
+
+// a query without answer is sent again this many times by send_command()
+pub const QUERY_RETRIES: u32 = 2;
+
+// what went wrong on the ports since the start, for the metrics
+pub static SERIAL_ERRORS: AtomicU64 = AtomicU64::new(0);
+pub static SERIAL_RETRIES: AtomicU64 = AtomicU64::new(0);
+pub static SERIAL_TIMEOUTS: AtomicU64 = AtomicU64::new(0);
+
+// e.g. "VSET1:" for channel 2 becomes "VSET2:"
+pub fn channel_command(command: &str, channel: u8) -> String {
+    command.replacen('1', &channel.to_string(), 1)
+}
+
+// anything commands can be sent through: a real serial port or the simulated supply
+pub trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send + ?Sized> Transport for T {}
 
 pub fn list_serial_ports() -> Vec<SerialPortInfo> {
-    let ports = serialport::available_ports().expect("No ports found!"); // get available ports
-    ports // return them
+    serialport::available_ports().expect("No ports found!") // get available ports and return them
+}
+
+// the ports that are open right now; the GUI, the polling thread and the watchdog all send
+// commands, a second open of the same port waits until the first one is closed again
+static PORTS_IN_USE: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+static PORT_CLOSED: Condvar = Condvar::new();
+
+fn claim_port(current_port: &str) {
+    let mut ports_in_use = PORTS_IN_USE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    while ports_in_use.contains(current_port) {
+        ports_in_use = PORT_CLOSED
+            .wait(ports_in_use)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+    ports_in_use.insert(current_port.to_string());
+}
+
+fn release_port(current_port: &str) {
+    PORTS_IN_USE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(current_port);
+    PORT_CLOSED.notify_all();
+}
+
+// an open port, the next one can open it when this one is dropped
+struct ClaimedPort {
+    port: Box<dyn Transport>,
+    current_port: String,
+}
+
+impl Read for ClaimedPort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for ClaimedPort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Drop for ClaimedPort {
+    fn drop(&mut self) {
+        release_port(&self.current_port);
+    }
+}
+
+fn open_port(current_port: &str, port_config: &PortConfig) -> Result<Box<dyn Transport>, String> {
+    claim_port(current_port);
+    let port = match open_transport(current_port, port_config) {
+        Ok(port) => port,
+        Err(e) => {
+            release_port(current_port);
+            warn!(port = current_port, "{}", e); // log the error, the caller shows it
+            return Err(e);
+        }
+    };
+    let port: Box<dyn Transport> = if capturing() {
+        // everything sent and received goes into the capture file too
+        Box::new(CapturingPort::new(port, current_port))
+    } else {
+        port
+    };
+    Ok(Box::new(ClaimedPort {
+        port,
+        current_port: current_port.to_string(),
+    }))
 }
 
-fn open_port(current_port: &String, my_output: &mut String) -> Result<Box<dyn SerialPort>, String> {
-    let open_string = format!("Trying to open port {}! \n", &current_port);
-    print!("{}", open_string); // print info
-    my_output.push_str(&open_string);
+fn open_transport(
+    current_port: &str,
+    port_config: &PortConfig,
+) -> Result<Box<dyn Transport>, String> {
+    debug!(target: TRAFFIC_TARGET, port = current_port, "Trying to open port");
+    if is_simulated(current_port) {
+        // no hardware needed for this one
+        return Ok(Box::new(SimulatedPort::open(current_port)?));
+    }
+    if let Some(path) = current_port.strip_prefix(REPLAY_PREFIX) {
+        // a recorded session instead of the supply
+        return Ok(Box::new(ReplayPort::open(path)?));
+    }
     if cfg!(target_os = "linux") {
         // if on linux, check path
         if !std::path::Path::new(current_port).exists() {
             // path does not exist?
+            SERIAL_ERRORS.fetch_add(1, Ordering::Relaxed);
             return Err("Port path does not exist".to_string()); // return error
         }
     }
-    // define the port
-    let serial_port = serialport::new(current_port, 9_600).timeout(Duration::from_millis(100));
+    // do not even try with settings the port can not handle
+    port_config.validate()?;
+    // define and configure the port
+    let serial_port = serialport::new(current_port, port_config.baud_rate)
+        .data_bits(port_config.data_bits)
+        .parity(port_config.parity)
+        .stop_bits(port_config.stop_bits)
+        .flow_control(port_config.flow_control)
+        .timeout(port_config.timeout());
 
     match serial_port.open() {
         // try to open it
         Ok(opened_port) => {
-            // it worked? Great, configure the port
-            let mut port = opened_port;
-            port.set_timeout(Duration::from_millis(100))
-                .expect("Setting timeout failed!");
-            port.set_data_bits(DataBits::Eight)
-                .expect("Setting data bits failed!");
-            port.set_parity(Parity::None)
-                .expect("Setting parity failed!");
-            port.set_stop_bits(StopBits::One)
-                .expect("Setting stop bit failed!");
-            port.set_flow_control(FlowControl::None)
-                .expect("Setting flow control failed!");
-            println!("Port {} opened successfully!", &current_port); // print info
-            Ok(port) // return the opened and configured port
+            // it worked? Great
+            debug!(target: TRAFFIC_TARGET, port = current_port, "Port opened");
+            Ok(Box::new(opened_port)) // return the opened and configured port
         }
         // if it did not work, return the error string
-        Err(e) => Err(format!("Error: Failed to open port, error code was: {}", e)),
+        Err(e) => {
+            SERIAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+            Err(format!("Error: Failed to open port, error code was: {}", e))
+        }
     }
 }
 
 pub fn get_set_amperage_voltage(
-    current_port: &String,
+    current_port: &str,
+    port_config: &PortConfig,
+    channel: u8,
     desired_command: &str,
-    answer_string: &mut String,
-    desired_setting: &String,
-    my_output: &mut String,
-) {
-    let mut say_hello = String::new();
-    match open_port(current_port, &mut say_hello) {
-        // try to open the selected port
-        Ok(returned_port) => {
-            // it worked? continue with the command
-            let mut port = returned_port; // fetch the port
-            my_output.push_str(&say_hello);
-            let mut my_command = String::new();
-            match desired_command {
-                IGET_COMMAND => {
-                    say_hello = "Get output amperage! \n".to_string();
-                    my_command = format!("{}", IGET_COMMAND);
-                }
-                VGET_COMMAND => {
-                    say_hello = "Get output voltage! \n".to_string();
-                    my_command = format!("{}", VGET_COMMAND);
-                }
-                ISET_COMMAND => {
-                    say_hello = format!("Set amperage to {} A! \n", desired_setting);
-                    my_command = format!("{}{}", ISET_COMMAND, desired_setting);
-                }
-                VSET_COMMAND => {
-                    say_hello = format!("Set voltage to {} V! \n", desired_setting);
-                    my_command = format!("{}{}", VSET_COMMAND, desired_setting);
-                }
-                IOUT_COMMAND => {
-                    say_hello = format!("Get actual amperage! \n");
-                    my_command = format!("{}", IOUT_COMMAND);
-                }
-                VOUT_COMMAND => {
-                    say_hello = format!("Get actual voltage! \n");
-                    my_command = format!("{}", VOUT_COMMAND);
-                }
-                ID_COMMAND => {
-                    say_hello = "Send ID command! \n".to_string();
-                    my_command = format!("{}", ID_COMMAND);
-                }
-                _ => {
-                    say_hello = format!("Unknown command, something went wrong! \n");
-                }
-            };
-            print!("{}", say_hello); // tell user what you do
-            my_output.push_str(&say_hello);
-
-            let mut transmit_output = String::new();
-            let mut my_answer_string = String::new();
-            transmit_serial(
-                &mut port,
-                &my_command,
-                &mut my_answer_string,
-                &mut transmit_output,
-            ); // transmit the message
-            my_output.push_str(&transmit_output);
-            answer_string.push_str(&my_answer_string);
-        }
-        Err(e) => {
-            // if it did not work
-            println!("{}", e); // print the error...
-            my_output.push_str(&e);
-            return; // ... and return
-        }
+    desired_setting: &str,
+) -> Result<String, String> {
+    let (say_hello, my_command) = match desired_command {
+        IGET_COMMAND => (
+            format!("Get output amperage of channel {}", channel),
+            channel_command(IGET_COMMAND, channel),
+        ),
+        VGET_COMMAND => (
+            format!("Get output voltage of channel {}", channel),
+            channel_command(VGET_COMMAND, channel),
+        ),
+        ISET_COMMAND => (
+            format!(
+                "Set amperage of channel {} to {} A",
+                channel, desired_setting
+            ),
+            format!(
+                "{}{}",
+                channel_command(ISET_COMMAND, channel),
+                desired_setting
+            ),
+        ),
+        VSET_COMMAND => (
+            format!(
+                "Set voltage of channel {} to {} V",
+                channel, desired_setting
+            ),
+            format!(
+                "{}{}",
+                channel_command(VSET_COMMAND, channel),
+                desired_setting
+            ),
+        ),
+        IOUT_COMMAND => (
+            format!("Get actual amperage of channel {}", channel),
+            channel_command(IOUT_COMMAND, channel),
+        ),
+        VOUT_COMMAND => (
+            format!("Get actual voltage of channel {}", channel),
+            channel_command(VOUT_COMMAND, channel),
+        ),
+        ID_COMMAND => ("Send ID command".to_string(), ID_COMMAND.to_string()),
+        _ => return Err(format!("Unknown command '{}'", desired_command)),
     };
+    let mut port = open_port(current_port, port_config)?; // try to open the selected port
+    debug!(target: TRAFFIC_TARGET, port = current_port, "{}", say_hello); // tell what you do
+    transmit_serial(&mut port, current_port, &my_command) // transmit the message
 }
 
-pub fn get_id(current_port: &String, my_output: &mut String) {
-    let mut say_hello = String::new();
-    match open_port(current_port, &mut say_hello) {
-        // try to open the selected port
-        Ok(returned_port) => {
-            // it worked? continue with the command
-            let mut port = returned_port; // fetch the port
-            my_output.push_str(&say_hello);
-
-            say_hello = "Send ID command!\n".to_string();
-            print!("{}", say_hello); // tell user what you do
-            my_output.push_str(&say_hello);
-
-            let mut transmit_output = String::new();
-            let mut answer_string = String::new();
-            transmit_serial(
-                &mut port,
-                ID_COMMAND,
-                &mut answer_string,
-                &mut transmit_output,
-            ); // transmit the message
-            my_output.push_str(&transmit_output);
-        }
-        Err(e) => {
-            // if it did not work
-            println!("{}", e); // print the error...
-            my_output.push_str(&e);
-            return; // ... and return
-        }
-    };
+pub fn get_id(current_port: &str, port_config: &PortConfig) -> Result<String, String> {
+    let mut port = open_port(current_port, port_config)?; // try to open the selected port
+    debug!(target: TRAFFIC_TARGET, port = current_port, "Send ID command");
+    transmit_serial(&mut port, current_port, ID_COMMAND) // transmit the message
 }
 
+// switch the output off or set the protections and tracking; the output is switched on only
+// through driver::Supply::set_output(), which checks the settings against the soft limits first
 pub fn turn_on_off(
-    current_port: &String,
+    current_port: &str,
+    port_config: &PortConfig,
     desired_command: &str,
-    answer_string: &mut String,
-    my_output: &mut String,
-) {
-    let mut say_hello = String::new();
-    match open_port(current_port, &mut say_hello) {
-        // try to open the selected port
-        Ok(returned_port) => {
-            // it worked? continue with the command
-            let mut port = returned_port; // fetch the port
-            my_output.push_str(&say_hello);
-            let mut my_command = String::new();
-            match desired_command {
-                ON_COMMAND => {
-                    say_hello = "Turn output ON! \n".to_string();
-                    my_command = format!("{}", ON_COMMAND);
-                }
-                OFF_COMMAND => {
-                    say_hello = "Turn output OFF! \n".to_string();
-                    my_command = format!("{}", OFF_COMMAND);
-                }
-                _ => {
-                    say_hello = format!("Unknown command, something went wrong! \n");
-                }
-            };
-            print!("{}", say_hello); // tell user what you do
-            my_output.push_str(&say_hello);
-
-            let mut transmit_output = String::new();
-            let mut my_answer_string = String::new();
-            transmit_serial(
-                &mut port,
-                &my_command,
-                &mut my_answer_string,
-                &mut transmit_output,
-            ); // transmit the message
-            my_output.push_str(&transmit_output);
-            answer_string.push_str(&my_answer_string);
-        }
-        Err(e) => {
-            // if it did not work
-            println!("{}", e); // print the error...
-            my_output.push_str(&e);
-            return; // ... and return
-        }
+) -> Result<(), String> {
+    let say_hello = match desired_command {
+        OFF_COMMAND => "Turn output OFF",
+        OVP_ON_COMMAND => "Turn over voltage protection ON",
+        OVP_OFF_COMMAND => "Turn over voltage protection OFF",
+        OCP_ON_COMMAND => "Turn over current protection ON",
+        OCP_OFF_COMMAND => "Turn over current protection OFF",
+        TRACK_INDEPENDENT_COMMAND => "Set channels to independent",
+        TRACK_SERIES_COMMAND => "Set channels to series tracking",
+        TRACK_PARALLEL_COMMAND => "Set channels to parallel tracking",
+        _ => return Err(format!("Unknown command '{}'", desired_command)),
     };
+    let mut port = open_port(current_port, port_config)?; // try to open the selected port
+    debug!(target: TRAFFIC_TARGET, port = current_port, "{}", say_hello); // tell what you do
+    transmit_serial(&mut port, current_port, desired_command)?; // transmit the message
+    Ok(())
 }
 
-pub fn get_status(current_port: &String, my_output: &mut String) {
-    let mut say_hello = String::new();
-    match open_port(current_port, &mut say_hello) {
-        // try to open the selected port
-        Ok(returned_port) => {
-            // it worked? continue with the command
-            let mut port = returned_port; // fetch the port
-            my_output.push_str(&say_hello);
-
-            say_hello = "Send status command!\n".to_string();
-            print!("{}", say_hello); // tell user what you do
-            my_output.push_str(&say_hello);
-
-            let mut transmit_output = String::new();
-            let mut answer_string = String::new();
-            transmit_serial(
-                &mut port,
-                STATUS_COMMAND,
-                &mut answer_string,
-                &mut transmit_output,
-            ); // transmit the message
-            my_output.push_str(&transmit_output);
-        }
-        Err(e) => {
-            // if it did not work
-            println!("{}", e); // print the error...
-            return; // ... and return
+pub fn get_status(current_port: &str, port_config: &PortConfig) -> Result<Status, String> {
+    let mut port = open_port(current_port, port_config)?; // try to open the selected port
+    debug!(target: TRAFFIC_TARGET, port = current_port, "Send status command");
+    let answer = transmit_serial_bytes(&mut port, current_port, STATUS_COMMAND)?; // transmit the message
+    Status::from_answer(&answer).ok_or_else(|| "No valid status byte received".to_string())
+    // decode the status byte
+}
+
+// send any command as it is and return the raw answer bytes, a query without answer is tried again
+pub fn send_command(
+    current_port: &str,
+    port_config: &PortConfig,
+    command: &str,
+) -> Result<Vec<u8>, String> {
+    let mut attempt = 0;
+    loop {
+        let mut port = open_port(current_port, port_config)?; // try to open the selected port
+        let answer = transmit_serial_bytes(&mut port, current_port, command)?; // transmit the message
+        if answer.is_empty() && is_query(command) && attempt < QUERY_RETRIES {
+            attempt += 1;
+            SERIAL_RETRIES.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                port = current_port,
+                command, attempt, "No answer, trying again"
+            );
+            continue;
         }
-    };
+        return Ok(answer);
+    }
 }
 
-fn transmit_serial(
-    port: &mut Box<dyn SerialPort>,
+// send a command and return the raw answer bytes
+fn transmit_serial_bytes(
+    port: &mut Box<dyn Transport>,
+    current_port: &str,
     command: &str,
-    answer: &mut String,
-    my_output: &mut String,
-) {
-    let mut transmit_output = format!(">>  {:?} \n", command.as_bytes());
-    print!("{}", transmit_output); // tell user what you want to do
-    my_output.push_str(&transmit_output);
+) -> Result<Vec<u8>, String> {
+    let started = Instant::now(); // for the latency
     let output = command.as_bytes(); // define data to write to serial interface
-    port.write(output).expect("Write failed!"); // write it
+    if let Err(e) = port.write_all(output) {
+        // write it, or tell why not
+        SERIAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+        error!(port = current_port, command, error = %e, "Write failed");
+        return Err(format!("Write failed: {}", e));
+    }
 
     let mut serial_buf: Vec<u8> = vec![0; 32]; // define the receive buffer
-    let mut result_vec: Vec<u8> = Vec::new(); // define the print buffer
+    let mut result_vec: Vec<u8> = Vec::new(); // define the answer buffer
     let mut length = 1;
     while length > 0 {
         // as long as data is received
-        length = port.read(serial_buf.as_mut_slice()).unwrap_or(0); // read from port
-        result_vec.extend_from_slice(&serial_buf[..length]); // add data to print buffer
-                                                             // println!("{:?}", result_vec);
-                                                             // println!("{}", std::str::from_utf8(&result_vec).unwrap());
+        length = match port.read(serial_buf.as_mut_slice()) {
+            Ok(length) => length,
+            Err(e) if e.kind() == ErrorKind::TimedOut => 0, // the end of every answer
+            Err(e) => {
+                SERIAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+                error!(port = current_port, command, error = %e, "Read failed");
+                0
+            }
+        };
+        result_vec.extend_from_slice(&serial_buf[..length]); // add data to answer buffer
+    }
+    if result_vec.is_empty() && is_query(command) {
+        SERIAL_TIMEOUTS.fetch_add(1, Ordering::Relaxed); // a query that got nothing back
+        warn!(port = current_port, command, "No answer to the query");
     }
-    transmit_output = format!("<<  {:?} \n", result_vec);
-    print!("{}", transmit_output); // print the result
-    my_output.push_str(&transmit_output);
-    let answer_string = std::str::from_utf8(&result_vec).unwrap();
-    transmit_output = format!(" <  {} \n\n", answer_string);
-    print!("{}", transmit_output); // print it as ASCII
-    my_output.push_str(&transmit_output);
-    answer.push_str(&answer_string);
+    // one event per command with everything that went over the wire
+    debug!(
+        target: TRAFFIC_TARGET,
+        port = current_port,
+        command,
+        tx = ?command.as_bytes(),
+        rx = ?result_vec,
+        answer = %String::from_utf8_lossy(&result_vec),
+        latency_ms = started.elapsed().as_millis() as u64,
+    );
+    Ok(result_vec)
+}
+
+// send a command and return the answer as text
+fn transmit_serial(
+    port: &mut Box<dyn Transport>,
+    current_port: &str,
+    command: &str,
+) -> Result<String, String> {
+    let result_vec = transmit_serial_bytes(port, current_port, command)?;
+    Ok(String::from_utf8_lossy(&result_vec).to_string())
 }
@@ -0,0 +1,395 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+use serialport::SerialPortType;
+
+use crate::limits::*;
+use crate::port_config::*;
+use crate::profiles::*;
+
+// the folder of the program in the config directory and the file in it
+const CONFIG_FOLDER: &str = "kd3005p-rs";
+const SETTINGS_FILE: &str = "settings.conf";
+
+// the live readings must not be faster than the supply answers, nor too slow to be useful
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+const MIN_POLL_INTERVAL_MS: u64 = 100;
+const MAX_POLL_INTERVAL_MS: u64 = 60_000;
+
+// the setpoints of a new channel, like the supply out of the box
+pub const DEFAULT_VOLTAGE: f64 = 12.0;
+pub const DEFAULT_AMPERAGE: f64 = 1.0;
+
+// the main window of the GUI
+pub const DEFAULT_WINDOW_WIDTH: f64 = 480.0;
+pub const DEFAULT_WINDOW_HEIGHT: f64 = 700.0;
+
+// names of the settings in the file
+pub const PORT_KEY: &str = "port";
+pub const PORT_SERIAL_KEY: &str = "port_serial";
+pub const VOLTAGE_KEY: &str = "voltage";
+pub const AMPERAGE_KEY: &str = "current";
+pub const POLL_INTERVAL_KEY: &str = "poll_interval_ms";
+pub const WINDOW_WIDTH_KEY: &str = "window_width";
+pub const WINDOW_HEIGHT_KEY: &str = "window_height";
+
+// everything that is kept between two starts of the program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub last_port: String,        // empty when no port was used yet
+    pub last_port_serial: String, // USB serial number of the last port, empty if it has none
+    pub voltage: f64,             // setpoints of a new channel
+    pub amperage: f64,
+    pub poll_interval_ms: u64, // of the live readings
+    pub limits: SafetyLimits,
+    pub port_config: PortConfig, // of every supply, the flags like --baud-rate go on top
+    pub window_width: f64,
+    pub window_height: f64,
+    pub profiles: Vec<Profile>,
+}
+
+// the settings can be compared directly, so the GUI can hold them
+#[cfg(feature = "gui")]
+impl Data for Settings {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            last_port: String::new(),
+            last_port_serial: String::new(),
+            voltage: DEFAULT_VOLTAGE,
+            amperage: DEFAULT_AMPERAGE,
+            poll_interval_ms: DEFAULT_POLL_INTERVAL_MS,
+            limits: SafetyLimits::default(),
+            port_config: PortConfig::default(),
+            window_width: DEFAULT_WINDOW_WIDTH,
+            window_height: DEFAULT_WINDOW_HEIGHT,
+            profiles: Vec::new(),
+        }
+    }
+}
+
+pub fn parse_setpoint(key: &str, value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok(number),
+        _ => Err(format!("Invalid {} '{}'", key.trim(), value.trim())),
+    }
+}
+
+// $XDG_CONFIG_HOME or ~/.config on Linux, the usual places on Windows and macOS
+pub fn config_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").filter(|home| !home.is_empty());
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home.map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => Some(PathBuf::from(dir)),
+            None => home.map(|home| PathBuf::from(home).join(".config")),
+        }
+    }?;
+    Some(base.join(CONFIG_FOLDER))
+}
+
+// where the settings are read from and saved to when no --config is given
+pub fn default_settings_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(SETTINGS_FILE))
+}
+
+// the USB serial number of a port, to find the supply again under another name
+pub fn usb_serial_number(port: &str) -> Option<String> {
+    serialport::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|info| info.port_name == port)
+        .and_then(|info| match info.port_type {
+            SerialPortType::UsbPort(usb) => usb.serial_number,
+            _ => None,
+        })
+}
+
+impl Settings {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    // change a single setting by its name, the value is checked before it is taken over
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key.trim() {
+            PORT_KEY => self.last_port = value.to_string(),
+            PORT_SERIAL_KEY => self.last_port_serial = value.to_string(),
+            VOLTAGE_KEY => self.voltage = parse_setpoint(key, value)?,
+            AMPERAGE_KEY => self.amperage = parse_setpoint(key, value)?,
+            POLL_INTERVAL_KEY => {
+                self.poll_interval_ms = match value.parse::<u64>() {
+                    Ok(interval)
+                        if (MIN_POLL_INTERVAL_MS..=MAX_POLL_INTERVAL_MS).contains(&interval) =>
+                    {
+                        interval
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Invalid poll interval '{}' ({} - {} ms)",
+                            value, MIN_POLL_INTERVAL_MS, MAX_POLL_INTERVAL_MS
+                        ))
+                    }
+                }
+            }
+            WINDOW_WIDTH_KEY | WINDOW_HEIGHT_KEY => {
+                let size = match value.parse::<f64>() {
+                    Ok(size) if size >= 100.0 => size,
+                    _ => return Err(format!("Invalid window size '{}'", value)),
+                };
+                if key.trim() == WINDOW_WIDTH_KEY {
+                    self.window_width = size;
+                } else {
+                    self.window_height = size;
+                }
+            }
+            BAUD_RATE_KEY | DATA_BITS_KEY | PARITY_KEY | STOP_BITS_KEY | FLOW_CONTROL_KEY
+            | TIMEOUT_KEY => self.port_config.set(key.trim(), value)?,
+            // the soft limits have the same names as on the command line
+            _ => self.limits.set(key, value)?,
+        }
+        Ok(())
+    }
+
+    // read the settings from a file with one "key = value" per line, a line starting with #
+    // is a comment, "[profile <name>]" starts a profile
+    pub fn from_file(path: &str) -> Result<Settings, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read settings {}: {}", path, e))?;
+        Settings::parse(&content).map_err(|e| format!("{}:{}", path, e))
+    }
+
+    // like from_file(), but a missing file is no error, the defaults are used then
+    pub fn load(path: &str) -> Result<Settings, String> {
+        if !std::path::Path::new(path).exists() {
+            return Ok(Settings::default());
+        }
+        Settings::from_file(path)
+    }
+
+    // the errors start with the line number
+    pub fn parse(content: &str) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        for (number, line) in content.lines().enumerate() {
+            // only a whole line is a comment, a # in a value (e.g. in the notes) is kept
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                let name = match section.trim().split_once(char::is_whitespace) {
+                    Some((PROFILE_SECTION, name)) if !name.trim().is_empty() => name,
+                    _ => {
+                        return Err(format!(
+                            "{}: expected '[profile <name>]', not '{}'",
+                            number + 1,
+                            line
+                        ))
+                    }
+                };
+                settings.profiles.push(Profile::new(name));
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}: expected 'key = value'", number + 1))?;
+            // the keys after a profile section belong to that profile
+            match settings.profiles.last_mut() {
+                Some(profile) => profile.set(key, value),
+                None => settings.set(key, value),
+            }
+            .map_err(|e| format!("{}: {}", number + 1, e))?;
+        }
+        Ok(settings)
+    }
+
+    // the counterpart of parse(), the general settings first, then the profiles
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# kd3005p-rs settings, written when the program ends\n");
+        text.push_str(&format!("{} = {}\n", PORT_KEY, self.last_port));
+        text.push_str(&format!(
+            "{} = {}\n",
+            PORT_SERIAL_KEY, self.last_port_serial
+        ));
+        text.push_str(&format!("{} = {}\n", VOLTAGE_KEY, self.voltage));
+        text.push_str(&format!("{} = {}\n", AMPERAGE_KEY, self.amperage));
+        text.push_str(&format!(
+            "{} = {}\n",
+            POLL_INTERVAL_KEY, self.poll_interval_ms
+        ));
+        for key in [
+            MAX_VOLTAGE_KEY,
+            MAX_AMPERAGE_KEY,
+            MAX_POWER_KEY,
+            MAX_ON_TIME_KEY,
+            WATCHDOG_KEY,
+        ] {
+            text.push_str(&format!("{} = {}\n", key, self.limits.get(key)));
+        }
+        for key in [
+            BAUD_RATE_KEY,
+            DATA_BITS_KEY,
+            PARITY_KEY,
+            STOP_BITS_KEY,
+            FLOW_CONTROL_KEY,
+            TIMEOUT_KEY,
+        ] {
+            text.push_str(&format!("{} = {}\n", key, self.port_config.get(key)));
+        }
+        text.push_str(&format!("{} = {}\n", WINDOW_WIDTH_KEY, self.window_width));
+        text.push_str(&format!("{} = {}\n", WINDOW_HEIGHT_KEY, self.window_height));
+        for profile in &self.profiles {
+            text.push('\n');
+            text.push_str(&profile.to_text());
+        }
+        text
+    }
+
+    // write the settings, the folder is created if it is not there yet
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() {
+                std::fs::create_dir_all(dir)
+                    .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+            }
+        }
+        std::fs::write(path, self.to_text())
+            .map_err(|e| format!("Could not write settings {}: {}", path, e))
+    }
+
+    // the port of the last supply: by its USB serial number when it has one, as the names of
+    // USB ports change with the order they are plugged in, otherwise by its name
+    pub fn find_port(&self) -> Option<String> {
+        if !self.last_port_serial.is_empty() {
+            let found = serialport::available_ports()
+                .unwrap_or_default()
+                .into_iter()
+                .find(|info| match &info.port_type {
+                    SerialPortType::UsbPort(usb) => {
+                        usb.serial_number.as_deref() == Some(self.last_port_serial.as_str())
+                    }
+                    _ => false,
+                });
+            if let Some(info) = found {
+                return Some(info.port_name);
+            }
+        }
+        if self.last_port.is_empty() {
+            None
+        } else {
+            Some(self.last_port.clone())
+        }
+    }
+
+    pub fn find_profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name == name.trim())
+    }
+
+    // add a profile, one with the same name is replaced
+    pub fn add_profile(&mut self, profile: Profile) {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(old_profile) => *old_profile = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    pub fn remove_profile(&mut self, name: &str) -> bool {
+        let count = self.profiles.len();
+        self.profiles.retain(|profile| profile.name != name.trim());
+        self.profiles.len() != count
+    }
+
+    // remember the port of a supply, with its USB serial number if it has one
+    pub fn remember_port(&mut self, port: &str) {
+        self.last_port = port.to_string();
+        self.last_port_serial = usb_serial_number(port).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_to_text_round_trip() {
+        let mut settings = Settings {
+            last_port: "/dev/ttyACM0".to_string(),
+            voltage: 3.3,
+            amperage: 0.5,
+            poll_interval_ms: 250,
+            window_width: 640.0,
+            ..Settings::default()
+        };
+        settings.limits.max_voltage = 5.5;
+        settings.limits.watchdog_seconds = 10.0;
+        settings.port_config.set(BAUD_RATE_KEY, "115200").unwrap();
+        settings.port_config.set(PARITY_KEY, "even").unwrap();
+        let mut profile = Profile::new("ESP32 board");
+        profile.voltage = 3.3;
+        profile.amperage = 0.5;
+        profile.limits.max_voltage = 3.6;
+        profile.notes = "first line\nsecond line".to_string();
+        settings.add_profile(profile);
+        settings.add_profile(Profile::new("LED strip"));
+        assert_eq!(Settings::parse(&settings.to_text()).unwrap(), settings);
+    }
+
+    #[test]
+    fn parse_gives_the_line_of_an_error() {
+        assert_eq!(Settings::parse("").unwrap(), Settings::default());
+        let error = Settings::parse("# comment\nvoltage = 5\npoll_interval_ms = 5\n").unwrap_err();
+        assert!(error.starts_with("3: Invalid poll interval"));
+        assert!(Settings::parse("colour = red")
+            .unwrap_err()
+            .starts_with("1: "));
+        assert!(Settings::parse("voltage 5").is_err());
+        assert!(Settings::parse("[profiles]").is_err());
+        assert!(Settings::parse("voltage = -1").is_err());
+    }
+
+    #[test]
+    fn only_a_whole_line_is_a_comment() {
+        let settings = Settings::parse("  # port = COM1\nport = /dev/ttyUSB#1\n").unwrap();
+        assert_eq!(settings.last_port, "/dev/ttyUSB#1");
+        // a comment after a value is part of the value
+        assert!(Settings::parse("voltage = 5 # the board").is_err());
+    }
+
+    #[test]
+    fn keys_after_a_profile_section_belong_to_the_profile() {
+        let settings =
+            Settings::parse("voltage = 5\n[profile USB]\nvoltage = 5.1\nmax_current = 2\n")
+                .unwrap();
+        assert_eq!(settings.voltage, 5.0);
+        let profile = settings.find_profile(" USB ").unwrap();
+        assert_eq!(profile.voltage, 5.1);
+        assert_eq!(profile.limits.max_amperage, 2.0);
+        assert_eq!(settings.limits, SafetyLimits::default());
+    }
+
+    #[test]
+    fn profiles_are_replaced_by_name() {
+        let mut settings = Settings::default();
+        settings.add_profile(Profile::new("USB"));
+        let mut profile = Profile::new("USB");
+        profile.voltage = 5.0;
+        settings.add_profile(profile);
+        assert_eq!(settings.profiles.len(), 1);
+        assert_eq!(settings.profiles[0].voltage, 5.0);
+        assert!(settings.remove_profile("USB"));
+        assert!(!settings.remove_profile("USB"));
+    }
+}
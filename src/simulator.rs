@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+
+use crate::model::*;
+use crate::serial::*;
+
+// the port name that selects the simulated supply instead of a real port; "SIMULATED:KA3305P"
+// simulates another model, and every port name is a supply of its own
+pub const SIMULATED_PORT: &str = "SIMULATED";
+const MODEL_SEPARATOR: char = ':';
+
+const LOAD_RESISTANCE: f64 = 10.0; // every simulated channel drives a 10 Ohm resistor
+
+// the tracking bits 2 and 3 of the status byte
+const TRACK_INDEPENDENT_BITS: u8 = 0x00;
+const TRACK_SERIES_BITS: u8 = 0x04;
+const TRACK_PARALLEL_BITS: u8 = 0x0C;
+
+// the settings of one output channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ChannelState {
+    voltage_setting: f64,
+    amperage_setting: f64,
+}
+
+impl ChannelState {
+    // true if the load wants more current than the limit allows
+    fn constant_current(&self) -> bool {
+        self.voltage_setting / LOAD_RESISTANCE > self.amperage_setting
+    }
+
+    // voltage and current into the load resistor while the output is on
+    fn output(&self) -> (f64, f64) {
+        if self.constant_current() {
+            (
+                self.amperage_setting * LOAD_RESISTANCE,
+                self.amperage_setting,
+            )
+        } else {
+            (self.voltage_setting, self.voltage_setting / LOAD_RESISTANCE)
+        }
+    }
+}
+
+// the state of one simulated supply, kept between the port openings like on the real thing
+struct SupplyState {
+    model: &'static Model,
+    channels: Vec<ChannelState>,
+    output_on: bool, // OUT1/OUT0 switch all channels together, like on the real one
+    ovp: bool,
+    ocp: bool,
+    beep: bool,
+    tracking_bits: u8,
+    memories: [(f64, f64); MEMORY_SLOTS as usize], // voltage and current of M1-M5 of CH1
+}
+
+// the simulated supplies by port name
+static SUPPLIES: Mutex<BTreeMap<String, SupplyState>> = Mutex::new(BTreeMap::new());
+
+impl SupplyState {
+    fn new(model: &'static Model) -> Self {
+        SupplyState {
+            model,
+            channels: vec![
+                ChannelState {
+                    voltage_setting: 12.0,
+                    amperage_setting: 1.0,
+                };
+                model.channels as usize
+            ],
+            output_on: false,
+            ovp: false,
+            ocp: false,
+            beep: true,
+            tracking_bits: TRACK_INDEPENDENT_BITS,
+            memories: [(12.0, 1.0); MEMORY_SLOTS as usize],
+        }
+    }
+
+    fn tracking(&self) -> bool {
+        self.tracking_bits != TRACK_INDEPENDENT_BITS
+    }
+
+    // while tracking, channel 2 follows the settings of channel 1
+    fn follow_channel_1(&mut self) {
+        if self.tracking() && self.channels.len() > 1 {
+            self.channels[1] = self.channels[0];
+        }
+    }
+
+    // the over current protection switches off as soon as a channel goes into CC
+    fn protect(&mut self) {
+        if self.ocp && self.output_on && self.channels.iter().any(ChannelState::constant_current) {
+            self.output_on = false;
+        }
+    }
+
+    // actual output voltage and current of a channel (index from 0)
+    fn output(&mut self, index: usize) -> (f64, f64) {
+        self.protect();
+        if !self.output_on {
+            return (0.0, 0.0);
+        }
+        self.channels[index].output()
+    }
+
+    fn set_voltage(&mut self, index: usize, voltage: f64) {
+        if index == 1 && self.tracking() {
+            return; // channel 2 is set through channel 1 then
+        }
+        // the output capacitor holds the old voltage for a moment, which trips the OVP of
+        // the real supply when the voltage is turned down with the output on
+        if self.ovp && self.output_on && voltage < self.channels[index].voltage_setting {
+            self.output_on = false;
+        }
+        self.channels[index].voltage_setting = voltage;
+        self.follow_channel_1();
+    }
+
+    fn set_amperage(&mut self, index: usize, amperage: f64) {
+        if index == 1 && self.tracking() {
+            return;
+        }
+        self.channels[index].amperage_setting = amperage;
+        self.follow_channel_1();
+    }
+
+    fn status_byte(&mut self) -> u8 {
+        self.protect(); // let the protection act first
+        let mut byte = 0x20 | self.tracking_bits; // panel unlocked
+        for (index, channel) in self.channels.iter().enumerate() {
+            if !channel.constant_current() {
+                byte |= 0x01 << index; // CV, bit 0 for CH1, bit 1 for CH2
+            }
+        }
+        if self.beep {
+            byte |= 0x10;
+        }
+        if self.output_on {
+            byte |= 0x40;
+        }
+        byte
+    }
+}
+
+// true for every port name that is a simulated supply
+pub fn is_simulated(current_port: &str) -> bool {
+    current_port == SIMULATED_PORT
+        || current_port
+            .strip_prefix(SIMULATED_PORT)
+            .is_some_and(|rest| rest.starts_with(MODEL_SEPARATOR))
+}
+
+// the model of "SIMULATED" or "SIMULATED:<model>"
+fn simulated_model(current_port: &str) -> Result<&'static Model, String> {
+    match current_port.split_once(MODEL_SEPARATOR) {
+        Some((_, name)) => {
+            identify(name).ok_or_else(|| format!("There is no model '{}' to simulate", name))
+        }
+        None => Ok(&KD3005P),
+    }
+}
+
+// a port that answers like a Korad supply, usable wherever a serial port is
+pub struct SimulatedPort {
+    current_port: String,
+    answer: Vec<u8>,
+}
+
+impl SimulatedPort {
+    // the supply of this port name, a new one the first time
+    pub fn open(current_port: &str) -> Result<Self, String> {
+        let model = simulated_model(current_port)?;
+        lock_supplies()
+            .entry(current_port.to_string())
+            .or_insert_with(|| SupplyState::new(model));
+        Ok(SimulatedPort {
+            current_port: current_port.to_string(),
+            answer: Vec::new(),
+        })
+    }
+}
+
+fn lock_supplies() -> std::sync::MutexGuard<'static, BTreeMap<String, SupplyState>> {
+    SUPPLIES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Write for SimulatedPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let command = String::from_utf8_lossy(buf);
+        let mut supplies = lock_supplies();
+        self.answer = match supplies.get_mut(&self.current_port) {
+            Some(state) => answer_command(state, command.trim()),
+            None => Vec::new(),
+        };
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for SimulatedPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // hand out the answer, then nothing like a port that timed out
+        let length = buf.len().min(self.answer.len());
+        buf[..length].copy_from_slice(&self.answer[..length]);
+        self.answer.drain(..length);
+        Ok(length)
+    }
+}
+
+// read a setting like "12.34" and keep it inside the limits of the supply
+fn parse_setting(value: &str, max: f64) -> Option<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .map(|number| number.max(0.0).min(max))
+}
+
+// the index of the memory of "RCL3" or "SAV3", None for other commands or no such memory
+fn memory_slot(command: &str, prefix: &str) -> Option<usize> {
+    let slot = command.strip_prefix(prefix)?.trim().parse::<usize>().ok()?;
+    (1..=MEMORY_SLOTS as usize)
+        .contains(&slot)
+        .then(|| slot - 1)
+}
+
+// the channel index and the rest of e.g. "VSET2:5.00" for "VSET1:", None for other commands
+fn channel_part<'a>(
+    state: &SupplyState,
+    command: &'a str,
+    template: &str,
+) -> Option<(usize, &'a str)> {
+    (1..=state.model.channels).find_map(|channel| {
+        command
+            .strip_prefix(&channel_command(template, channel))
+            .map(|rest| (channel as usize - 1, rest))
+    })
+}
+
+// what the simulated supply does with a command, returns the answer bytes
+fn answer_command(state: &mut SupplyState, command: &str) -> Vec<u8> {
+    let model = state.model;
+    if let Some((index, value)) = channel_part(state, command, VSET_COMMAND) {
+        if let Some(voltage) = parse_setting(value, model.max_voltage) {
+            state.set_voltage(index, voltage);
+        }
+        return Vec::new();
+    }
+    if let Some((index, value)) = channel_part(state, command, ISET_COMMAND) {
+        if let Some(amperage) = parse_setting(value, model.max_amperage) {
+            state.set_amperage(index, amperage);
+        }
+        return Vec::new();
+    }
+    if let Some((index, "")) = channel_part(state, command, VGET_COMMAND) {
+        return format!("{:05.2}", state.channels[index].voltage_setting).into_bytes();
+    }
+    if let Some((index, "")) = channel_part(state, command, IGET_COMMAND) {
+        return format!("{:.3}", state.channels[index].amperage_setting).into_bytes();
+    }
+    if let Some((index, "")) = channel_part(state, command, VOUT_COMMAND) {
+        return format!("{:05.2}", state.output(index).0).into_bytes();
+    }
+    if let Some((index, "")) = channel_part(state, command, IOUT_COMMAND) {
+        return format!("{:.3}", state.output(index).1).into_bytes();
+    }
+    if let Some(slot) = memory_slot(command, RECALL_COMMAND) {
+        let (voltage, amperage) = state.memories[slot];
+        state.set_voltage(0, voltage);
+        state.set_amperage(0, amperage);
+        return Vec::new();
+    }
+    if let Some(slot) = memory_slot(command, SAVE_COMMAND) {
+        state.memories[slot] = (
+            state.channels[0].voltage_setting,
+            state.channels[0].amperage_setting,
+        );
+        return Vec::new();
+    }
+    match command {
+        ID_COMMAND => return format!("KORAD {} V2.0 (simulated)", model.name).into_bytes(),
+        STATUS_COMMAND => return vec![state.status_byte()],
+        ON_COMMAND => state.output_on = true,
+        OFF_COMMAND => state.output_on = false,
+        OVP_ON_COMMAND if model.ovp_ocp => state.ovp = true,
+        OVP_OFF_COMMAND if model.ovp_ocp => state.ovp = false,
+        OCP_ON_COMMAND if model.ovp_ocp => state.ocp = true,
+        OCP_OFF_COMMAND if model.ovp_ocp => state.ocp = false,
+        TRACK_INDEPENDENT_COMMAND if model.tracking => state.tracking_bits = TRACK_INDEPENDENT_BITS,
+        TRACK_SERIES_COMMAND if model.tracking => {
+            state.tracking_bits = TRACK_SERIES_BITS;
+            state.follow_channel_1();
+        }
+        TRACK_PARALLEL_COMMAND if model.tracking => {
+            state.tracking_bits = TRACK_PARALLEL_BITS;
+            state.follow_channel_1();
+        }
+        "BEEP1" => state.beep = true,
+        "BEEP0" => state.beep = false,
+        _ => {} // like the real one: unknown commands are ignored
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every test has its own port name, so its supply is its own while the tests run
+    fn query(current_port: &str, command: &str) -> String {
+        let mut port = SimulatedPort::open(current_port).unwrap();
+        port.write_all(command.as_bytes()).unwrap();
+        let mut answer = Vec::new();
+        port.read_to_end(&mut answer).unwrap();
+        String::from_utf8_lossy(&answer).to_string()
+    }
+
+    fn status_byte(current_port: &str) -> u8 {
+        query(current_port, STATUS_COMMAND).as_bytes()[0]
+    }
+
+    #[test]
+    fn port_names_of_the_simulator() {
+        assert!(is_simulated("SIMULATED"));
+        assert!(is_simulated("SIMULATED:KA3305P"));
+        assert!(!is_simulated("SIMULATEDX"));
+        assert!(!is_simulated("/dev/ttyACM0"));
+        assert!(SimulatedPort::open("SIMULATED:KX9999").is_err());
+        assert_eq!(
+            query("SIMULATED:KA3305P id", ID_COMMAND),
+            "KORAD KA3305P V2.0 (simulated)"
+        );
+    }
+
+    #[test]
+    fn every_port_is_a_supply_of_its_own() {
+        query("SIMULATED:KD3005P first", "VSET1:5.00");
+        query("SIMULATED:KD3005P second", "VSET1:3.30");
+        assert_eq!(query("SIMULATED:KD3005P first", "VSET1?"), "05.00");
+        assert_eq!(query("SIMULATED:KD3005P second", "VSET1?"), "03.30");
+    }
+
+    #[test]
+    fn the_output_drives_the_load_resistor() {
+        let port = "SIMULATED:KD3005P output";
+        query(port, "VSET1:5.00");
+        assert_eq!(query(port, "VOUT1?"), "00.00"); // output off
+        query(port, ON_COMMAND);
+        assert_eq!(query(port, "VOUT1?"), "05.00");
+        assert_eq!(query(port, "IOUT1?"), "0.500");
+        assert_eq!(status_byte(port) & 0x41, 0x41); // on, CV
+        query(port, "ISET1:0.100"); // 10 Ohm at 0.1 A is 1 V
+        assert_eq!(query(port, "VOUT1?"), "01.00");
+        assert_eq!(status_byte(port) & 0x01, 0x00); // CC
+        query(port, "VSET1:99"); // kept inside the model
+        assert_eq!(query(port, "VSET1?"), "30.00");
+    }
+
+    #[test]
+    fn channel_2_and_tracking() {
+        let port = "SIMULATED:KA3305P tracking";
+        query(port, "VSET2:3.00");
+        assert_eq!(query(port, "VSET2?"), "03.00");
+        assert_eq!(query(port, "VSET1?"), "12.00");
+        assert_eq!(query("SIMULATED:KD3005P channel", "VSET2?"), ""); // one channel only
+        query(port, TRACK_SERIES_COMMAND);
+        assert_eq!(query(port, "VSET2?"), "12.00");
+        query(port, "VSET1:6.00");
+        query(port, "VSET2:1.00"); // ignored while tracking
+        assert_eq!(query(port, "VSET2?"), "06.00");
+        assert_eq!(status_byte(port) & 0x0C, TRACK_SERIES_BITS);
+        query(port, TRACK_PARALLEL_COMMAND);
+        assert_eq!(status_byte(port) & 0x0C, TRACK_PARALLEL_BITS);
+        query(port, TRACK_INDEPENDENT_COMMAND);
+        query(port, "VSET2:1.00");
+        assert_eq!(query(port, "VSET2?"), "01.00");
+        assert_eq!(status_byte(port) & 0x0C, TRACK_INDEPENDENT_BITS);
+    }
+
+    #[test]
+    fn ocp_and_ovp_switch_the_output_off() {
+        let port = "SIMULATED:KD3005P protection";
+        query(port, "VSET1:5.00");
+        query(port, OCP_ON_COMMAND);
+        query(port, ON_COMMAND);
+        assert_eq!(status_byte(port) & 0x40, 0x40);
+        query(port, "ISET1:0.100"); // CC trips the OCP
+        assert_eq!(status_byte(port) & 0x40, 0x00);
+        query(port, OCP_OFF_COMMAND);
+        query(port, OVP_ON_COMMAND);
+        query(port, ON_COMMAND);
+        query(port, "VSET1:6.00"); // up is fine
+        assert_eq!(status_byte(port) & 0x40, 0x40);
+        query(port, "VSET1:0.50"); // down trips the OVP
+        assert_eq!(status_byte(port) & 0x40, 0x00);
+    }
+
+    #[test]
+    fn memories_keep_channel_1() {
+        let port = "SIMULATED:KD3005P memories";
+        query(port, "VSET1:3.30");
+        query(port, "ISET1:0.250");
+        query(port, "SAV2");
+        query(port, "VSET1:5.00");
+        query(port, "RCL2");
+        assert_eq!(query(port, "VSET1?"), "03.30");
+        assert_eq!(query(port, "ISET1?"), "0.250");
+        query(port, "RCL6"); // no such memory
+        assert_eq!(query(port, "VSET1?"), "03.30");
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+use serde::Serialize;
+
+use crate::driver::Measurement;
+
+// min, max, mean, standard deviation and RMS of one quantity
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Statistics {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std_dev: f64, // of the sample (n - 1), 0 with less than 2 values
+    pub rms: f64,
+    sum_of_squares: f64, // of the differences to the mean (Welford)
+    sum_of_values_squared: f64,
+}
+
+impl Statistics {
+    // add one value, the mean and the deviation are updated without keeping the values
+    pub fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.sum_of_squares += delta * (value - self.mean);
+        self.sum_of_values_squared += value * value;
+        self.std_dev = if self.count > 1 {
+            (self.sum_of_squares / (self.count - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        self.rms = (self.sum_of_values_squared / self.count as f64).sqrt();
+    }
+}
+
+// statistics of voltage, current and power, since the last reset or over the last `window` readings
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MeasurementStatistics {
+    pub voltage: Statistics,
+    pub amperage: Statistics,
+    pub power: Statistics,
+    pub window: usize, // 0 means everything since the last reset
+    #[serde(skip)]
+    readings: VecDeque<Measurement>,
+}
+
+// the statistics change with every reading, so the GUI can compare them directly
+#[cfg(feature = "gui")]
+impl Data for MeasurementStatistics {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl MeasurementStatistics {
+    pub fn new(window: usize) -> Self {
+        MeasurementStatistics {
+            window,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, measurement: Measurement) {
+        if self.window == 0 {
+            self.add_to_totals(measurement);
+            return;
+        }
+        // a sliding window: drop the oldest reading and count the rest again
+        self.readings.push_back(measurement);
+        while self.readings.len() > self.window {
+            self.readings.pop_front();
+        }
+        self.voltage = Statistics::default();
+        self.amperage = Statistics::default();
+        self.power = Statistics::default();
+        for reading in self.readings.clone() {
+            self.add_to_totals(reading);
+        }
+    }
+
+    fn add_to_totals(&mut self, measurement: Measurement) {
+        self.voltage.add(measurement.voltage);
+        self.amperage.add(measurement.amperage);
+        self.power.add(measurement.power);
+    }
+
+    // start again, the window stays
+    pub fn reset(&mut self) {
+        *self = MeasurementStatistics::new(self.window);
+    }
+
+    // a small table, one line per quantity
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "n = {}{}\n     {:>9} {:>9} {:>9} {:>9} {:>9}\n",
+            self.voltage.count,
+            if self.window > 0 {
+                format!(" (last {})", self.window)
+            } else {
+                String::new()
+            },
+            "min",
+            "max",
+            "mean",
+            "std",
+            "rms"
+        );
+        for (name, statistics) in [
+            ("V", &self.voltage),
+            ("A", &self.amperage),
+            ("W", &self.power),
+        ] {
+            description.push_str(&format!(
+                "{:<4} {:>9.4} {:>9.4} {:>9.4} {:>9.4} {:>9.4}\n",
+                name,
+                statistics.min,
+                statistics.max,
+                statistics.mean,
+                statistics.std_dev,
+                statistics.rms
+            ));
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics_of_some_values() {
+        let mut statistics = Statistics::default();
+        for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            statistics.add(value);
+        }
+        assert_eq!(statistics.count, 8);
+        assert_eq!(statistics.min, 2.0);
+        assert_eq!(statistics.max, 9.0);
+        assert_eq!(statistics.mean, 5.0);
+        assert!((statistics.std_dev - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+        assert!((statistics.rms - (232.0f64 / 8.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn one_value_has_no_deviation() {
+        let mut statistics = Statistics::default();
+        statistics.add(-3.0);
+        assert_eq!(statistics.std_dev, 0.0);
+        assert_eq!(statistics.rms, 3.0);
+        assert_eq!(statistics.min, -3.0);
+    }
+
+    #[test]
+    fn the_window_keeps_the_last_readings() {
+        let mut statistics = MeasurementStatistics::new(2);
+        for voltage in [1.0, 2.0, 3.0] {
+            statistics.add(Measurement::new(voltage, 1.0));
+        }
+        assert_eq!(statistics.voltage.count, 2);
+        assert_eq!(statistics.voltage.min, 2.0);
+        assert_eq!(statistics.power.mean, 2.5);
+        statistics.reset();
+        assert_eq!(statistics, MeasurementStatistics::new(2));
+
+        let mut statistics = MeasurementStatistics::new(0);
+        for voltage in [1.0, 2.0, 3.0] {
+            statistics.add(Measurement::new(voltage, 1.0));
+        }
+        assert_eq!(statistics.voltage.count, 3);
+        assert_eq!(statistics.voltage.mean, 2.0);
+    }
+}
@@ -0,0 +1,129 @@
+// decoded answer of the STATUS? command, the supply answers with a single byte:
+// bit 0: CH1 mode (0 = CC, 1 = CV)
+// bit 1: CH2 mode (0 = CC, 1 = CV), only on dual output models
+// bit 2, 3: tracking (00 = independent, 01 = series, 11 = parallel)
+// bit 4: beep (0 = off, 1 = on)
+// bit 5: lock (0 = locked, 1 = unlocked)
+// bit 6: output (0 = off, 1 = on)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Status {
+    pub byte: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tracking {
+    Independent,
+    Series,
+    Parallel,
+    Unknown,
+}
+
+impl Status {
+    pub fn from_byte(byte: u8) -> Self {
+        Status { byte }
+    }
+
+    // the answer must be exactly one byte, anything else is garbage
+    pub fn from_answer(answer: &[u8]) -> Option<Self> {
+        match answer {
+            [byte] => Some(Status::from_byte(*byte)),
+            _ => None,
+        }
+    }
+
+    // channel 1 or 2, true if the channel is in constant voltage mode
+    pub fn constant_voltage(&self, channel: u8) -> bool {
+        match channel {
+            1 => self.byte & 0x01 != 0,
+            2 => self.byte & 0x02 != 0,
+            _ => false,
+        }
+    }
+
+    pub fn tracking(&self) -> Tracking {
+        match (self.byte >> 2) & 0x03 {
+            0 => Tracking::Independent,
+            1 => Tracking::Series,
+            3 => Tracking::Parallel,
+            _ => Tracking::Unknown,
+        }
+    }
+
+    pub fn beep(&self) -> bool {
+        self.byte & 0x10 != 0
+    }
+
+    pub fn locked(&self) -> bool {
+        self.byte & 0x20 == 0
+    }
+
+    pub fn output_on(&self) -> bool {
+        self.byte & 0x40 != 0
+    }
+
+    // human readable form for the info label, for as many channels as the model has
+    pub fn describe(&self, channels: u8) -> String {
+        let mut description = String::new();
+        for channel in 1..=channels {
+            description.push_str(&format!(
+                "CH{}: {}, ",
+                channel,
+                if self.constant_voltage(channel) {
+                    "CV"
+                } else {
+                    "CC"
+                }
+            ));
+        }
+        if channels > 1 {
+            description.push_str(&format!("Tracking: {:?}, ", self.tracking()));
+        }
+        description.push_str(&format!(
+            "Output: {}, Beep: {}, Panel: {} \n",
+            if self.output_on() { "ON" } else { "OFF" },
+            if self.beep() { "on" } else { "off" },
+            if self.locked() { "locked" } else { "unlocked" },
+        ));
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_answer_takes_exactly_one_byte() {
+        assert_eq!(Status::from_answer(&[0x51]), Some(Status::from_byte(0x51)));
+        assert_eq!(Status::from_answer(&[]), None);
+        assert_eq!(Status::from_answer(&[0x51, 0x00]), None);
+    }
+
+    #[test]
+    fn bits_of_the_status_byte() {
+        let status = Status::from_byte(0x71); // CH1 CV, beep, unlocked, output on
+        assert!(status.constant_voltage(1));
+        assert!(!status.constant_voltage(2));
+        assert!(!status.constant_voltage(3));
+        assert!(status.beep());
+        assert!(!status.locked());
+        assert!(status.output_on());
+        assert!(Status::from_byte(0x00).locked());
+    }
+
+    #[test]
+    fn tracking_bits() {
+        assert_eq!(Status::from_byte(0x00).tracking(), Tracking::Independent);
+        assert_eq!(Status::from_byte(0x04).tracking(), Tracking::Series);
+        assert_eq!(Status::from_byte(0x0C).tracking(), Tracking::Parallel);
+        assert_eq!(Status::from_byte(0x08).tracking(), Tracking::Unknown);
+    }
+
+    #[test]
+    fn describe_shows_tracking_only_for_two_channels() {
+        let status = Status::from_byte(0x46);
+        assert!(!status.describe(1).contains("Tracking"));
+        assert!(status.describe(2).contains("CH2: CV"));
+        assert!(status.describe(2).contains("Tracking: Series"));
+    }
+}
@@ -0,0 +1,270 @@
+use core::time::Duration;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+use serde::Serialize;
+
+use crate::driver::{Measurement, Supply};
+
+// more points make no sense on a supply with 10 mV / 1 mA steps
+const MAX_POINTS: usize = 1000;
+
+// what is stepped: the voltage setting (with a fixed current limit) or the current limit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SweepMode {
+    Voltage,
+    Current,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepSettings {
+    pub mode: SweepMode,
+    pub channel: u8,
+    pub start: f64,
+    pub stop: f64,
+    pub step: f64,
+    pub fixed: f64, // the current limit in a voltage sweep, the voltage in a current sweep
+    pub settle_ms: u64,
+    pub max_power: f64, // W, the sweep stops above it, 0 means no limit
+}
+
+// the settings of the last sweep are kept with its points, so the GUI can hold them
+#[cfg(feature = "gui")]
+impl Data for SweepSettings {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+// one point of the curve: what was set and what came out
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct SweepPoint {
+    pub setpoint: f64,
+    pub measurement: Measurement,
+}
+
+impl SweepSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.start < 0.0 || self.stop < 0.0 || self.fixed < 0.0 {
+            return Err("Start, stop and the fixed value must not be negative".to_string());
+        }
+        if self.step <= 0.0 {
+            return Err("The step must be above 0".to_string());
+        }
+        if self.max_power < 0.0 {
+            return Err("The power limit must not be negative".to_string());
+        }
+        let points = ((self.stop - self.start).abs() / self.step) as usize + 1;
+        if points > MAX_POINTS {
+            return Err(format!(
+                "{} points are too many, use a bigger step (at most {} points)",
+                points, MAX_POINTS
+            ));
+        }
+        Ok(())
+    }
+
+    // from start to stop, both included; a stop below the start sweeps downwards
+    pub fn setpoints(&self) -> Vec<f64> {
+        let direction = if self.stop < self.start { -1.0 } else { 1.0 };
+        let points = ((self.stop - self.start).abs() / self.step + 1e-9) as usize;
+        let mut setpoints: Vec<f64> = (0..=points)
+            .map(|point| {
+                let setpoint = self.start + direction * self.step * point as f64;
+                (setpoint * 1000.0).round() / 1000.0 // the supply takes 10 mV and 1 mA anyway
+            })
+            .collect();
+        match setpoints.last_mut() {
+            Some(last) if (*last - self.stop).abs() < self.step * 1e-6 => *last = self.stop,
+            _ => setpoints.push(self.stop), // the last step is shorter
+        }
+        setpoints
+    }
+
+    // the most the DUT can get at a setpoint: the voltage times the current limit
+    pub fn worst_case_power(&self, setpoint: f64) -> f64 {
+        setpoint * self.fixed
+    }
+
+    // true if a setpoint could go above the power limit of the sweep
+    fn above_power_limit(&self, setpoint: f64) -> bool {
+        self.max_power > 0.0 && self.worst_case_power(setpoint) > self.max_power
+    }
+
+    // the unit of the stepped value
+    pub fn unit(&self) -> &'static str {
+        match self.mode {
+            SweepMode::Voltage => "V",
+            SweepMode::Current => "A",
+        }
+    }
+}
+
+// set the fixed value, switch on, step through the setpoints and read the output after each
+// settling time, every point is given to on_point as soon as it is measured; the output is
+// switched off and the old settings are put back in any case
+pub fn run_sweep(
+    supply: &mut Supply,
+    settings: &SweepSettings,
+    my_output: &mut String,
+    on_point: &mut dyn FnMut(&SweepPoint),
+) -> Result<Vec<SweepPoint>, String> {
+    settings.validate()?;
+    let channel = settings.channel;
+    let old_voltage = supply.voltage_setting(channel)?;
+    let old_amperage = supply.amperage_setting(channel)?;
+
+    let mut points = Vec::new();
+    let result = sweep_points(supply, settings, &mut points, my_output, on_point);
+
+    // no matter how the sweep ended
+    let off_result = supply.set_output(false);
+    supply.set_voltage(channel, old_voltage)?;
+    supply.set_amperage(channel, old_amperage)?;
+    off_result?;
+    my_output.push_str(&format!(
+        "Output OFF, settings back at {:.2} V / {:.3} A \n",
+        old_voltage, old_amperage
+    ));
+    result.map(|_| points)
+}
+
+fn sweep_points(
+    supply: &mut Supply,
+    settings: &SweepSettings,
+    points: &mut Vec<SweepPoint>,
+    my_output: &mut String,
+    on_point: &mut dyn FnMut(&SweepPoint),
+) -> Result<(), String> {
+    let channel = settings.channel;
+    let setpoints = settings.setpoints();
+    if settings.above_power_limit(setpoints[0]) {
+        return Err(format!(
+            "Already the first point could give {:.3} W, above the limit of {} W",
+            settings.worst_case_power(setpoints[0]),
+            settings.max_power
+        ));
+    }
+    // start at the first point, so the DUT does not see the old settings
+    match settings.mode {
+        SweepMode::Voltage => {
+            supply.set_amperage(channel, settings.fixed)?;
+            supply.set_voltage(channel, setpoints[0])?;
+        }
+        SweepMode::Current => {
+            supply.set_voltage(channel, settings.fixed)?;
+            supply.set_amperage(channel, setpoints[0])?;
+        }
+    }
+    supply.set_output(true)?;
+    for setpoint in setpoints {
+        // do not even set a point that could go above the limit
+        if settings.above_power_limit(setpoint) {
+            my_output.push_str(&format!(
+                "Sweep stopped before {} {}: it could give {:.3} W, above the limit of {} W! \n",
+                setpoint,
+                settings.unit(),
+                settings.worst_case_power(setpoint),
+                settings.max_power
+            ));
+            return Ok(());
+        }
+        match settings.mode {
+            SweepMode::Voltage => supply.set_voltage(channel, setpoint)?,
+            SweepMode::Current => supply.set_amperage(channel, setpoint)?,
+        }
+        std::thread::sleep(Duration::from_millis(settings.settle_ms));
+        let measurement = supply.measure(channel)?;
+        let point = SweepPoint {
+            setpoint,
+            measurement,
+        };
+        on_point(&point);
+        points.push(point);
+        if settings.max_power > 0.0 && measurement.power > settings.max_power {
+            // keep what was measured, but go no further
+            my_output.push_str(&format!(
+                "Sweep stopped at {} {}: {:.3} W is above the limit of {} W! \n",
+                setpoint,
+                settings.unit(),
+                measurement.power,
+                settings.max_power
+            ));
+            return Ok(());
+        }
+    }
+    my_output.push_str(&format!("Sweep done, {} points \n", points.len()));
+    Ok(())
+}
+
+// one line per point, for a spreadsheet
+pub fn to_csv(settings: &SweepSettings, points: &[SweepPoint]) -> String {
+    let mut csv = format!(
+        "setpoint_{},voltage_v,current_a,power_w\n",
+        settings.unit().to_lowercase()
+    );
+    for point in points {
+        csv.push_str(&format!(
+            "{},{:.2},{:.3},{:.4}\n",
+            point.setpoint,
+            point.measurement.voltage,
+            point.measurement.amperage,
+            point.measurement.power
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(start: f64, stop: f64, step: f64) -> SweepSettings {
+        SweepSettings {
+            mode: SweepMode::Voltage,
+            channel: 1,
+            start,
+            stop,
+            step,
+            fixed: 0.5,
+            settle_ms: 0,
+            max_power: 0.0,
+        }
+    }
+
+    #[test]
+    fn setpoints_include_start_and_stop() {
+        assert_eq!(
+            settings(0.0, 1.0, 0.25).setpoints(),
+            vec![0.0, 0.25, 0.5, 0.75, 1.0]
+        );
+        assert_eq!(
+            settings(1.0, 1.3, 0.1).setpoints(),
+            vec![1.0, 1.1, 1.2, 1.3]
+        );
+        assert_eq!(settings(2.0, 2.0, 0.5).setpoints(), vec![2.0]);
+    }
+
+    #[test]
+    fn setpoints_downwards_and_with_a_short_last_step() {
+        assert_eq!(settings(1.0, 0.0, 0.5).setpoints(), vec![1.0, 0.5, 0.0]);
+        assert_eq!(
+            settings(0.0, 1.0, 0.4).setpoints(),
+            vec![0.0, 0.4, 0.8, 1.0]
+        );
+    }
+
+    #[test]
+    fn validate_and_power_limit() {
+        assert!(settings(0.0, 5.0, 0.0).validate().is_err());
+        assert!(settings(-1.0, 5.0, 0.1).validate().is_err());
+        assert!(settings(0.0, 30.0, 0.01).validate().is_err()); // too many points
+        let mut sweep = settings(0.0, 5.0, 0.1);
+        assert!(sweep.validate().is_ok());
+        assert!(!sweep.above_power_limit(30.0)); // no limit
+        sweep.max_power = 2.0;
+        assert_eq!(sweep.worst_case_power(5.0), 2.5);
+        assert!(sweep.above_power_limit(5.0));
+        assert!(!sweep.above_power_limit(4.0));
+    }
+}
@@ -0,0 +1,126 @@
+// the driver against recorded sessions of real supplies, no hardware needed
+use kd3005p_rs::capture::{replay_remaining, REPLAY_PREFIX};
+use kd3005p_rs::driver::Supply;
+use kd3005p_rs::limits::SafetyLimits;
+use kd3005p_rs::port_config::PortConfig;
+
+// every test has its own file, the replays are kept per file while the tests run
+fn replay(file: &str) -> String {
+    format!("{}/tests/replays/{}", env!("CARGO_MANIFEST_DIR"), file)
+}
+
+fn open(replay_path: &str) -> Supply {
+    Supply::open(
+        &format!("{}{}", REPLAY_PREFIX, replay_path),
+        &PortConfig::default(),
+    )
+    .expect("the replay should open")
+}
+
+#[test]
+fn set_voltage_sends_the_recorded_command() {
+    let path = replay("set_voltage.cap");
+    let supply = open(&path);
+    assert_eq!(supply.model.name, "KD3005P"); // from the *IDN? answer
+    supply.set_voltage(1, 5.0).unwrap();
+    assert_eq!(supply.voltage_setting(1).unwrap(), 5.0);
+    assert_eq!(replay_remaining(&path).unwrap(), 0);
+}
+
+#[test]
+fn measure_reads_voltage_then_current() {
+    let path = replay("measure.cap");
+    let supply = open(&path);
+    supply.set_amperage(1, 0.5).unwrap();
+    assert_eq!(supply.amperage_setting(1).unwrap(), 0.5);
+    let measurement = supply.measure(1).unwrap();
+    assert_eq!(measurement.voltage, 4.98);
+    assert_eq!(measurement.amperage, 0.213);
+    assert!((measurement.power - 4.98 * 0.213).abs() < 1e-9);
+    assert_eq!(replay_remaining(&path).unwrap(), 0);
+}
+
+#[test]
+fn output_follows_the_status_byte() {
+    let path = replay("output.cap");
+    let mut supply = open(&path);
+    supply.set_output(true).unwrap();
+    let status = supply.status().unwrap();
+    assert!(status.output_on());
+    assert!(status.constant_voltage(1));
+    assert!(!supply.protection_tripped(&status));
+    supply.set_output(false).unwrap();
+    assert!(!supply.status().unwrap().output_on());
+    assert_eq!(replay_remaining(&path).unwrap(), 0);
+}
+
+#[test]
+fn recall_and_save_memories() {
+    let path = replay("memories.cap");
+    let supply = open(&path);
+    supply.recall(2).unwrap();
+    assert_eq!(supply.voltage_setting(1).unwrap(), 3.3);
+    assert_eq!(supply.amperage_setting(1).unwrap(), 0.25);
+    supply.save(3).unwrap();
+    assert!(supply.save(6).is_err()); // not sent, there is no M6
+    assert_eq!(replay_remaining(&path).unwrap(), 0);
+}
+
+#[test]
+fn soft_limits_stop_a_setting_before_it_is_sent() {
+    let path = replay("set_voltage.cap#/dev/ttyACM0"); // its own session of the same file
+    let mut supply = open(&path);
+    supply.limits = SafetyLimits {
+        max_voltage: 3.3,
+        ..SafetyLimits::default()
+    };
+    assert!(supply.set_voltage(1, 5.0).is_err());
+    assert!(supply.set_voltage(2, 1.0).is_err()); // the KD3005P has one channel
+    assert_eq!(replay_remaining(&path).unwrap(), 2); // VSET1:5.00 and VSET1? are left
+}
+
+#[test]
+fn a_command_that_was_not_recorded_fails() {
+    let path = replay("measure.cap#/dev/ttyACM0");
+    let supply = open(&path);
+    assert!(supply.set_amperage(1, 1.0).is_err()); // ISET1:0.500 was recorded
+}
+
+#[test]
+fn a_query_without_answer_is_sent_again() {
+    let path = replay("query_retry.cap");
+    let supply = open(&path);
+    let measurement = supply.measure(1);
+    // the retried VOUT1? gets its answer, then IOUT1? is not in the recording
+    assert!(measurement.is_err());
+    assert_eq!(replay_remaining(&path).unwrap(), 0);
+}
+
+#[test]
+fn replay_takes_only_the_traffic_of_one_port() {
+    let first = replay("two_ports.cap");
+    let supply = open(&first);
+    assert_eq!(supply.model.name, "KD3005P");
+    let measurement = supply.measure(1).unwrap();
+    assert_eq!(measurement.voltage, 12.0);
+    assert_eq!(measurement.amperage, 0.0);
+    assert_eq!(replay_remaining(&first).unwrap(), 0);
+
+    let second = format!("{}#/dev/ttyACM1", replay("two_ports.cap"));
+    let supply = open(&second);
+    assert_eq!(supply.model.name, "KA3305P");
+    let measurement = supply.measure(2).unwrap();
+    assert_eq!(measurement.voltage, 5.0);
+    assert_eq!(measurement.amperage, 0.1);
+    assert_eq!(replay_remaining(&second).unwrap(), 0);
+}
+
+#[test]
+fn replay_of_an_unknown_port_fails() {
+    let path = format!("{}#/dev/ttyUSB9", replay("two_ports.cap"));
+    assert!(Supply::open(
+        &format!("{}{}", REPLAY_PREFIX, path),
+        &PortConfig::default()
+    )
+    .is_err());
+}
@@ -0,0 +1,36 @@
+#![cfg(feature = "async")]
+// the async driver against the same recordings as the blocking one
+use kd3005p_rs::async_driver::AsyncSupply;
+use kd3005p_rs::capture::{replay_remaining, REPLAY_PREFIX};
+use kd3005p_rs::port_config::PortConfig;
+
+fn replay(file: &str) -> String {
+    format!("{}/tests/replays/{}", env!("CARGO_MANIFEST_DIR"), file)
+}
+
+#[tokio::test]
+async fn async_driver_replays_a_session() {
+    // a session of its own, the blocking tests use the same file
+    let path = format!("{}#/dev/ttyACM0", replay("measure.cap"));
+    let supply = AsyncSupply::open(
+        &format!("{}{}", REPLAY_PREFIX, path),
+        &PortConfig::default(),
+    )
+    .await
+    .expect("the replay should open");
+    supply.set_amperage(1, 0.5).await.unwrap();
+    assert_eq!(supply.amperage_setting(1).await.unwrap(), 0.5);
+    let measurement = supply.measure(1).await.unwrap();
+    assert_eq!(measurement.voltage, 4.98);
+    assert_eq!(replay_remaining(&path).unwrap(), 0);
+}
+
+#[tokio::test]
+async fn async_driver_opens_a_simulated_model() {
+    let supply = AsyncSupply::open("SIMULATED:KA3305P async", &PortConfig::default())
+        .await
+        .unwrap();
+    assert_eq!(supply.model.name, "KA3305P");
+    supply.set_voltage(2, 3.3).await.unwrap();
+    assert_eq!(supply.voltage_setting(2).await.unwrap(), 3.3);
+}